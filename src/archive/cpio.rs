@@ -1,16 +1,33 @@
+use std::collections::HashMap;
 use std::io::Cursor;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Result;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use bytes::Bytes;
+use nix::sys::stat::major;
+use nix::sys::stat::makedev;
+use nix::sys::stat::minor;
 use nix::sys::stat::Mode;
 use nix::sys::stat::SFlag;
 
+use super::archive_path;
 use crate::entry::Directory;
+use crate::entry::Entry;
 use crate::entry::Metadata;
+use crate::entry::Special;
 use crate::entry::Symlink;
 use crate::BytesExt;
+use crate::BytesPath;
 use crate::File;
 use crate::Filesystem;
 use crate::Gid;
+use crate::InodeKey;
 use crate::Uid;
 
 const HEADER_LEN: usize = 110;
@@ -29,7 +46,7 @@ fn align_to_4_bytes(pos: usize) -> usize {
 
 impl Filesystem {
     /// Parse an uncompressed cpio
-    pub fn parse_cpio(contents: &Bytes) -> std::io::Result<Self> {
+    pub fn parse_cpio(contents: &Bytes) -> Result<Self> {
         let mut fs = Self::new();
         let mut cursor = Cursor::new(&contents);
 
@@ -49,7 +66,7 @@ impl Filesystem {
                 .gid(Gid::from_raw(entry.gid()))
                 .build();
             if sflag.contains(SFlag::S_IFDIR) {
-                fs.insert(path, Directory::builder().metadata(metadata).build());
+                fs.try_insert(path, Directory::builder().metadata(metadata).build())?;
             } else if sflag.contains(SFlag::S_IFLNK) {
                 let name_size = entry.file_size() as usize;
                 // the symlink target starts at the header_start + HEADER_LEN +
@@ -57,7 +74,7 @@ impl Filesystem {
                 let link_start =
                     align_to_4_bytes(header_start_pos + HEADER_LEN + entry.name().len() + 1);
                 let target = contents.slice(link_start..link_start + name_size);
-                fs.insert(path, Symlink::new(target, Some(metadata)));
+                fs.try_insert(path, Symlink::new(target, Some(metadata)))?;
             } else if sflag.contains(SFlag::S_IFREG) {
                 let file_size = entry.file_size() as usize;
                 // the file starts at the header_start + HEADER_LEN + path +
@@ -65,13 +82,13 @@ impl Filesystem {
                 let file_start =
                     align_to_4_bytes(header_start_pos + HEADER_LEN + entry.name().len() + 1);
                 let file_contents = contents.slice(file_start..file_start + file_size);
-                fs.insert(
+                fs.try_insert(
                     path,
                     File::builder()
                         .contents(file_contents)
                         .metadata(metadata)
                         .build(),
-                );
+                )?;
             } else {
                 todo!();
             }
@@ -80,6 +97,138 @@ impl Filesystem {
         }
         Ok(fs)
     }
+
+    /// Read a newc cpio archive from any [Read]er. Unlike
+    /// [Filesystem::parse_cpio], all the data is copied out of the reader.
+    /// Non-directory entries that share an inode number become hardlinks of
+    /// the first entry with that inode. The data for hardlinks may be stored
+    /// with any one of the entries.
+    pub fn from_cpio<R: Read>(reader: R) -> Result<Self> {
+        let mut fs = Self::new();
+        let mut links: HashMap<u32, BytesPath> = HashMap::new();
+        let mut reader = reader;
+        loop {
+            let mut entry_reader = cpio::newc::Reader::new(reader)?;
+            let entry = entry_reader.entry();
+            if entry.is_trailer() {
+                break;
+            }
+            let path = archive_path(entry.name().as_bytes())?;
+            let (ino, nlink) = (entry.ino(), entry.nlink());
+            let file_type = SFlag::from_bits_truncate(entry.mode() & SFlag::S_IFMT.bits());
+            let rdev = makedev(entry.rdev_major().into(), entry.rdev_minor().into());
+            let metadata = Metadata::builder()
+                .mode(Mode::from_bits_truncate(entry.mode()))
+                .uid(Uid::from_raw(entry.uid()))
+                .gid(Gid::from_raw(entry.gid()))
+                .modified(SystemTime::UNIX_EPOCH + Duration::from_secs(entry.mtime().into()))
+                .build();
+            let mut data = Vec::with_capacity(entry.file_size() as usize);
+            entry_reader.read_to_end(&mut data)?;
+            reader = entry_reader.finish()?;
+
+            if file_type != SFlag::S_IFDIR && nlink > 1 {
+                if let Some(target) = links.get(&ino) {
+                    fs.link(target, path.clone())?;
+                    if !data.is_empty() {
+                        fs.get_file_mut(&path)?.write_at(0, &data)?;
+                    }
+                    continue;
+                }
+                links.insert(ino, path.clone());
+            }
+            match file_type {
+                SFlag::S_IFDIR => {
+                    fs.insert(path, Directory::builder().metadata(metadata).build());
+                }
+                SFlag::S_IFREG => {
+                    fs.insert(
+                        path,
+                        File::builder().contents(data).metadata(metadata).build(),
+                    );
+                }
+                SFlag::S_IFLNK => {
+                    fs.insert(path, Symlink::new(Bytes::from(data), Some(metadata)));
+                }
+                _ => {
+                    fs.insert(path, Special::new(file_type, rdev, metadata));
+                }
+            }
+        }
+        Ok(fs)
+    }
+
+    /// Write the filesystem as a newc cpio archive. The output is
+    /// deterministic: entries are written in sorted path order, inode numbers
+    /// are assigned in that same order and the device numbers of the
+    /// containing filesystem are zeroed. Hardlinks share an inode number and
+    /// their data is only written with the first path. xattrs cannot be
+    /// represented in cpio and are dropped.
+    pub fn to_cpio<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = writer;
+        let mut inos: HashMap<InodeKey, u32> = HashMap::new();
+        for (path, key) in &self.paths {
+            let entry = &self.inodes[*key];
+            let metadata = entry.metadata();
+            let name = match path.is_empty() {
+                true => ".",
+                false => std::str::from_utf8(path.as_os_str().as_bytes()).map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("'{}' is not valid utf8", path.display()),
+                    )
+                })?,
+            };
+            let first = !inos.contains_key(key);
+            let next_ino = inos.len() as u32 + 1;
+            let ino = *inos.entry(*key).or_insert(next_ino);
+            let (file_type, rdev, data): (SFlag, u64, Box<dyn Read + '_>) = match entry {
+                Entry::Directory(_) => (SFlag::S_IFDIR, 0, Box::new(std::io::empty())),
+                Entry::File(f) => (SFlag::S_IFREG, 0, Box::new(f.reader())),
+                Entry::Special(s) => (
+                    s.file_type(),
+                    s.rdev().unwrap_or(0),
+                    Box::new(std::io::empty()),
+                ),
+                Entry::Symlink(s) => (
+                    SFlag::S_IFLNK,
+                    0,
+                    Box::new(s.target().as_os_str().as_bytes()),
+                ),
+            };
+            let size = match (first, entry) {
+                (true, Entry::File(f)) => f.len(),
+                (true, Entry::Symlink(s)) => s.target().as_os_str().len() as u64,
+                _ => 0,
+            };
+            let size = u32::try_from(size).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("'{}' is too large for cpio", path.display()),
+                )
+            })?;
+            let mut entry_writer = cpio::newc::Builder::new(name)
+                .ino(ino)
+                .mode(file_type.bits() | metadata.mode().bits())
+                .uid(metadata.uid().as_u32())
+                .gid(metadata.gid().as_u32())
+                .nlink(self.refcounts[*key] as u32)
+                .mtime(
+                    metadata
+                        .modified()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs() as u32,
+                )
+                .rdev_major(major(rdev) as u32)
+                .rdev_minor(minor(rdev) as u32)
+                .write(writer, size);
+            std::io::copy(&mut data.take(size.into()), &mut entry_writer)?;
+            writer = entry_writer.finish()?;
+        }
+        cpio::newc::trailer(writer)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -111,8 +260,45 @@ mod tests {
         let fs = Filesystem::parse_cpio(&contents).expect("failed to parse cpio");
         let mut demo_fs = demo_fs();
         // cpio is missing the top-level directory
-        demo_fs.unlink(&BytesPath::from("")).unwrap();
+        demo_fs.unlink(BytesPath::from("")).unwrap();
         // cpio does not support xattrs
         assert_approx_eq!(demo_fs, fs, Fields::all() - Fields::XATTR);
     }
+
+    #[test]
+    fn from_cpio() {
+        let contents = include_bytes!("../../testdata/testdata.cpio");
+        let fs = Filesystem::from_cpio(&contents[..]).expect("failed to read cpio");
+        let parsed =
+            Filesystem::parse_cpio(&Bytes::from_static(contents)).expect("failed to parse cpio");
+        // parse_cpio does not set any times
+        assert_approx_eq!(parsed, fs, Fields::all() - Fields::TIME);
+    }
+
+    #[test]
+    fn to_cpio_round_trip() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        fs.insert(
+            "testdata/fifo",
+            Special::new(SFlag::S_IFIFO, 0, Metadata::default()),
+        );
+        fs.insert(
+            "testdata/null",
+            Special::new(SFlag::S_IFCHR, makedev(1, 3), Metadata::default()),
+        );
+        let mut cpio = Vec::new();
+        fs.to_cpio(&mut cpio).expect("failed to write cpio");
+        assert!(cpio.starts_with(b"070701"));
+        assert_eq!(cpio.len() % 4, 0);
+        let mut cpio2 = Vec::new();
+        fs.to_cpio(&mut cpio2).expect("failed to write cpio");
+        assert_eq!(cpio, cpio2, "cpio output is not deterministic");
+
+        let round_tripped = Filesystem::from_cpio(cpio.as_slice()).expect("failed to read cpio");
+        // cpio does not support xattrs
+        assert_approx_eq!(fs, round_tripped, Fields::all() - Fields::XATTR);
+        assert_eq!(round_tripped.nlink("testdata/hardlink.txt").unwrap(), 2);
+    }
 }
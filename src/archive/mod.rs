@@ -3,5 +3,24 @@
 #[cfg(feature = "cpio")]
 mod cpio;
 
+#[cfg(feature = "tar")]
+mod oci;
 #[cfg(feature = "tar")]
 mod tar;
+
+#[cfg(feature = "tar")]
+pub use oci::OPAQUE_WHITEOUT;
+#[cfg(feature = "tar")]
+pub use oci::WHITEOUT_PREFIX;
+
+use bytes::Bytes;
+
+use crate::BytesPath;
+
+/// Paths in an archive are relative to the root, but are often prefixed with
+/// './' (or are just '.' for the root itself) and directories may have a
+/// trailing '/', all of which [BytesPath::try_normalize] takes care of. A
+/// path that escapes the root is an error.
+fn archive_path(path: &[u8]) -> std::io::Result<BytesPath> {
+    BytesPath::from(Bytes::copy_from_slice(path)).try_normalize()
+}
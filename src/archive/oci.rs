@@ -0,0 +1,204 @@
+use std::ffi::OsStr;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Result;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::entry::Entry;
+use crate::overlay::is_opaque;
+use crate::File;
+use crate::Filesystem;
+use crate::OPAQUE_XATTR;
+
+/// Prefix of the file name of a whiteout marker in an OCI layer. A file named
+/// `.wh.foo` deletes `foo` from the layers below.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// File name of the marker that makes its directory opaque in an OCI layer
+pub const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+impl Filesystem {
+    /// Read an (uncompressed) OCI image layer tarball. Whiteout marker files
+    /// are translated to the same representation that [crate::OverlayFs]
+    /// uses: `.wh.foo` becomes an [Entry::whiteout] at `foo`, and a
+    /// directory containing `.wh..wh..opq` is marked with [OPAQUE_XATTR].
+    pub fn from_oci_layer<R: Read>(reader: R) -> Result<Self> {
+        let mut fs = Self::from_tar(reader)?;
+        let markers: Vec<PathBuf> = fs
+            .paths
+            .keys()
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.as_bytes().starts_with(WHITEOUT_PREFIX.as_bytes()))
+            })
+            .map(|path| path.to_path_buf())
+            .collect();
+        for path in markers {
+            fs.unlink(&path)?;
+            let parent = path.parent().unwrap_or(Path::new(""));
+            let name = path.file_name().expect("markers have a name").as_bytes();
+            if name == OPAQUE_WHITEOUT.as_bytes() {
+                match fs.get_mut(parent) {
+                    Ok(dir) if dir.is_directory() => {
+                        dir.set_xattr(OPAQUE_XATTR, "y");
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("opaque marker '{}' is not in a directory", path.display()),
+                        ));
+                    }
+                }
+            } else {
+                let name = &name[WHITEOUT_PREFIX.len()..];
+                fs.try_insert(parent.join(OsStr::from_bytes(name)), Entry::whiteout())?;
+            }
+        }
+        Ok(fs)
+    }
+
+    /// The inverse of [Filesystem::from_oci_layer]: write the filesystem as
+    /// an OCI layer tarball, replacing each [Entry::whiteout] with a `.wh.`
+    /// marker file and each [OPAQUE_XATTR] with a `.wh..wh..opq` marker.
+    pub fn to_oci_layer<W: Write>(&self, writer: W) -> Result<()> {
+        let mut layer = self.clone();
+        let whiteouts: Vec<PathBuf> = self
+            .iter()
+            .filter(|(_, entry)| entry.is_whiteout())
+            .map(|(path, _)| path.to_path_buf())
+            .collect();
+        for path in whiteouts {
+            let name = path.file_name().ok_or_else(|| {
+                Error::new(ErrorKind::InvalidInput, "the root cannot be a whiteout")
+            })?;
+            let mut marker = WHITEOUT_PREFIX.as_bytes().to_vec();
+            marker.extend(name.as_bytes());
+            layer.unlink(&path)?;
+            layer.insert(
+                path.with_file_name(OsStr::from_bytes(&marker)),
+                File::new_empty(),
+            );
+        }
+        let opaque: Vec<PathBuf> = self
+            .iter()
+            .filter(|(_, entry)| is_opaque(entry))
+            .map(|(path, _)| path.to_path_buf())
+            .collect();
+        for path in opaque {
+            layer.get_mut(&path)?.remove_xattr(&OPAQUE_XATTR.into());
+            layer.insert(path.join(OPAQUE_WHITEOUT), File::new_empty());
+        }
+        layer.to_tar(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+    use crate::OverlayFs;
+
+    /// Build a tarball out of directories (`None`) and files
+    fn layer(entries: &[(&str, Option<&str>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            match contents {
+                Some(contents) => {
+                    header.set_mode(0o644);
+                    header.set_size(contents.len() as u64);
+                    builder.append_data(&mut header, path, contents.as_bytes())
+                }
+                None => {
+                    header.set_mode(0o755);
+                    header.set_size(0);
+                    header.set_entry_type(tar::EntryType::Directory);
+                    builder.append_data(&mut header, path, std::io::empty())
+                }
+            }
+            .expect("failed to build tar");
+        }
+        builder.into_inner().expect("failed to build tar")
+    }
+
+    fn round_trip(fs: &Filesystem) -> Filesystem {
+        let mut tar = Vec::new();
+        fs.to_oci_layer(&mut tar).expect("failed to write layer");
+        Filesystem::from_oci_layer(tar.as_slice()).expect("failed to read layer")
+    }
+
+    #[test]
+    fn whiteout() {
+        let fs = Filesystem::from_oci_layer(
+            layer(&[("testdata/", None), ("testdata/.wh.lorem.txt", Some(""))]).as_slice(),
+        )
+        .expect("failed to read layer");
+        assert!(fs.get("testdata/lorem.txt").unwrap().is_whiteout());
+        assert!(!fs.exists("testdata/.wh.lorem.txt"));
+
+        let mut overlay = OverlayFs::new();
+        overlay.push(demo_fs());
+        overlay.push(fs.clone());
+        assert!(overlay.get("testdata/lorem.txt").is_err());
+        assert!(overlay.get("testdata/dir/lorem.txt").is_ok());
+
+        let mut tar = Vec::new();
+        fs.to_oci_layer(&mut tar).expect("failed to write layer");
+        let plain = Filesystem::from_tar(tar.as_slice()).expect("failed to read tar");
+        assert_eq!(plain.get_file("testdata/.wh.lorem.txt").unwrap().len(), 0,);
+        assert!(!plain.exists("testdata/lorem.txt"));
+        assert_eq!(round_trip(&fs), fs);
+    }
+
+    #[test]
+    fn opaque() {
+        let fs = Filesystem::from_oci_layer(
+            layer(&[
+                ("testdata/", None),
+                ("testdata/dir/", None),
+                ("testdata/dir/.wh..wh..opq", Some("")),
+                ("testdata/dir/new.txt", Some("new\n")),
+            ])
+            .as_slice(),
+        )
+        .expect("failed to read layer");
+        assert!(is_opaque(fs.get("testdata/dir").unwrap()));
+        assert!(!is_opaque(fs.get("testdata").unwrap()));
+        assert!(!fs.exists("testdata/dir/.wh..wh..opq"));
+
+        let mut overlay = OverlayFs::new();
+        overlay.push(demo_fs());
+        overlay.push(fs.clone());
+        assert!(overlay.get("testdata/dir/lorem.txt").is_err());
+        assert!(overlay.get("testdata/dir/new.txt").is_ok());
+        assert!(overlay.get("testdata/lorem.txt").is_ok());
+
+        let mut tar = Vec::new();
+        fs.to_oci_layer(&mut tar).expect("failed to write layer");
+        let plain = Filesystem::from_tar(tar.as_slice()).expect("failed to read tar");
+        assert!(plain.exists("testdata/dir/.wh..wh..opq"));
+        assert!(plain
+            .get("testdata/dir")
+            .unwrap()
+            .metadata()
+            .xattrs()
+            .is_empty());
+        assert_eq!(round_trip(&fs), fs);
+
+        assert_eq!(
+            Filesystem::from_oci_layer(layer(&[("dir/.wh..wh..opq", Some(""))]).as_slice())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData,
+        );
+    }
+}
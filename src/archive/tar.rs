@@ -1,21 +1,39 @@
 use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::io::Cursor;
+use std::io::Error;
+use std::io::ErrorKind;
 use std::io::Read;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use bytes::Bytes;
+use nix::sys::stat::major;
+use nix::sys::stat::makedev;
+use nix::sys::stat::minor;
 use nix::sys::stat::Mode;
+use nix::sys::stat::SFlag;
 use tar::Archive;
 use tar::EntryType;
 
+use super::archive_path;
 use crate::entry::Directory;
+use crate::entry::Entry;
 use crate::entry::Metadata;
+use crate::entry::Special;
 use crate::entry::Symlink;
 use crate::BytesExt;
 use crate::BytesPath;
 use crate::File;
 use crate::Filesystem;
 use crate::Gid;
+use crate::InodeKey;
 use crate::Uid;
+use crate::XattrFilter;
 
 // See https://www.gnu.org/software/tar/manual/html_node/Standard.html for some
 // of the offsets used here to get borrows to the underlying slice
@@ -34,10 +52,10 @@ impl Filesystem {
                     // remove trailing / for consistency
                     let new_len = path.len() - 1;
                     path.bytes_mut().truncate(new_len);
-                    fs.insert(path, Directory::builder().metadata(metadata).build());
+                    fs.try_insert(path, Directory::builder().metadata(metadata).build())?;
                 }
                 EntryType::Regular => {
-                    fs.insert(
+                    fs.try_insert(
                         path,
                         File::builder()
                             .contents(
@@ -45,7 +63,7 @@ impl Filesystem {
                             )
                             .metadata(metadata)
                             .build(),
-                    );
+                    )?;
                 }
                 EntryType::Symlink => {
                     let link_target = contents.subslice_or_copy(
@@ -53,7 +71,7 @@ impl Filesystem {
                             .link_name_bytes()
                             .expect("symlink must have link target"),
                     );
-                    fs.insert(path, Symlink::new(link_target, Some(metadata)));
+                    fs.try_insert(path, Symlink::new(link_target, Some(metadata)))?;
                 }
                 ty => {
                     todo!("unhandled entry type {ty:?}");
@@ -62,20 +80,218 @@ impl Filesystem {
         }
         Ok(fs)
     }
+
+    /// Read a tarball from any [Read]er. Unlike [Filesystem::parse_tar], all
+    /// the data is copied out of the reader. Hardlinks share the same entry
+    /// as their target, and GNU long names and sparse files are expanded by
+    /// the [tar] crate.
+    pub fn from_tar<R: Read>(reader: R) -> std::io::Result<Self> {
+        let mut fs = Filesystem::new();
+        for entry in Archive::new(reader).entries()? {
+            let mut entry = entry?;
+            let path = archive_path(&entry.path_bytes())?;
+            let mut metadata = Metadata::try_from_entry_with(&mut entry, Bytes::copy_from_slice)?;
+            metadata.modified =
+                SystemTime::UNIX_EPOCH + Duration::from_secs(entry.header().mtime()?);
+            match entry.header().entry_type() {
+                EntryType::Directory => {
+                    fs.insert(path, Directory::builder().metadata(metadata).build());
+                }
+                EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                    let mut contents = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut contents)?;
+                    fs.insert(
+                        path,
+                        File::builder()
+                            .contents(contents)
+                            .metadata(metadata)
+                            .build(),
+                    );
+                }
+                EntryType::Symlink => {
+                    let target = entry.link_name_bytes().ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, "symlink must have link target")
+                    })?;
+                    fs.insert(
+                        path,
+                        Symlink::new(Bytes::copy_from_slice(&target), Some(metadata)),
+                    );
+                }
+                EntryType::Link => {
+                    let target = entry.link_name_bytes().ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidData, "hardlink must have link target")
+                    })?;
+                    fs.link(archive_path(&target)?, path)?;
+                }
+                ty @ (EntryType::Char | EntryType::Block | EntryType::Fifo) => {
+                    let file_type = match ty {
+                        EntryType::Char => SFlag::S_IFCHR,
+                        EntryType::Block => SFlag::S_IFBLK,
+                        _ => SFlag::S_IFIFO,
+                    };
+                    let rdev = makedev(
+                        entry.header().device_major()?.unwrap_or(0).into(),
+                        entry.header().device_minor()?.unwrap_or(0).into(),
+                    );
+                    fs.insert(path, Special::new(file_type, rdev, metadata));
+                }
+                ty => {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!("unsupported tar entry type {ty:?}"),
+                    ));
+                }
+            };
+        }
+        Ok(fs)
+    }
+}
+
+/// A single PAX extended header record, where the length includes itself
+fn pax_record(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let len = key.len() + value.len() + 3;
+    let mut len_digits = len.to_string().len();
+    // adding the length can make it longer
+    if (len + len_digits).to_string().len() > len_digits {
+        len_digits += 1;
+    }
+    let mut record = format!("{} ", len + len_digits).into_bytes();
+    record.extend(key);
+    record.push(b'=');
+    record.extend(value);
+    record.push(b'\n');
+    record
+}
+
+impl Filesystem {
+    /// Write the filesystem as an uncompressed tarball. The output is
+    /// deterministic: entries are written in sorted path order, hardlinks are
+    /// written as links to the first path, xattrs are stored in PAX records
+    /// and any header fields that are not represented in the [Filesystem]
+    /// (atime, ctime, user/group names and device numbers for anything other
+    /// than device nodes) are zeroed.
+    pub fn to_tar<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        self.to_tar_with(writer, &XattrFilter::default())
+    }
+
+    /// Like [Filesystem::to_tar], but only storing the xattrs allowed by
+    /// `filter`.
+    pub fn to_tar_with<W: Write>(&self, writer: W, filter: &XattrFilter) -> std::io::Result<()> {
+        let mut builder = tar::Builder::new(writer);
+        let mut first_paths: HashMap<InodeKey, &Path> = HashMap::new();
+        for (path, key) in &self.paths {
+            let entry = &self.inodes[*key];
+            let metadata = entry.metadata();
+            let mut name = match path.is_empty() {
+                true => b".".to_vec(),
+                false => path.as_os_str().as_bytes().to_vec(),
+            };
+            if entry.is_directory() {
+                name.push(b'/');
+            }
+            let name = Path::new(OsStr::from_bytes(&name));
+
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(metadata.mode().bits());
+            header.set_uid(metadata.uid().as_u32().into());
+            header.set_gid(metadata.gid().as_u32().into());
+            header.set_mtime(
+                metadata
+                    .modified()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            );
+            header.set_device_major(0)?;
+            header.set_device_minor(0)?;
+            header.set_size(0);
+
+            if let Some(target) = first_paths.get(key) {
+                header.set_entry_type(EntryType::Link);
+                builder.append_link(&mut header, name, target)?;
+                continue;
+            }
+            first_paths.insert(*key, path);
+
+            let mut records = Vec::new();
+            for (xattr, value) in metadata.xattrs() {
+                if let Some(value) = filter.apply(xattr, value) {
+                    let mut key = b"SCHILY.xattr.".to_vec();
+                    key.extend(xattr);
+                    records.extend(pax_record(&key, &value));
+                }
+            }
+            if !records.is_empty() {
+                let mut pax_header = tar::Header::new_gnu();
+                pax_header.set_entry_type(EntryType::XHeader);
+                pax_header.set_mode(0o644);
+                pax_header.set_mtime(0);
+                pax_header.set_uid(0);
+                pax_header.set_gid(0);
+                pax_header.set_size(records.len() as u64);
+                builder.append_data(&mut pax_header, "././@PaxHeader", records.as_slice())?;
+            }
+
+            match entry {
+                Entry::Directory(_) => {
+                    header.set_entry_type(EntryType::Directory);
+                    builder.append_data(&mut header, name, std::io::empty())?;
+                }
+                Entry::File(f) => {
+                    header.set_entry_type(EntryType::Regular);
+                    header.set_size(f.len());
+                    builder.append_data(&mut header, name, f.reader())?;
+                }
+                Entry::Special(s) => {
+                    let entry_type = match s.file_type() {
+                        SFlag::S_IFCHR => EntryType::Char,
+                        SFlag::S_IFBLK => EntryType::Block,
+                        SFlag::S_IFIFO => EntryType::Fifo,
+                        ty => {
+                            return Err(Error::new(
+                                ErrorKind::Unsupported,
+                                format!("{ty:?} cannot be stored in a tarball"),
+                            ));
+                        }
+                    };
+                    header.set_entry_type(entry_type);
+                    let rdev = s.rdev().unwrap_or(0);
+                    header.set_device_major(major(rdev) as u32)?;
+                    header.set_device_minor(minor(rdev) as u32)?;
+                    builder.append_data(&mut header, name, std::io::empty())?;
+                }
+                Entry::Symlink(s) => {
+                    header.set_entry_type(EntryType::Symlink);
+                    builder.append_link(&mut header, name, s.target())?;
+                }
+            }
+        }
+        builder.into_inner()?;
+        Ok(())
+    }
 }
 
 impl Metadata {
     fn try_from_entry<R: Read>(
         contents: &Bytes,
         entry: &mut tar::Entry<R>,
+    ) -> std::io::Result<Self> {
+        Self::try_from_entry_with(entry, |b| contents.subslice_or_copy(b))
+    }
+
+    /// Build [Metadata] from a tar entry, using `to_bytes` to get [Bytes]
+    /// for the xattrs in the PAX extensions.
+    fn try_from_entry_with<R: Read>(
+        entry: &mut tar::Entry<R>,
+        to_bytes: impl Fn(&[u8]) -> Bytes,
     ) -> std::io::Result<Self> {
         let mut xattrs = BTreeMap::new();
         if let Ok(Some(pax_extensions)) = entry.pax_extensions() {
             for ext in pax_extensions.into_iter().filter_map(Result::ok) {
                 if ext.key_bytes().starts_with(b"SCHILY.xattr.") {
                     xattrs.insert(
-                        contents.subslice_or_copy(&ext.key_bytes()["SCHILY.xattr.".len()..]),
-                        contents.subslice_or_copy(ext.value_bytes()),
+                        to_bytes(&ext.key_bytes()["SCHILY.xattr.".len()..]),
+                        to_bytes(ext.value_bytes()),
                     );
                 }
             }
@@ -94,8 +310,9 @@ mod tests {
     use pretty_assertions::assert_eq;
 
     use super::*;
+    use crate::cmp::assert_approx_eq;
+    use crate::cmp::Fields;
     use crate::tests::demo_fs;
-    use crate::BytesPath;
 
     #[test]
     fn tar() {
@@ -103,7 +320,130 @@ mod tests {
         let fs = Filesystem::parse_tar(&contents).expect("failed to parse tar");
         let mut demo_fs = demo_fs();
         // tar is missing the top-level directory
-        demo_fs.unlink(&BytesPath::from("")).unwrap();
+        demo_fs.unlink(BytesPath::from("")).unwrap();
         assert_eq!(demo_fs, fs);
     }
+
+    #[test]
+    fn from_tar() {
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.follow_symlinks(false);
+        builder
+            .append_dir_all(".", "testdata/fs")
+            .expect("failed to build tar");
+        let contents = builder.into_inner().expect("failed to build tar");
+        let fs = Filesystem::from_tar(contents.as_slice()).expect("failed to read tar");
+        let expected = Filesystem::from_dir("testdata/fs").expect("failed to load dir");
+        // tar only stores mtime, in whole seconds
+        assert_approx_eq!(expected, fs, Fields::all() - Fields::TIME);
+        let secs = |t: SystemTime| t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+        for (path, entry) in &expected {
+            assert_eq!(
+                secs(entry.metadata().modified()),
+                secs(fs.get(path).expect("missing entry").metadata().modified()),
+                "{path:?}",
+            );
+        }
+    }
+
+    #[test]
+    fn from_tar_links_and_long_names() {
+        let long_name = format!("{}/lorem.txt", "dir".repeat(50));
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        header.set_size("Lorem ipsum\n".len() as u64);
+        builder
+            .append_data(&mut header, &long_name, &b"Lorem ipsum\n"[..])
+            .expect("failed to build tar");
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        header.set_size(0);
+        header.set_entry_type(EntryType::Link);
+        builder
+            .append_link(&mut header, "./hardlink.txt", &long_name)
+            .expect("failed to build tar");
+        let contents = builder.into_inner().expect("failed to build tar");
+
+        let fs = Filesystem::from_tar(contents.as_slice()).expect("failed to read tar");
+        for path in [long_name.as_str(), "hardlink.txt"] {
+            assert_eq!(
+                fs.get_file(path).expect("missing file").to_bytes().as_ref(),
+                b"Lorem ipsum\n",
+            );
+            assert_eq!(fs.nlink(path).expect("missing file"), 2);
+        }
+    }
+
+    #[test]
+    fn from_tar_rejects_escaping_path() {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_size(0);
+        header.set_entry_type(EntryType::Regular);
+        // set_path refuses '..', so write the name directly
+        let name = b"a/../../escape";
+        header.as_old_mut().name[..name.len()].copy_from_slice(name);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(Vec::new());
+        builder
+            .append(&header, std::io::empty())
+            .expect("failed to build tar");
+        let contents = builder.into_inner().expect("failed to build tar");
+
+        let err = Filesystem::from_tar(contents.as_slice()).expect_err("tar should be rejected");
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn pax_record_len() {
+        assert_eq!(pax_record(b"key", b"value"), b"13 key=value\n");
+        assert_eq!(pax_record(b"k", b"va"), b"7 k=va\n");
+        // 9 bytes without the length, so the length needs a second digit
+        assert_eq!(pax_record(b"k", b"value"), b"11 k=value\n");
+    }
+
+    #[test]
+    fn to_tar_round_trip() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        fs.insert(
+            "testdata/fifo",
+            Special::new(SFlag::S_IFIFO, 0, Metadata::default()),
+        );
+        fs.insert(
+            "testdata/null",
+            Special::new(SFlag::S_IFCHR, makedev(1, 3), Metadata::default()),
+        );
+        let mut tar = Vec::new();
+        fs.to_tar(&mut tar).expect("failed to write tar");
+        let mut tar2 = Vec::new();
+        fs.to_tar(&mut tar2).expect("failed to write tar");
+        assert_eq!(tar, tar2, "tar output is not deterministic");
+
+        let round_tripped = Filesystem::from_tar(tar.as_slice()).expect("failed to read tar");
+        assert_eq!(fs, round_tripped);
+        assert_eq!(round_tripped.nlink("testdata/hardlink.txt").unwrap(), 2);
+    }
+
+    #[test]
+    fn to_tar_xattr_filter() {
+        let mut fs = demo_fs();
+        fs.get_mut("testdata/lorem.txt")
+            .unwrap()
+            .set_xattr("security.selinux", "system_u:object_r:etc_t:s0");
+        let mut tar = Vec::new();
+        fs.to_tar_with(&mut tar, &XattrFilter::namespaces(["user"]))
+            .expect("failed to write tar");
+        let round_tripped = Filesystem::from_tar(tar.as_slice()).expect("failed to read tar");
+        assert_eq!(demo_fs(), round_tripped);
+    }
 }
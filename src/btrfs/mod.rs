@@ -0,0 +1,1433 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::ops::Deref;
+use std::path::Path;
+
+use bytes::Bytes;
+use nix::sys::stat::Mode;
+use nix::sys::stat::SFlag;
+use sendstream_parser::Command;
+use sendstream_parser::Sendstream;
+use uuid::Uuid;
+
+use crate::entry::Directory;
+use crate::entry::Special;
+use crate::entry::Symlink;
+use crate::file::File;
+use crate::Filesystem;
+use crate::Gid;
+use crate::Uid;
+use crate::ValidationError;
+
+mod send;
+mod spill;
+
+pub use spill::Spill;
+pub use spill::SPILL_CHUNK_SIZE;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error<'c> {
+    #[error("invariant violated: {0}")]
+    InvariantViolated(&'static str),
+    #[error("parent subvol not yet received: {0}")]
+    MissingParent(Uuid),
+    #[error("clone source subvol not yet received: {0}")]
+    MissingCloneSource(Uuid),
+    #[error("received filesystem is invalid: {0:?}")]
+    Invalid(Vec<ValidationError>),
+    #[error(transparent)]
+    Parse(sendstream_parser::Error<'c>),
+    #[error("unsupported sendstream version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("failed to apply {command:?}: {error:?}")]
+    Apply {
+        command: Command<'c>,
+        error: std::io::Error,
+    },
+}
+
+/// How far [Subvols::receive_with_progress] has got through a sendstream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of commands received so far, including the current one
+    pub commands: usize,
+    /// Total number of commands in the sendstream
+    pub total_commands: usize,
+    /// Total length of the file data written so far
+    pub bytes: u64,
+}
+
+type ProgressFn<'a, 'f> = dyn FnMut(&Command<'f>, &Progress) + 'a;
+
+enum ApplyError<'c> {
+    Apply(std::io::Error),
+    Btrfs(Error<'c>),
+}
+
+impl<'c> From<std::io::Error> for ApplyError<'c> {
+    fn from(e: std::io::Error) -> Self {
+        Self::Apply(e)
+    }
+}
+
+impl<'c> From<Error<'c>> for ApplyError<'c> {
+    fn from(e: Error<'c>) -> Self {
+        Self::Btrfs(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subvol {
+    parent_uuid: Option<Uuid>,
+    fs: Filesystem,
+}
+
+impl Subvol {
+    /// The subvolume that this one was snapshotted from, if any
+    pub fn parent_uuid(&self) -> Option<Uuid> {
+        self.parent_uuid
+    }
+
+    fn new() -> Self {
+        Subvol {
+            parent_uuid: None,
+            fs: Filesystem::new(),
+        }
+    }
+
+    /// A valid sendstream always creates parent directories before any of
+    /// their children.
+    fn ensure_parent_dir<'c>(&self, path: &Path) -> Result<(), Error<'c>> {
+        match path.parent() {
+            Some(parent) => match self.fs.get(parent) {
+                Ok(entry) if entry.is_directory() => Ok(()),
+                _ => Err(Error::InvariantViolated("parent directory does not exist")),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subvols(BTreeMap<Uuid, Subvol>);
+
+impl Subvols {
+    pub fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    pub fn get(&self, uuid: &Uuid) -> Option<&Subvol> {
+        self.0.get(uuid)
+    }
+
+    /// `uuid` followed by its parent, grandparent and so on, for as long as
+    /// those subvolumes have been received.
+    pub fn lineage(&self, uuid: &Uuid) -> Vec<Uuid> {
+        let mut lineage = Vec::new();
+        let mut next = self.0.get(uuid).map(|_| *uuid);
+        while let Some(uuid) = next {
+            lineage.push(uuid);
+            next = self.0[&uuid]
+                .parent_uuid
+                .filter(|p| self.0.contains_key(p) && !lineage.contains(p));
+        }
+        lineage
+    }
+
+    /// The received contents of the subvolume `uuid`
+    pub fn filesystem(&self, uuid: &Uuid) -> Option<&Filesystem> {
+        self.0.get(uuid).map(|subvol| &subvol.fs)
+    }
+
+    /// Take ownership of the contents of the subvolume `uuid`, dropping all
+    /// the others.
+    pub fn into_filesystem(mut self, uuid: &Uuid) -> Option<Filesystem> {
+        self.0.remove(uuid).map(|subvol| subvol.fs)
+    }
+
+    /// Subvolumes that were snapshotted directly from `uuid`
+    pub fn children(&self, uuid: &Uuid) -> Vec<Uuid> {
+        self.0
+            .iter()
+            .filter(|(_, subvol)| subvol.parent_uuid.as_ref() == Some(uuid))
+            .map(|(child, _)| *child)
+            .collect()
+    }
+
+    #[remain::check]
+    fn apply_cmd<'c>(
+        &self,
+        subvol_uuid: Uuid,
+        subvol: &mut Subvol,
+        cmd: &Command<'c>,
+        spill: Option<&mut Spill>,
+    ) -> Result<(), ApplyError<'c>> {
+        match cmd {
+            Command::Chmod(c) => {
+                subvol.fs.chmod(c.path(), c.mode().mode())?;
+                Ok(())
+            }
+            Command::Chown(c) => {
+                subvol
+                    .fs
+                    .chown(c.path(), Uid::from(c.uid()), Gid::from(c.gid()))?;
+                Ok(())
+            }
+            Command::Clone(c) => {
+                // the source may be in this subvol or any other that was
+                // already received (usually the parent of a snapshot)
+                let src_fs = match c.uuid() == subvol_uuid {
+                    true => &subvol.fs,
+                    false => {
+                        &self
+                            .0
+                            .get(&c.uuid())
+                            .ok_or(Error::MissingCloneSource(c.uuid()))?
+                            .fs
+                    }
+                };
+                let start = c.src_offset().as_u64();
+                let end = start
+                    .checked_add(c.len().as_u64())
+                    .ok_or(Error::InvariantViolated("Clone source range overflows"))?;
+                // cloning the source is cheap since the data is shared, and
+                // lets the source and destination be in the same subvol
+                let src = src_fs.get_file(c.src_path())?.clone();
+                subvol
+                    .fs
+                    .get_file_mut(c.dst_path())?
+                    .clone_range_from(c.dst_offset().as_u64(), &src, start..end)
+                    .map_err(std::io::Error::from)?;
+                Ok(())
+            }
+            Command::End => Err(Error::InvariantViolated("attempted to apply end").into()),
+            Command::Link(l) => {
+                subvol.fs.link(l.target().as_path(), l.link_name())?;
+                Ok(())
+            }
+            Command::Mkdir(m) => {
+                subvol.ensure_parent_dir(m.path().as_path())?;
+                subvol
+                    .fs
+                    .try_insert(m.path().as_path(), Directory::default())?;
+                Ok(())
+            }
+            Command::Mkfifo(ref m) => {
+                subvol.fs.try_insert(
+                    m.path().as_path(),
+                    Special::new(m.mode().file_type(), *m.rdev(), Default::default()),
+                )?;
+                Ok(())
+            }
+            Command::Mkfile(m) => {
+                // btrfs creates files as 0600, the real mode comes from a
+                // later chmod
+                let mut f = File::new_empty();
+                f.metadata.chmod(Mode::from_bits_truncate(0o600));
+                subvol.fs.try_insert(m.path().as_path(), f)?;
+                Ok(())
+            }
+            Command::Mknod(m) => {
+                // fifos and sockets have their own commands
+                if !matches!(m.mode().file_type(), SFlag::S_IFCHR | SFlag::S_IFBLK) {
+                    return Err(Error::InvariantViolated("mknod of a non-device").into());
+                }
+                subvol.fs.try_insert(
+                    m.path().as_path(),
+                    Special::new(m.mode().file_type(), *m.rdev(), Default::default()),
+                )?;
+                Ok(())
+            }
+            Command::Mksock(m) => {
+                subvol.fs.try_insert(
+                    m.path().as_path(),
+                    Special::new(m.mode().file_type(), *m.rdev(), Default::default()),
+                )?;
+                Ok(())
+            }
+            Command::RemoveXattr(r) => {
+                subvol
+                    .fs
+                    .get_mut(r.path())?
+                    .metadata_mut()
+                    .xattrs
+                    .retain(|k, _| k != r.name().deref());
+                Ok(())
+            }
+            Command::Rename(r) => {
+                subvol.fs.rename(r.from(), r.to())?;
+                Ok(())
+            }
+            Command::Rmdir(r) => match subvol.fs.rmdir(r.path()) {
+                Err(e) if e.kind() == ErrorKind::DirectoryNotEmpty => {
+                    Err(Error::InvariantViolated("rmdir of a non-empty directory").into())
+                }
+                res => res.map_err(ApplyError::from),
+            },
+            Command::SetXattr(s) => {
+                subvol.fs.get_mut(s.path())?.metadata_mut().xattrs.insert(
+                    Bytes::copy_from_slice(s.name()),
+                    Bytes::copy_from_slice(s.data()),
+                );
+                Ok(())
+            }
+            Command::Snapshot(_) => {
+                Err(Error::InvariantViolated("attempted to apply snapshot").into())
+            }
+            Command::Subvol(_) => Err(Error::InvariantViolated("attempted to apply subvol").into()),
+            Command::Symlink(s) => {
+                subvol
+                    .fs
+                    .try_insert(s.link_name(), Symlink::new(s.target().as_path(), None))?;
+                Ok(())
+            }
+            Command::Truncate(t) => {
+                subvol.fs.truncate(t.path(), t.size())?;
+                Ok(())
+            }
+            Command::Unlink(u) => {
+                if subvol.fs.get(u.path())?.is_directory() {
+                    return Err(Error::InvariantViolated("unlink of a directory").into());
+                }
+                subvol.fs.unlink(u.path())?;
+                Ok(())
+            }
+            Command::UpdateExtent(u) => {
+                // the range is unchanged from the same file in the parent
+                // subvol, so share its data instead of copying it
+                let parent_uuid = subvol.parent_uuid.ok_or(Error::InvariantViolated(
+                    "UpdateExtent outside of a snapshot",
+                ))?;
+                let parent = self
+                    .0
+                    .get(&parent_uuid)
+                    .ok_or(Error::MissingParent(parent_uuid))?;
+                let start = u.offset().as_u64();
+                let src = parent.fs.get_file(u.path())?;
+                let end = start
+                    .checked_add(u.len())
+                    .filter(|end| *end <= src.len())
+                    .ok_or(Error::InvariantViolated(
+                        "UpdateExtent past the end of parent file",
+                    ))?;
+                subvol
+                    .fs
+                    .get_file_mut(u.path())?
+                    .clone_range_from(start, src, start..end)
+                    .map_err(std::io::Error::from)?;
+                Ok(())
+            }
+            Command::Utimes(u) => {
+                subvol
+                    .fs
+                    .set_times(u.path(), *u.ctime(), *u.atime(), *u.mtime())?;
+                Ok(())
+            }
+            Command::Write(w) => {
+                let f = subvol.fs.get_file_mut(w.path())?;
+                let mut wr = f.writer();
+                wr.seek(SeekFrom::Start(w.offset().as_u64()))
+                    .expect("infallible");
+                wr.check_write(w.data().as_slice().len() as u64)
+                    .map_err(std::io::Error::from)?;
+                let data = match spill {
+                    Some(spill) => spill.store(w.data().as_slice())?,
+                    None => Bytes::copy_from_slice(w.data().as_slice()),
+                };
+                wr.write(data);
+                Ok(())
+            }
+        }
+    }
+
+    /// Parse subvolumes from an uncompressed sendstream. Only version 1
+    /// sendstreams can be parsed, see [check_versions].
+    pub fn receive<'f>(&mut self, sendstream: Sendstream<'f>) -> Result<(), Error<'f>> {
+        self.receive_with(sendstream, None, &mut |_, _| {})
+    }
+
+    /// Like [Subvols::receive], but `progress` is called after every command
+    /// is received, which is useful to report progress for long sendstreams.
+    pub fn receive_with_progress<'f, F>(
+        &mut self,
+        sendstream: Sendstream<'f>,
+        mut progress: F,
+    ) -> Result<(), Error<'f>>
+    where
+        F: FnMut(&Command<'f>, &Progress),
+    {
+        self.receive_with(sendstream, None, &mut progress)
+    }
+
+    /// Like [Subvols::receive], but the contents of every write are stored in
+    /// `spill` instead of being copied into memory. See [Spill].
+    pub fn receive_streaming<'f>(
+        &mut self,
+        sendstream: Sendstream<'f>,
+        spill: &mut Spill,
+    ) -> Result<(), Error<'f>> {
+        self.receive_with(sendstream, Some(spill), &mut |_, _| {})
+    }
+
+    /// Parse and receive every sendstream in `data`. When `verify` is set, the
+    /// crc32c of every command is checked first, since the parser does not
+    /// check them. Checksums can only be verified against the raw bytes: a
+    /// parsed [Sendstream] no longer has them, so callers of [Subvols::receive]
+    /// (and friends) that want verification must call [verify_checksums] on
+    /// the data before parsing it.
+    /// Only version 1 sendstreams are supported: v2 streams (`btrfs send
+    /// --proto 2`, which may contain encoded writes) are rejected with
+    /// [Error::UnsupportedVersion].
+    pub fn receive_bytes<'f>(&mut self, data: &'f [u8], verify: bool) -> Result<(), Error<'f>> {
+        check_versions(data)?;
+        if verify {
+            verify_checksums(data)?;
+        }
+        for sendstream in Sendstream::parse_all(data).map_err(Error::Parse)? {
+            self.receive(sendstream)?;
+        }
+        Ok(())
+    }
+
+    fn receive_with<'f>(
+        &mut self,
+        sendstream: Sendstream<'f>,
+        mut spill: Option<&mut Spill>,
+        progress: &mut ProgressFn<'_, 'f>,
+    ) -> Result<(), Error<'f>> {
+        let mut cmd_iter = sendstream.into_commands().into_iter();
+        let mut status = Progress {
+            commands: 0,
+            total_commands: cmd_iter.len(),
+            bytes: 0,
+        };
+        let mut report = |cmd: &Command<'f>| {
+            status.commands += 1;
+            if let Command::Write(w) = cmd {
+                status.bytes += w.data().as_slice().len() as u64;
+            }
+            progress(cmd, &status);
+        };
+        let first = cmd_iter.next().expect("must have at least one command");
+        let (mut subvol_uuid, mut subvol) = #[remain::sorted]
+        match &first {
+            Command::Snapshot(s) => {
+                let mut subvol = self
+                    .0
+                    .get(&s.clone_uuid())
+                    .ok_or(Error::MissingParent(s.clone_uuid()))?
+                    .clone();
+                subvol.parent_uuid = Some(s.clone_uuid());
+                (s.uuid(), subvol)
+            }
+            Command::Subvol(s) => {
+                let mut subvol = Subvol::new();
+                subvol.fs.insert("", Directory::default());
+                (s.uuid(), subvol)
+            }
+            _ => {
+                return Err(Error::InvariantViolated(
+                    "first command was not subvol start",
+                ))
+            }
+        };
+        report(&first);
+        let mut ended = false;
+        for cmd in cmd_iter.by_ref() {
+            match &cmd {
+                Command::End => {
+                    report(&cmd);
+                    ended = true;
+                    break;
+                }
+                Command::Snapshot(s) => {
+                    self.finish(subvol_uuid, subvol)?;
+                    subvol = self
+                        .0
+                        .get(&s.clone_uuid())
+                        .ok_or(Error::MissingParent(s.clone_uuid()))?
+                        .clone();
+                    subvol.parent_uuid = Some(s.clone_uuid());
+                    subvol_uuid = s.uuid();
+                }
+                Command::Subvol(s) => {
+                    self.finish(subvol_uuid, subvol)?;
+                    subvol = Subvol::new();
+                    subvol.fs.insert("", Directory::default());
+                    subvol_uuid = s.uuid();
+                }
+                _ => {
+                    if let Err(error) =
+                        self.apply_cmd(subvol_uuid, &mut subvol, &cmd, spill.as_deref_mut())
+                    {
+                        return Err(match error {
+                            ApplyError::Apply(error) => Error::Apply {
+                                command: cmd,
+                                error,
+                            },
+                            ApplyError::Btrfs(error) => error,
+                        });
+                    }
+                }
+            }
+            report(&cmd);
+        }
+        if !ended {
+            return Err(Error::InvariantViolated(
+                "stream ended without an end command",
+            ));
+        }
+        if cmd_iter.next().is_some() {
+            return Err(Error::InvariantViolated("commands after end of stream"));
+        }
+        self.finish(subvol_uuid, subvol)
+    }
+
+    /// Store a completely received subvolume, making sure that it is valid
+    fn finish<'f>(&mut self, uuid: Uuid, subvol: Subvol) -> Result<(), Error<'f>> {
+        subvol.fs.validate().map_err(Error::Invalid)?;
+        self.0.insert(uuid, subvol);
+        Ok(())
+    }
+}
+
+/// Either the header of a new sendstream or a single (unparsed) command.
+enum Chunk<'a> {
+    Version(u32),
+    Command(&'a [u8]),
+}
+
+/// Split the (possibly concatenated) sendstreams in `data` into headers and
+/// commands, stopping at the first truncated command.
+fn chunks(mut data: &[u8]) -> impl Iterator<Item = Chunk<'_>> {
+    const MAGIC: &[u8] = b"btrfs-stream\0";
+    const HEADER_LEN: usize = 10;
+    std::iter::from_fn(move || {
+        if let Some(rest) = data.strip_prefix(MAGIC) {
+            let version = rest.get(..4)?;
+            data = &rest[4..];
+            return Some(Chunk::Version(u32::from_le_bytes(
+                version.try_into().expect("4 bytes"),
+            )));
+        }
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        let len = u32::from_le_bytes(data[..4].try_into().expect("4 bytes")) as usize;
+        let cmd = data.get(..HEADER_LEN + len)?;
+        data = &data[cmd.len()..];
+        Some(Chunk::Command(cmd))
+    })
+}
+
+/// Check the crc32c of every command in the (possibly concatenated)
+/// sendstreams in `data`. Anything that cannot be parsed is left for
+/// [Sendstream::parse_all] to report.
+pub fn verify_checksums<'f>(data: &[u8]) -> Result<(), Error<'f>> {
+    for chunk in chunks(data) {
+        if let Chunk::Command(cmd) = chunk {
+            let expected = u32::from_le_bytes(cmd[6..10].try_into().expect("4 bytes"));
+            let mut zeroed = cmd.to_vec();
+            zeroed[6..10].fill(0);
+            if send::crc32c(&zeroed) != expected {
+                return Err(Error::InvariantViolated("checksum mismatch"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that every (possibly concatenated) sendstream in `data` is version
+/// 1 of the protocol. The parser does not understand anything else and
+/// panics on it, so call this before [Sendstream::parse_all] unless using
+/// [Subvols::receive_bytes], which already does.
+pub fn check_versions<'f>(data: &[u8]) -> Result<(), Error<'f>> {
+    for chunk in chunks(data) {
+        match chunk {
+            Chunk::Version(1) | Chunk::Command(_) => {}
+            Chunk::Version(version) => return Err(Error::UnsupportedVersion(version)),
+        }
+    }
+    Ok(())
+}
+
+impl Default for Subvols {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashSet;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use bytes::Bytes;
+    use nix::sys::stat::SFlag;
+
+    use super::send::Encoder;
+    use super::*;
+    use crate::cmp::assert_approx_eq;
+    use crate::cmp::Fields;
+    use crate::entry::Metadata;
+    use crate::file::extent::Extent;
+    use crate::tests::demo_fs;
+    use crate::BytesExt;
+    use crate::Entry;
+    use crate::Gid;
+    use crate::Uid;
+
+    /// Wrapper around [Encoder] so that tests can concisely build specific
+    /// command sequences without regenerating the testdata.
+    pub(crate) struct StreamBuilder(Encoder);
+
+    impl StreamBuilder {
+        pub(crate) fn subvol(uuid: Uuid) -> Self {
+            let mut enc = Encoder::new();
+            enc.subvol(Path::new("subvol"), uuid, 0);
+            Self(enc)
+        }
+
+        pub(crate) fn snapshot(uuid: Uuid, parent_uuid: Uuid) -> Self {
+            let mut enc = Encoder::new();
+            enc.snapshot(Path::new("snapshot"), uuid, 0, parent_uuid, 0);
+            Self(enc)
+        }
+
+        pub(crate) fn mkfile(mut self, path: &str) -> Self {
+            self.0.mkfile(Path::new(path), 0);
+            self
+        }
+
+        pub(crate) fn mkdir(mut self, path: &str) -> Self {
+            self.0.mkdir(Path::new(path), 0);
+            self
+        }
+
+        pub(crate) fn mkspecial(mut self, path: &str, rdev: u64, mode: u32) -> Self {
+            self.0.mkspecial(Path::new(path), 0, rdev, mode);
+            self
+        }
+
+        pub(crate) fn symlink(mut self, path: &str, target: &str) -> Self {
+            self.0.symlink(Path::new(path), 0, Path::new(target));
+            self
+        }
+
+        pub(crate) fn rename(mut self, from: &str, to: &str) -> Self {
+            self.0.rename(Path::new(from), Path::new(to));
+            self
+        }
+
+        pub(crate) fn link(mut self, path: &str, target: &str) -> Self {
+            self.0.link(Path::new(path), Path::new(target));
+            self
+        }
+
+        pub(crate) fn unlink(mut self, path: &str) -> Self {
+            self.0.unlink(Path::new(path));
+            self
+        }
+
+        pub(crate) fn rmdir(mut self, path: &str) -> Self {
+            self.0.rmdir(Path::new(path));
+            self
+        }
+
+        pub(crate) fn set_xattr(mut self, path: &str, name: &str, data: &str) -> Self {
+            self.0
+                .set_xattr(Path::new(path), name.as_bytes(), data.as_bytes());
+            self
+        }
+
+        pub(crate) fn remove_xattr(mut self, path: &str, name: &str) -> Self {
+            self.0.remove_xattr(Path::new(path), name.as_bytes());
+            self
+        }
+
+        pub(crate) fn write(mut self, path: &str, offset: u64, data: impl AsRef<[u8]>) -> Self {
+            self.0.write(Path::new(path), offset, data.as_ref());
+            self
+        }
+
+        pub(crate) fn clone(
+            mut self,
+            src_uuid: Uuid,
+            src_path: &str,
+            src_offset: u64,
+            len: u64,
+            dst_path: &str,
+            dst_offset: u64,
+        ) -> Self {
+            self.0.clone(
+                Path::new(dst_path),
+                dst_offset,
+                len,
+                src_uuid,
+                0,
+                Path::new(src_path),
+                src_offset,
+            );
+            self
+        }
+
+        pub(crate) fn truncate(mut self, path: &str, size: u64) -> Self {
+            self.0.truncate(Path::new(path), size);
+            self
+        }
+
+        pub(crate) fn chown(mut self, path: &str, uid: u32, gid: u32) -> Self {
+            self.0.chown(Path::new(path), uid, gid);
+            self
+        }
+
+        pub(crate) fn utimes(mut self, path: &str, atime: u64, mtime: u64, ctime: u64) -> Self {
+            let time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            self.0
+                .utimes(Path::new(path), time(atime), time(mtime), time(ctime));
+            self
+        }
+
+        pub(crate) fn update_extent(mut self, path: &str, offset: u64, len: u64) -> Self {
+            self.0.update_extent(Path::new(path), offset, len);
+            self
+        }
+
+        pub(crate) fn end(mut self) -> Self {
+            self.0.end();
+            self
+        }
+
+        /// Finish this stream and receive it into an existing set of
+        /// subvolumes
+        pub(crate) fn receive_into(&mut self, subvols: &mut Subvols) -> Result<(), Error<'_>> {
+            self.0.end();
+            let mut sendstreams =
+                Sendstream::parse_all(self.0.as_bytes()).expect("failed to parse sendstream");
+            assert_eq!(1, sendstreams.len());
+            subvols.receive(sendstreams.remove(0))
+        }
+
+        /// Finish this stream and receive it as a single new subvolume
+        pub(crate) fn receive(&mut self) -> Result<Subvol, Error<'_>> {
+            let mut subvols = Subvols::new();
+            self.receive_into(&mut subvols)?;
+            Ok(subvols
+                .0
+                .into_values()
+                .next()
+                .expect("must have received one subvol"))
+        }
+    }
+
+    /// Receive the test sendstream and return the subvolumes with the parent
+    /// always first.
+    fn receive_testdata() -> Vec<Subvol> {
+        let contents = Bytes::from_static(include_bytes!("../../testdata/testdata.sendstream"));
+        let sendstreams = Sendstream::parse_all(&contents).expect("failed to parse sendstream");
+        let mut subvols = Subvols::new();
+        for sendstream in sendstreams {
+            subvols
+                .receive(sendstream)
+                .expect("failed to receive sendstream");
+        }
+        // drop the uuid which will change on every build and re-order so that
+        // the parent is always first
+        let uuids: HashSet<Uuid> = subvols.0.keys().copied().collect();
+        let mut subvols: Vec<_> = subvols.0.into_values().collect();
+        assert_eq!(2, subvols.len());
+        subvols.sort_by_key(|s| s.parent_uuid);
+        let parent_uuid = subvols[1].parent_uuid.unwrap();
+        assert!(uuids.contains(&parent_uuid));
+        subvols
+    }
+
+    #[test]
+    fn filesystem() {
+        let contents = include_bytes!("../../testdata/testdata.sendstream");
+        let mut subvols = Subvols::new();
+        subvols
+            .receive_bytes(contents, true)
+            .expect("failed to receive");
+        let expected = receive_testdata();
+        for subvol in &expected {
+            let uuid = subvols
+                .0
+                .iter()
+                .find(|(_, s)| s.parent_uuid.is_some() == subvol.parent_uuid.is_some())
+                .map(|(uuid, _)| *uuid)
+                .expect("missing subvol");
+            assert_eq!(subvols.filesystem(&uuid), Some(&subvol.fs));
+        }
+        assert_eq!(subvols.filesystem(&Uuid::nil()), None);
+        let uuid = *subvols.0.keys().next().expect("no subvols");
+        let fs = subvols.filesystem(&uuid).cloned();
+        assert_eq!(subvols.clone().into_filesystem(&uuid), fs);
+        assert_eq!(subvols.into_filesystem(&Uuid::nil()), None);
+    }
+
+    #[test]
+    fn lineage() {
+        let contents = include_bytes!("../../testdata/testdata.sendstream");
+        let mut subvols = Subvols::new();
+        subvols
+            .receive_bytes(contents, true)
+            .expect("failed to receive");
+        let (child, parent) = subvols
+            .0
+            .iter()
+            .find_map(|(uuid, subvol)| subvol.parent_uuid().map(|p| (*uuid, p)))
+            .expect("one subvol is a snapshot");
+        assert_eq!(subvols.get(&parent).unwrap().parent_uuid(), None);
+        assert_eq!(subvols.lineage(&child), vec![child, parent]);
+        assert_eq!(subvols.lineage(&parent), vec![parent]);
+        assert_eq!(subvols.lineage(&Uuid::nil()), Vec::<Uuid>::new());
+        assert_eq!(subvols.children(&parent), vec![child]);
+        assert_eq!(subvols.children(&child), Vec::<Uuid>::new());
+        assert!(subvols.get(&Uuid::nil()).is_none());
+    }
+
+    #[test]
+    fn sendstream() {
+        let subvols = receive_testdata();
+        assert_approx_eq!(demo_fs(), &subvols[0].fs, Fields::all() - Fields::TIME);
+        // the second subvol has some differences compared to the demo fs
+        let mut demo2 = demo_fs();
+        demo2.insert(
+            "wow",
+            File::builder()
+                .metadata(
+                    Metadata::builder()
+                        .mode(Mode::from_bits_truncate(0o644))
+                        .uid(Uid::from_raw(0))
+                        .gid(Gid::from_raw(0))
+                        .build(),
+                )
+                .build(),
+        );
+        assert_approx_eq!(demo2, &subvols[1].fs, Fields::all() - Fields::TIME);
+    }
+
+    #[test]
+    fn write() {
+        let subvols = receive_testdata();
+        for subvol in &subvols {
+            assert_eq!(
+                subvol
+                    .fs
+                    .get_file("testdata/lorem.txt")
+                    .expect("missing file")
+                    .to_bytes()
+                    .as_ref(),
+                b"Lorem ipsum\n",
+            );
+            assert_eq!(
+                subvol
+                    .fs
+                    .get_file("testdata/dir/lorem.txt")
+                    .expect("missing file")
+                    .to_bytes()
+                    .as_ref(),
+                b"Lorem ipsum dolor sit amet\n",
+            );
+        }
+    }
+
+    #[test]
+    fn write_past_max_len() {
+        let mut stream = StreamBuilder::subvol(Uuid::nil())
+            .mkfile("lorem.txt")
+            .write("lorem.txt", u64::MAX - 2, "Lorem");
+        assert!(matches!(
+            stream.receive(),
+            Err(Error::Apply { error, .. }) if error.kind() == ErrorKind::FileTooLarge
+        ));
+    }
+
+    #[test]
+    fn mkfile() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkfile("lorem.txt")
+            .write("lorem.txt", 0, "Lorem ipsum\n")
+            .receive()
+            .expect("failed to receive");
+        let f = subvol.fs.get_file("lorem.txt").expect("missing file");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ipsum\n");
+        assert_eq!(f.metadata.mode(), Mode::from_bits_truncate(0o600));
+        assert_eq!(f.metadata.uid(), Uid::from_raw(0));
+        assert_eq!(f.metadata.gid(), Gid::from_raw(0));
+    }
+
+    #[test]
+    fn mkdir() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkdir("testdata")
+            .mkdir("testdata/dir")
+            .mkfile("testdata/dir/lorem.txt")
+            .write("testdata/dir/lorem.txt", 0, "Lorem ipsum dolor sit amet\n")
+            .receive()
+            .expect("failed to receive");
+        let mut expected = Filesystem::new();
+        expected.insert("", Directory::default());
+        expected.insert("testdata", Directory::default());
+        expected.insert("testdata/dir", Directory::default());
+        expected.insert(
+            "testdata/dir/lorem.txt",
+            File::builder()
+                .contents("Lorem ipsum dolor sit amet\n")
+                .build(),
+        );
+        assert_approx_eq!(expected, subvol.fs, Fields::all() - Fields::MODE);
+    }
+
+    #[test]
+    fn mkdir_missing_parent() {
+        let mut stream = StreamBuilder::subvol(Uuid::nil()).mkdir("testdata/dir");
+        assert!(matches!(
+            stream.receive(),
+            Err(Error::InvariantViolated("parent directory does not exist"))
+        ));
+    }
+
+    #[test]
+    fn rename() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkdir("o257-7-0")
+            .mkdir("o257-7-0/dir")
+            .mkfile("o258-7-0")
+            .write("o258-7-0", 0, "Lorem ipsum\n")
+            .rename("o258-7-0", "o257-7-0/dir/lorem.txt")
+            .rename("o257-7-0", "testdata")
+            .receive()
+            .expect("failed to receive");
+        let paths: Vec<_> = subvol.fs.iter().map(|(p, _)| p).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new(""),
+                Path::new("testdata"),
+                Path::new("testdata/dir"),
+                Path::new("testdata/dir/lorem.txt"),
+            ],
+        );
+        assert_eq!(
+            subvol
+                .fs
+                .get_file("testdata/dir/lorem.txt")
+                .expect("missing file")
+                .to_bytes()
+                .as_ref(),
+            b"Lorem ipsum\n",
+        );
+    }
+
+    #[test]
+    fn unlink_and_rmdir() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkdir("testdata")
+            .mkdir("testdata/dir")
+            .mkfile("testdata/dir/lorem.txt")
+            .mkfile("testdata/lorem.txt")
+            .unlink("testdata/dir/lorem.txt")
+            .rmdir("testdata/dir")
+            .receive()
+            .expect("failed to receive");
+        let paths: Vec<_> = subvol.fs.iter().map(|(p, _)| p).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new(""),
+                Path::new("testdata"),
+                Path::new("testdata/lorem.txt"),
+            ],
+        );
+    }
+
+    #[test]
+    fn rmdir_not_empty() {
+        let mut stream = StreamBuilder::subvol(Uuid::nil())
+            .mkdir("testdata")
+            .mkfile("testdata/lorem.txt")
+            .rmdir("testdata");
+        assert!(matches!(
+            stream.receive(),
+            Err(Error::InvariantViolated("rmdir of a non-empty directory"))
+        ));
+    }
+
+    #[test]
+    fn unlink_directory() {
+        let mut stream = StreamBuilder::subvol(Uuid::nil())
+            .mkdir("testdata")
+            .unlink("testdata");
+        assert!(matches!(
+            stream.receive(),
+            Err(Error::InvariantViolated("unlink of a directory"))
+        ));
+    }
+
+    #[test]
+    fn symlink() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkfile("lorem.txt")
+            .symlink("symlink", "lorem.txt")
+            .receive()
+            .expect("failed to receive");
+        match subvol.fs.get("symlink").expect("missing symlink") {
+            Entry::Symlink(s) => assert_eq!(s.target(), Path::new("lorem.txt")),
+            e => panic!("expected symlink, got {e:?}"),
+        }
+    }
+
+    #[test]
+    fn link() {
+        let stream = || {
+            StreamBuilder::subvol(Uuid::nil())
+                .mkfile("lorem.txt")
+                .write("lorem.txt", 0, "Lorem ipsum\n")
+                .link("hardlink.txt", "lorem.txt")
+                .write("hardlink.txt", 6, "IPSUM")
+        };
+        let subvol = stream().receive().expect("failed to receive");
+        for path in ["lorem.txt", "hardlink.txt"] {
+            assert_eq!(
+                subvol
+                    .fs
+                    .get_file(path)
+                    .expect("missing file")
+                    .to_bytes()
+                    .as_ref(),
+                b"Lorem IPSUM\n",
+            );
+            assert_eq!(subvol.fs.nlink(path).expect("missing file"), 2);
+        }
+
+        let subvol = stream()
+            .unlink("lorem.txt")
+            .receive()
+            .expect("failed to receive");
+        assert!(subvol.fs.get("lorem.txt").is_err());
+        assert_eq!(
+            subvol
+                .fs
+                .get_file("hardlink.txt")
+                .expect("missing file")
+                .to_bytes()
+                .as_ref(),
+            b"Lorem IPSUM\n",
+        );
+        assert_eq!(subvol.fs.nlink("hardlink.txt").expect("missing file"), 1);
+    }
+
+    #[test]
+    fn special() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkspecial("fifo", 0, SFlag::S_IFIFO.bits() | 0o644)
+            .mkspecial("sock", 0, SFlag::S_IFSOCK.bits() | 0o755)
+            .mkspecial(
+                "null",
+                nix::sys::stat::makedev(1, 3),
+                SFlag::S_IFCHR.bits() | 0o666,
+            )
+            .receive()
+            .expect("failed to receive");
+        for (path, file_type, rdev) in [
+            ("fifo", SFlag::S_IFIFO, None),
+            ("sock", SFlag::S_IFSOCK, None),
+            ("null", SFlag::S_IFCHR, Some(nix::sys::stat::makedev(1, 3))),
+        ] {
+            match subvol.fs.get(path).expect("missing entry") {
+                Entry::Special(s) => {
+                    assert_eq!(s.file_type(), file_type, "{path}");
+                    assert_eq!(s.rdev(), rdev, "{path}");
+                }
+                e => panic!("expected special file, got {e:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn utimes() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkdir("dir")
+            .mkfile("dir/lorem.txt")
+            .utimes("dir/lorem.txt", 1, 2, 3)
+            .utimes("dir", 4, 5, 6)
+            .receive()
+            .expect("failed to receive");
+        for (path, (atime, mtime, ctime)) in [("dir/lorem.txt", (1, 2, 3)), ("dir", (4, 5, 6))] {
+            let metadata = subvol.fs.get(path).expect("missing entry").metadata();
+            assert_eq!(
+                metadata.accessed(),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(atime)
+            );
+            assert_eq!(
+                metadata.modified(),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)
+            );
+            assert_eq!(
+                metadata.created(),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(ctime)
+            );
+        }
+    }
+
+    #[test]
+    fn chown() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkdir("dir")
+            .mkfile("dir/lorem.txt")
+            .symlink("dir/symlink", "lorem.txt")
+            .chown("dir", 1000, 1000)
+            .chown("dir/lorem.txt", 1001, 1002)
+            .chown("dir/symlink", 1003, 1004)
+            .receive()
+            .expect("failed to receive");
+        for (path, uid, gid) in [
+            ("dir", 1000, 1000),
+            ("dir/lorem.txt", 1001, 1002),
+            ("dir/symlink", 1003, 1004),
+        ] {
+            let metadata = subvol.fs.get(path).expect("missing entry").metadata();
+            assert_eq!(metadata.uid(), Uid::from_raw(uid));
+            assert_eq!(metadata.gid(), Gid::from_raw(gid));
+        }
+    }
+
+    #[test]
+    fn xattrs() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkdir("dir")
+            .mkfile("dir/lorem.txt")
+            .set_xattr("dir", "user.foo", "bar")
+            .set_xattr("dir/lorem.txt", "user.foo", "baz")
+            .set_xattr("dir/lorem.txt", "user.removed", "qux")
+            .remove_xattr("dir/lorem.txt", "user.removed")
+            .receive()
+            .expect("failed to receive");
+        for (path, value) in [("dir", "bar"), ("dir/lorem.txt", "baz")] {
+            assert_eq!(
+                subvol
+                    .fs
+                    .get(path)
+                    .expect("missing entry")
+                    .metadata()
+                    .xattrs(),
+                &BTreeMap::from([(Bytes::from("user.foo"), Bytes::from(value))]),
+            );
+        }
+    }
+
+    #[test]
+    fn truncate() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkfile("grow")
+            .truncate("grow", 4)
+            .mkfile("grow_data")
+            .write("grow_data", 0, "Lorem")
+            .truncate("grow_data", 8)
+            .mkfile("shrink")
+            .write("shrink", 0, "Lorem ipsum")
+            .truncate("shrink", 5)
+            .receive()
+            .expect("failed to receive");
+        for (path, contents) in [
+            ("grow", &b"\0\0\0\0"[..]),
+            ("grow_data", b"Lorem\0\0\0"),
+            ("shrink", b"Lorem"),
+        ] {
+            let f = subvol.fs.get_file(path).expect("missing file");
+            assert_eq!(f.len(), contents.len() as u64);
+            assert_eq!(f.to_bytes().as_ref(), contents);
+        }
+    }
+
+    #[test]
+    fn clone_from_parent() {
+        let parent_uuid = Uuid::from_u128(1);
+        let child_uuid = Uuid::from_u128(2);
+        let mut subvols = Subvols::new();
+        StreamBuilder::subvol(parent_uuid)
+            .mkfile("lorem.txt")
+            .write("lorem.txt", 0, "Lorem ipsum dolor sit amet\n")
+            .receive_into(&mut subvols)
+            .expect("failed to receive parent");
+        StreamBuilder::snapshot(child_uuid, parent_uuid)
+            .mkfile("ipsum.txt")
+            .clone(parent_uuid, "lorem.txt", 6, 5, "ipsum.txt", 0)
+            .clone(child_uuid, "ipsum.txt", 0, 5, "ipsum.txt", 5)
+            .receive_into(&mut subvols)
+            .expect("failed to receive snapshot");
+
+        let src = subvols.0[&parent_uuid]
+            .fs
+            .get_file("lorem.txt")
+            .expect("missing source");
+        let dst = subvols.0[&child_uuid]
+            .fs
+            .get_file("ipsum.txt")
+            .expect("missing destination");
+        assert_eq!(dst.to_bytes().as_ref(), b"ipsumipsum");
+        // the cloned extents must point at the source data, not a copy of it
+        let src_data = src.extents[&0].bytes();
+        assert_eq!(dst.extents.len(), 2);
+        for ext in dst.extents.values() {
+            assert!(matches!(ext, Extent::Cloned(_)), "{ext:?}");
+            assert!(src_data.is_subslice(ext.data()), "{ext:?}");
+        }
+    }
+
+    #[test]
+    fn clone_missing_source() {
+        let mut stream = StreamBuilder::subvol(Uuid::from_u128(1))
+            .mkfile("lorem.txt")
+            .clone(Uuid::from_u128(2), "lorem.txt", 0, 5, "lorem.txt", 0);
+        assert!(matches!(
+            stream.receive(),
+            Err(Error::MissingCloneSource(u)) if u == Uuid::from_u128(2)
+        ));
+    }
+
+    #[test]
+    fn clone_range_overflow() {
+        let uuid = Uuid::from_u128(1);
+        let mut stream = StreamBuilder::subvol(uuid)
+            .mkfile("lorem.txt")
+            .write("lorem.txt", 0, "Lorem")
+            .clone(uuid, "lorem.txt", u64::MAX, 2, "lorem.txt", 0);
+        assert!(matches!(stream.receive(), Err(Error::InvariantViolated(_))));
+        let mut stream = StreamBuilder::subvol(uuid)
+            .mkfile("lorem.txt")
+            .write("lorem.txt", 0, "Lorem")
+            .clone(uuid, "lorem.txt", 0, 5, "lorem.txt", u64::MAX - 2);
+        assert!(matches!(
+            stream.receive(),
+            Err(Error::Apply { error, .. }) if error.kind() == ErrorKind::FileTooLarge
+        ));
+    }
+
+    #[test]
+    fn update_extent() {
+        let parent_uuid = Uuid::from_u128(1);
+        let child_uuid = Uuid::from_u128(2);
+        let mut subvols = Subvols::new();
+        StreamBuilder::subvol(parent_uuid)
+            .mkfile("lorem.txt")
+            .write("lorem.txt", 0, "Lorem ipsum dolor sit amet\n")
+            .receive_into(&mut subvols)
+            .expect("failed to receive parent");
+        StreamBuilder::snapshot(child_uuid, parent_uuid)
+            .write("lorem.txt", 0, "Xxxxx Xxxxx")
+            .update_extent("lorem.txt", 6, 5)
+            .receive_into(&mut subvols)
+            .expect("failed to receive snapshot");
+
+        let src = subvols.0[&parent_uuid]
+            .fs
+            .get_file("lorem.txt")
+            .expect("missing source");
+        let dst = subvols.0[&child_uuid]
+            .fs
+            .get_file("lorem.txt")
+            .expect("missing file");
+        assert_eq!(dst.to_bytes().as_ref(), b"Xxxxx ipsum dolor sit amet\n");
+        let ext = &dst.extents[&6];
+        assert!(matches!(ext, Extent::Cloned(_)), "{ext:?}");
+        assert!(src.extents[&0].bytes().is_subslice(ext.data()), "{ext:?}");
+    }
+
+    #[test]
+    fn update_extent_past_end() {
+        let parent_uuid = Uuid::from_u128(1);
+        let mut subvols = Subvols::new();
+        StreamBuilder::subvol(parent_uuid)
+            .mkfile("lorem.txt")
+            .write("lorem.txt", 0, "Lorem")
+            .receive_into(&mut subvols)
+            .expect("failed to receive parent");
+        let mut stream = StreamBuilder::snapshot(Uuid::from_u128(2), parent_uuid).update_extent(
+            "lorem.txt",
+            0,
+            10,
+        );
+        assert!(matches!(
+            stream.receive_into(&mut subvols),
+            Err(Error::InvariantViolated(_))
+        ));
+        let mut stream = StreamBuilder::snapshot(Uuid::from_u128(2), parent_uuid).update_extent(
+            "lorem.txt",
+            u64::MAX,
+            2,
+        );
+        assert!(matches!(
+            stream.receive_into(&mut subvols),
+            Err(Error::InvariantViolated(_))
+        ));
+    }
+
+    #[test]
+    fn mknod() {
+        let subvol = StreamBuilder::subvol(Uuid::nil())
+            .mkspecial(
+                "sda1",
+                nix::sys::stat::makedev(8, 1),
+                SFlag::S_IFBLK.bits() | 0o660,
+            )
+            .receive()
+            .expect("failed to receive");
+        match subvol.fs.get("sda1").expect("missing entry") {
+            Entry::Special(s) => {
+                assert_eq!(s.file_type(), SFlag::S_IFBLK);
+                let rdev = s.rdev().expect("devices have an rdev");
+                assert_eq!(nix::sys::stat::major(rdev), 8);
+                assert_eq!(nix::sys::stat::minor(rdev), 1);
+            }
+            e => panic!("expected special file, got {e:?}"),
+        }
+
+        // mkspecial only uses mknod for anything that is not a fifo or socket
+        assert!(matches!(
+            StreamBuilder::subvol(Uuid::nil())
+                .mkspecial("lorem.txt", 0, SFlag::S_IFREG.bits() | 0o644)
+                .receive(),
+            Err(Error::InvariantViolated(_))
+        ));
+    }
+
+    #[test]
+    fn receive_streaming() {
+        const WRITE_LEN: usize = 48 << 10;
+        let mut stream = StreamBuilder::subvol(Uuid::nil()).mkfile("big");
+        let mut expected = Vec::new();
+        for i in 0..64u8 {
+            let data = vec![i; WRITE_LEN];
+            stream = stream.write("big", expected.len() as u64, &data);
+            expected.extend(data);
+        }
+        let stream = stream.end();
+        let mut sendstreams =
+            Sendstream::parse_all(stream.0.as_bytes()).expect("failed to parse sendstream");
+        let mut spill = Spill::new(tempfile::tempfile().expect("failed to create tempfile"));
+        let mut subvols = Subvols::new();
+        subvols
+            .receive_streaming(sendstreams.remove(0), &mut spill)
+            .expect("failed to receive");
+        assert_eq!(spill.len(), expected.len() as u64);
+
+        let subvol = subvols.0.into_values().next().expect("missing subvol");
+        let f = subvol.fs.get_file("big").expect("missing file");
+        assert_eq!(f.to_bytes().as_ref(), expected.as_slice());
+        // all the data lives in the same mapped chunk, one write after another
+        let data: Vec<_> = f.extents.values().map(Extent::data).collect();
+        for pair in data.windows(2) {
+            assert_eq!(pair[0].as_ptr_range().end, pair[1].as_ptr());
+        }
+    }
+
+    #[test]
+    fn end() {
+        let mut enc = Encoder::new();
+        enc.subvol(Path::new("subvol"), Uuid::nil(), 0);
+        enc.mkdir(Path::new("dir"), 0);
+        let mut sendstreams = Sendstream::parse_all(enc.as_bytes()).expect("failed to parse");
+        assert!(matches!(
+            Subvols::new().receive(sendstreams.remove(0)),
+            Err(Error::InvariantViolated(_))
+        ));
+
+        let mut subvols = Subvols::new();
+        assert!(matches!(
+            StreamBuilder::subvol(Uuid::nil())
+                .mkdir("dir")
+                .end()
+                .mkdir("dir2")
+                .receive_into(&mut subvols),
+            Err(Error::InvariantViolated(_))
+        ));
+        assert!(subvols.0.is_empty());
+    }
+
+    #[test]
+    fn checksums() {
+        let mut data = StreamBuilder::subvol(Uuid::nil())
+            .mkfile("lorem.txt")
+            .write("lorem.txt", 0, "Lorem ipsum\n")
+            .end()
+            .0
+            .into_bytes();
+        Subvols::new()
+            .receive_bytes(&data, true)
+            .expect("failed to receive");
+        let testdata = include_bytes!("../../testdata/testdata.sendstream");
+        verify_checksums(testdata).expect("kernel checksums are valid");
+
+        let pos = data
+            .windows(5)
+            .position(|w| w == b"Lorem")
+            .expect("missing data");
+        data[pos] = b'l';
+        assert!(matches!(
+            Subvols::new().receive_bytes(&data, true),
+            Err(Error::InvariantViolated("checksum mismatch"))
+        ));
+        let mut subvols = Subvols::new();
+        subvols
+            .receive_bytes(&data, false)
+            .expect("failed to receive");
+        let subvol = subvols.0.into_values().next().expect("missing subvol");
+        assert_eq!(
+            subvol.fs.get_file("lorem.txt").unwrap().to_bytes().as_ref(),
+            b"lorem ipsum\n",
+        );
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let v1 = StreamBuilder::subvol(Uuid::nil())
+            .mkfile("lorem.txt")
+            .end()
+            .0
+            .into_bytes();
+        let mut v2 = v1.clone();
+        v2[13..17].copy_from_slice(&2u32.to_le_bytes());
+        check_versions(&v1).expect("v1 is supported");
+        for data in [v2.clone(), [v1, v2].concat()] {
+            assert!(matches!(
+                Subvols::new().receive_bytes(&data, false),
+                Err(Error::UnsupportedVersion(2))
+            ));
+        }
+    }
+
+    #[test]
+    fn progress() {
+        let contents = include_bytes!("../../testdata/testdata.sendstream");
+        let mut subvols = Subvols::new();
+        for sendstream in Sendstream::parse_all(contents).expect("failed to parse sendstream") {
+            let total = sendstream.commands().len();
+            let written: u64 = sendstream
+                .commands()
+                .iter()
+                .filter_map(|cmd| match cmd {
+                    Command::Write(w) => Some(w.data().as_slice().len() as u64),
+                    _ => None,
+                })
+                .sum();
+            let mut calls = Vec::new();
+            subvols
+                .receive_with_progress(sendstream, |_, progress| calls.push(*progress))
+                .expect("failed to receive");
+            assert_eq!(calls.len(), total);
+            assert!(calls
+                .iter()
+                .enumerate()
+                .all(|(i, p)| p.commands == i + 1 && p.total_commands == total));
+            assert!(calls.windows(2).all(|w| w[0].bytes <= w[1].bytes));
+            assert_eq!(calls.last().expect("at least one command").bytes, written);
+        }
+        assert_eq!(subvols.0.len(), 2);
+    }
+}
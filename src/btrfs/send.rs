@@ -0,0 +1,840 @@
+//! Serialize [Subvol]s back into the btrfs sendstream wire format. See
+//! https://btrfs.readthedocs.io/en/latest/dev/dev-send-stream.html for a
+//! description of the format.
+
+#[cfg(feature = "diff")]
+use std::collections::BTreeMap;
+#[cfg(feature = "diff")]
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+#[cfg(feature = "diff")]
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use nix::sys::stat::SFlag;
+use uuid::Uuid;
+
+use super::Subvol;
+#[cfg(feature = "diff")]
+use crate::diff::Change;
+use crate::entry::Entry;
+use crate::file::extent::Cloned;
+use crate::file::extent::Extent;
+use crate::file::File;
+use crate::Filesystem;
+use crate::InodeKey;
+
+/// Command types, copied from linux/fs/btrfs/send.h
+pub(crate) mod cmd {
+    pub(crate) const SUBVOL: u16 = 1;
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) const SNAPSHOT: u16 = 2;
+    pub(crate) const MKFILE: u16 = 3;
+    pub(crate) const MKDIR: u16 = 4;
+    pub(crate) const MKNOD: u16 = 5;
+    pub(crate) const MKFIFO: u16 = 6;
+    pub(crate) const MKSOCK: u16 = 7;
+    pub(crate) const SYMLINK: u16 = 8;
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) const RENAME: u16 = 9;
+    pub(crate) const LINK: u16 = 10;
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) const UNLINK: u16 = 11;
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) const RMDIR: u16 = 12;
+    pub(crate) const SET_XATTR: u16 = 13;
+    pub(crate) const REMOVE_XATTR: u16 = 14;
+    pub(crate) const WRITE: u16 = 15;
+    pub(crate) const CLONE: u16 = 16;
+    pub(crate) const TRUNCATE: u16 = 17;
+    pub(crate) const CHMOD: u16 = 18;
+    pub(crate) const CHOWN: u16 = 19;
+    pub(crate) const UTIMES: u16 = 20;
+    pub(crate) const END: u16 = 21;
+    #[cfg(test)]
+    pub(crate) const UPDATE_EXTENT: u16 = 22;
+}
+
+/// Attribute types, copied from linux/fs/btrfs/send.h
+pub(crate) mod attr {
+    pub(crate) const UUID: u16 = 1;
+    pub(crate) const CTRANSID: u16 = 2;
+    pub(crate) const INO: u16 = 3;
+    pub(crate) const SIZE: u16 = 4;
+    pub(crate) const MODE: u16 = 5;
+    pub(crate) const UID: u16 = 6;
+    pub(crate) const GID: u16 = 7;
+    pub(crate) const RDEV: u16 = 8;
+    pub(crate) const CTIME: u16 = 9;
+    pub(crate) const MTIME: u16 = 10;
+    pub(crate) const ATIME: u16 = 11;
+    pub(crate) const XATTR_NAME: u16 = 13;
+    pub(crate) const XATTR_DATA: u16 = 14;
+    pub(crate) const PATH: u16 = 15;
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) const PATH_TO: u16 = 16;
+    pub(crate) const LINK: u16 = 17;
+    pub(crate) const FILE_OFFSET: u16 = 18;
+    pub(crate) const DATA: u16 = 19;
+    pub(crate) const CLONE_UUID: u16 = 20;
+    pub(crate) const CLONE_CTRANSID: u16 = 21;
+    pub(crate) const CLONE_PATH: u16 = 22;
+    pub(crate) const CLONE_OFFSET: u16 = 23;
+    pub(crate) const CLONE_LEN: u16 = 24;
+}
+
+/// Attribute lengths are a u16, so large writes must be split up. This is the
+/// same limit that the kernel uses.
+const MAX_WRITE: usize = 48 * 1024;
+
+/// btrfs does not checksum the crc32c the usual way: there is no initial or
+/// final inversion.
+pub(super) fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for b in data {
+        crc ^= u32::from(*b);
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                0 => crc >> 1,
+                _ => (crc >> 1) ^ 0x82f6_3b78,
+            };
+        }
+    }
+    crc
+}
+
+fn path_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_bytes()
+}
+
+fn time_bytes(time: SystemTime) -> [u8; 12] {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut t = [0; 12];
+    t[..8].copy_from_slice(&since_epoch.as_secs().to_le_bytes());
+    t[8..].copy_from_slice(&since_epoch.subsec_nanos().to_le_bytes());
+    t
+}
+
+/// Serializes individual commands into a single sendstream.
+pub(crate) struct Encoder(Vec<u8>);
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        let mut buf = b"btrfs-stream\0".to_vec();
+        buf.extend(1u32.to_le_bytes());
+        Self(buf)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub(crate) fn command(&mut self, cmd: u16, attrs: &[(u16, &[u8])]) -> &mut Self {
+        let mut data = Vec::new();
+        for (attr, val) in attrs {
+            data.extend(attr.to_le_bytes());
+            data.extend(
+                u16::try_from(val.len())
+                    .expect("attribute too long")
+                    .to_le_bytes(),
+            );
+            data.extend(*val);
+        }
+        let start = self.0.len();
+        self.0.extend((data.len() as u32).to_le_bytes());
+        self.0.extend(cmd.to_le_bytes());
+        // the crc is computed with the crc field itself zeroed
+        self.0.extend(0u32.to_le_bytes());
+        self.0.extend(data);
+        let crc = crc32c(&self.0[start..]);
+        self.0[start + 6..start + 10].copy_from_slice(&crc.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn subvol(&mut self, path: &Path, uuid: Uuid, ctransid: u64) -> &mut Self {
+        self.command(
+            cmd::SUBVOL,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::UUID, &uuid.to_u128_le().to_le_bytes()),
+                (attr::CTRANSID, &ctransid.to_le_bytes()),
+            ],
+        )
+    }
+
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) fn snapshot(
+        &mut self,
+        path: &Path,
+        uuid: Uuid,
+        ctransid: u64,
+        clone_uuid: Uuid,
+        clone_ctransid: u64,
+    ) -> &mut Self {
+        self.command(
+            cmd::SNAPSHOT,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::UUID, &uuid.to_u128_le().to_le_bytes()),
+                (attr::CTRANSID, &ctransid.to_le_bytes()),
+                (attr::CLONE_UUID, &clone_uuid.to_u128_le().to_le_bytes()),
+                (attr::CLONE_CTRANSID, &clone_ctransid.to_le_bytes()),
+            ],
+        )
+    }
+
+    pub(crate) fn mkfile(&mut self, path: &Path, ino: u64) -> &mut Self {
+        self.command(
+            cmd::MKFILE,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::INO, &ino.to_le_bytes()),
+            ],
+        )
+    }
+
+    pub(crate) fn mkdir(&mut self, path: &Path, ino: u64) -> &mut Self {
+        self.command(
+            cmd::MKDIR,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::INO, &ino.to_le_bytes()),
+            ],
+        )
+    }
+
+    /// Create a device node, fifo or socket depending on the file type bits
+    /// in `mode`
+    pub(crate) fn mkspecial(&mut self, path: &Path, ino: u64, rdev: u64, mode: u32) -> &mut Self {
+        let file_type = SFlag::from_bits_truncate(mode & SFlag::S_IFMT.bits());
+        let cmd = match file_type {
+            SFlag::S_IFIFO => cmd::MKFIFO,
+            SFlag::S_IFSOCK => cmd::MKSOCK,
+            _ => cmd::MKNOD,
+        };
+        self.command(
+            cmd,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::INO, &ino.to_le_bytes()),
+                (attr::RDEV, &rdev.to_le_bytes()),
+                (attr::MODE, &u64::from(mode).to_le_bytes()),
+            ],
+        )
+    }
+
+    pub(crate) fn symlink(&mut self, path: &Path, ino: u64, target: &Path) -> &mut Self {
+        self.command(
+            cmd::SYMLINK,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::INO, &ino.to_le_bytes()),
+                (attr::LINK, path_bytes(target)),
+            ],
+        )
+    }
+
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) fn rename(&mut self, from: &Path, to: &Path) -> &mut Self {
+        self.command(
+            cmd::RENAME,
+            &[
+                (attr::PATH, path_bytes(from)),
+                (attr::PATH_TO, path_bytes(to)),
+            ],
+        )
+    }
+
+    pub(crate) fn link(&mut self, path: &Path, target: &Path) -> &mut Self {
+        self.command(
+            cmd::LINK,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::LINK, path_bytes(target)),
+            ],
+        )
+    }
+
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) fn unlink(&mut self, path: &Path) -> &mut Self {
+        self.command(cmd::UNLINK, &[(attr::PATH, path_bytes(path))])
+    }
+
+    #[cfg(any(test, feature = "diff"))]
+    pub(crate) fn rmdir(&mut self, path: &Path) -> &mut Self {
+        self.command(cmd::RMDIR, &[(attr::PATH, path_bytes(path))])
+    }
+
+    pub(crate) fn set_xattr(&mut self, path: &Path, name: &[u8], data: &[u8]) -> &mut Self {
+        self.command(
+            cmd::SET_XATTR,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::XATTR_NAME, name),
+                (attr::XATTR_DATA, data),
+            ],
+        )
+    }
+
+    pub(crate) fn remove_xattr(&mut self, path: &Path, name: &[u8]) -> &mut Self {
+        self.command(
+            cmd::REMOVE_XATTR,
+            &[(attr::PATH, path_bytes(path)), (attr::XATTR_NAME, name)],
+        )
+    }
+
+    /// Write `data` at `offset`, splitting it into multiple commands if
+    /// necessary
+    pub(crate) fn write(&mut self, path: &Path, offset: u64, data: &[u8]) -> &mut Self {
+        for (i, chunk) in data.chunks(MAX_WRITE).enumerate() {
+            let offset = offset + (i * MAX_WRITE) as u64;
+            self.command(
+                cmd::WRITE,
+                &[
+                    (attr::PATH, path_bytes(path)),
+                    (attr::FILE_OFFSET, &offset.to_le_bytes()),
+                    (attr::DATA, chunk),
+                ],
+            );
+        }
+        self
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn clone(
+        &mut self,
+        dst_path: &Path,
+        dst_offset: u64,
+        len: u64,
+        src_uuid: Uuid,
+        src_ctransid: u64,
+        src_path: &Path,
+        src_offset: u64,
+    ) -> &mut Self {
+        self.command(
+            cmd::CLONE,
+            &[
+                (attr::FILE_OFFSET, &dst_offset.to_le_bytes()),
+                (attr::CLONE_LEN, &len.to_le_bytes()),
+                (attr::PATH, path_bytes(dst_path)),
+                (attr::CLONE_UUID, &src_uuid.to_u128_le().to_le_bytes()),
+                (attr::CLONE_CTRANSID, &src_ctransid.to_le_bytes()),
+                (attr::CLONE_PATH, path_bytes(src_path)),
+                (attr::CLONE_OFFSET, &src_offset.to_le_bytes()),
+            ],
+        )
+    }
+
+    pub(crate) fn truncate(&mut self, path: &Path, size: u64) -> &mut Self {
+        self.command(
+            cmd::TRUNCATE,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::SIZE, &size.to_le_bytes()),
+            ],
+        )
+    }
+
+    pub(crate) fn chmod(&mut self, path: &Path, mode: u32) -> &mut Self {
+        self.command(
+            cmd::CHMOD,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::MODE, &u64::from(mode).to_le_bytes()),
+            ],
+        )
+    }
+
+    pub(crate) fn chown(&mut self, path: &Path, uid: u32, gid: u32) -> &mut Self {
+        self.command(
+            cmd::CHOWN,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::UID, &u64::from(uid).to_le_bytes()),
+                (attr::GID, &u64::from(gid).to_le_bytes()),
+            ],
+        )
+    }
+
+    pub(crate) fn utimes(
+        &mut self,
+        path: &Path,
+        atime: SystemTime,
+        mtime: SystemTime,
+        ctime: SystemTime,
+    ) -> &mut Self {
+        self.command(
+            cmd::UTIMES,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::ATIME, &time_bytes(atime)),
+                (attr::MTIME, &time_bytes(mtime)),
+                (attr::CTIME, &time_bytes(ctime)),
+            ],
+        )
+    }
+
+    #[cfg(test)]
+    pub(crate) fn update_extent(&mut self, path: &Path, offset: u64, len: u64) -> &mut Self {
+        self.command(
+            cmd::UPDATE_EXTENT,
+            &[
+                (attr::PATH, path_bytes(path)),
+                (attr::FILE_OFFSET, &offset.to_le_bytes()),
+                (attr::SIZE, &len.to_le_bytes()),
+            ],
+        )
+    }
+
+    pub(crate) fn end(&mut self) -> &mut Self {
+        self.command(cmd::END, &[])
+    }
+}
+
+/// Create a single entry without any data or metadata
+fn send_create(enc: &mut Encoder, path: &Path, ino: u64, entry: &Entry) {
+    match entry {
+        Entry::Directory(_) => {
+            enc.mkdir(path, ino);
+        }
+        Entry::File(_) => {
+            enc.mkfile(path, ino);
+        }
+        Entry::Special(s) => {
+            enc.mkspecial(
+                path,
+                ino,
+                s.rdev().unwrap_or(0),
+                s.file_type().bits() | s.metadata().mode().bits(),
+            );
+        }
+        Entry::Symlink(s) => {
+            enc.symlink(path, ino, s.target());
+        }
+    }
+}
+
+/// Check if `base` already has exactly the same bytes as `ext` would have
+/// if it were written at `offset`
+fn unchanged(base: &File, offset: u64, ext: &Extent) -> bool {
+    if offset + ext.len() > base.len() {
+        return false;
+    }
+    let mut buf = vec![0; ext.len() as usize];
+    base.read_at(&mut buf, offset);
+    match ext {
+        Extent::Hole(_) => buf.iter().all(|b| *b == 0),
+        _ => buf == ext.bytes(),
+    }
+}
+
+/// Find a file in `fs` that a [Cloned] extent could be cloned from
+fn find_clone_source<'f>(fs: &'f Filesystem, cloned: &Cloned) -> Option<&'f Path> {
+    fs.iter().find_map(|(path, entry)| match entry {
+        Entry::File(f) if f == cloned.src_file() => Some(path),
+        _ => None,
+    })
+}
+
+/// Send the contents of `f`, assuming that the receiver already has a file
+/// with the contents of `base` at `path`. Only the byte ranges that differ
+/// from `base` are sent, and data that was cloned from a file in
+/// `clone_src` is sent as a clone instead of a write.
+fn send_file_data(
+    enc: &mut Encoder,
+    path: &Path,
+    f: &File,
+    base: Option<&File>,
+    clone_src: Option<(Uuid, &Filesystem)>,
+) {
+    let base_len = base.map_or(0, File::len);
+    let mut len = base_len;
+    for (offset, ext) in &f.extents {
+        let offset = *offset;
+        if base.is_some_and(|base| unchanged(base, offset, ext)) {
+            continue;
+        }
+        let end = offset + ext.len();
+        match ext {
+            Extent::Hole(_) => {
+                // holes past the end of the old contents are created by a
+                // later write or truncate, but old data must be zeroed
+                if offset < base_len {
+                    let zeroed = std::cmp::min(end, base_len) - offset;
+                    enc.write(path, offset, &vec![0; zeroed as usize]);
+                }
+                continue;
+            }
+            Extent::Cloned(c) => match clone_src
+                .and_then(|(uuid, fs)| find_clone_source(fs, c).map(|src| (uuid, src)))
+            {
+                Some((src_uuid, src_path)) => {
+                    enc.clone(
+                        path,
+                        offset,
+                        ext.len(),
+                        src_uuid,
+                        0,
+                        src_path,
+                        c.src_range().0,
+                    );
+                }
+                None => {
+                    enc.write(path, offset, ext.data());
+                }
+            },
+            _ => {
+                enc.write(path, offset, &ext.bytes());
+            }
+        }
+        len = std::cmp::max(len, end);
+    }
+    if len != f.len() {
+        enc.truncate(path, f.len());
+    }
+}
+
+/// Send all the metadata of an entry. If `old` is given, xattrs that only
+/// exist on `old` are removed and unchanged xattrs are not re-sent.
+fn send_metadata(enc: &mut Encoder, path: &Path, entry: &Entry, old: Option<&Entry>) {
+    let metadata = entry.metadata();
+    if let Some(old) = old {
+        for name in old.metadata().xattrs().keys() {
+            if !metadata.xattrs().contains_key(name) {
+                enc.remove_xattr(path, name);
+            }
+        }
+    }
+    for (name, value) in metadata.xattrs() {
+        if old.and_then(|old| old.metadata().xattrs().get(name)) != Some(value) {
+            enc.set_xattr(path, name, value);
+        }
+    }
+    enc.chown(path, metadata.uid().as_u32(), metadata.gid().as_u32());
+    if !entry.is_symlink() {
+        enc.chmod(path, metadata.mode().bits());
+    }
+    enc.utimes(
+        path,
+        metadata.accessed(),
+        metadata.modified(),
+        metadata.created(),
+    );
+}
+
+/// Where `path` ended up after applying all of `renames` in order
+#[cfg(feature = "diff")]
+fn renamed_path(renames: &[(PathBuf, PathBuf)], path: &Path) -> PathBuf {
+    let mut path = path.to_owned();
+    for (from, to) in renames {
+        if let Ok(rel) = path.strip_prefix(from) {
+            path = match rel.as_os_str().is_empty() {
+                true => to.clone(),
+                false => to.join(rel),
+            };
+        }
+    }
+    path
+}
+
+impl Subvol {
+    /// Serialize this subvolume as a full (non-incremental) sendstream that
+    /// will be received with the given uuid.
+    pub fn send(&self, uuid: Uuid) -> Vec<u8> {
+        let mut enc = Encoder::new();
+        enc.subvol(Path::new(&uuid.to_string()), uuid, 0);
+        self.send_entries(&mut enc);
+        enc.end();
+        enc.into_bytes()
+    }
+
+    fn send_entries(&self, enc: &mut Encoder) {
+        // btrfs hands out inode numbers starting after the root dir (256)
+        let mut ino = 256;
+        let mut first_paths: HashMap<InodeKey, &Path> = HashMap::new();
+        for (path, key) in &self.fs.paths {
+            let path = path.as_path();
+            if let Some(target) = first_paths.get(key) {
+                enc.link(path, target);
+                continue;
+            }
+            first_paths.insert(*key, path);
+            ino += 1;
+            let entry = &self.fs.inodes[*key];
+            // the top-level directory is created by the subvol itself
+            if !path.as_os_str().is_empty() {
+                send_create(enc, path, ino, entry);
+            }
+            if let Entry::File(f) = entry {
+                send_file_data(enc, path, f, None, None);
+            }
+        }
+        // metadata is sent after all the entries have been created, otherwise
+        // creating children would change the times of their parent
+        for (path, key) in &self.fs.paths {
+            let path = path.as_path();
+            if first_paths.get(key) == Some(&path) {
+                send_metadata(enc, path, &self.fs.inodes[*key], None);
+            }
+        }
+    }
+
+    /// Serialize this subvolume as an incremental sendstream against
+    /// `parent` (like `btrfs send -p`). The stream only contains the
+    /// commands needed to turn a snapshot of `parent` into this subvolume,
+    /// so it can only be received once `parent` was received as
+    /// `parent_uuid`.
+    #[cfg(feature = "diff")]
+    pub fn send_incremental(&self, uuid: Uuid, parent_uuid: Uuid, parent: &Subvol) -> Vec<u8> {
+        let mut enc = Encoder::new();
+        enc.snapshot(Path::new(&uuid.to_string()), uuid, 0, parent_uuid, 0);
+
+        let mut removed_dirs = BTreeSet::new();
+        // destination path -> source path of renames, or None for new entries
+        let mut created: BTreeMap<&Path, Option<&Path>> = BTreeMap::new();
+        let mut contents_changed = Vec::new();
+        let mut metadata: BTreeMap<&Path, Option<&Entry>> = BTreeMap::new();
+        let changes = parent.fs.diff(&self.fs);
+        for change in &changes {
+            match change {
+                Change::Added(path) => {
+                    created.insert(path, None);
+                }
+                Change::Removed(path) => {
+                    if parent.fs.get(path).is_ok_and(Entry::is_directory) {
+                        removed_dirs.insert(path.as_path());
+                    } else {
+                        // nothing can still be inside of a non-directory
+                        enc.unlink(path);
+                    }
+                }
+                Change::Renamed { from, to } => {
+                    created.insert(to, Some(from));
+                }
+                Change::ContentsChanged(path) => contents_changed.push(path.as_path()),
+                Change::MetadataChanged(path) => {
+                    metadata.insert(path, parent.fs.get(path).ok());
+                }
+            }
+        }
+
+        // inode numbers only need to be unique, they are not otherwise used
+        let mut ino = 256 + parent.fs.inodes.len() as u64;
+        let mut renames: Vec<(PathBuf, PathBuf)> = Vec::new();
+        // a removed directory may still contain entries that are about to be
+        // moved out of it, so it cannot be removed yet, but it may be in the
+        // way of a new entry so move it out of the way first
+        for dir in &removed_dirs {
+            if created.contains_key(dir) && !renames.iter().any(|(from, _)| dir.starts_with(from)) {
+                let orphan = loop {
+                    ino += 1;
+                    let orphan = PathBuf::from(format!("o{ino}-0-0"));
+                    if parent.fs.get(&orphan).is_err() && self.fs.get(&orphan).is_err() {
+                        break orphan;
+                    }
+                };
+                enc.rename(dir, &orphan);
+                renames.push((dir.to_path_buf(), orphan));
+            }
+        }
+
+        // any path that exists in both subvols is already in place, so can be
+        // the target of a hardlink
+        let mut first_paths: HashMap<InodeKey, &Path> = HashMap::new();
+        for (path, key) in &self.fs.paths {
+            if !created.contains_key(path.as_path()) && parent.fs.get(path).is_ok() {
+                first_paths.entry(*key).or_insert(path);
+            }
+        }
+        // destinations are sorted, so parent directories are always created
+        // (or moved into place) before any of their children
+        for (path, from) in &created {
+            let key = self.fs.paths[*path];
+            match from {
+                Some(from) => {
+                    let current = renamed_path(&renames, from);
+                    // already moved along with a renamed directory
+                    if current != *path {
+                        enc.rename(&current, path);
+                        renames.push((current, path.to_path_buf()));
+                    }
+                    first_paths.entry(key).or_insert(path);
+                }
+                None => match first_paths.get(&key) {
+                    Some(target) => {
+                        enc.link(path, target);
+                    }
+                    None => {
+                        first_paths.insert(key, path);
+                        ino += 1;
+                        let entry = &self.fs.inodes[key];
+                        send_create(&mut enc, path, ino, entry);
+                        if let Entry::File(f) = entry {
+                            send_file_data(
+                                &mut enc,
+                                path,
+                                f,
+                                None,
+                                Some((parent_uuid, &parent.fs)),
+                            );
+                        }
+                        metadata.insert(path, None);
+                    }
+                },
+            }
+        }
+
+        for dir in removed_dirs.iter().rev() {
+            enc.rmdir(&renamed_path(&renames, dir));
+        }
+
+        for path in contents_changed {
+            let entry = self.fs.get(path).expect("changed paths exist");
+            match (entry, parent.fs.get(path).expect("changed paths exist")) {
+                (Entry::File(f), Entry::File(base)) => {
+                    send_file_data(
+                        &mut enc,
+                        path,
+                        f,
+                        Some(base),
+                        Some((parent_uuid, &parent.fs)),
+                    );
+                }
+                // symlinks and special files cannot be changed in place
+                _ => {
+                    enc.unlink(path);
+                    ino += 1;
+                    send_create(&mut enc, path, ino, entry);
+                    metadata.insert(path, None);
+                }
+            }
+        }
+
+        for (path, old) in metadata {
+            let entry = self.fs.get(path).expect("changed paths exist");
+            send_metadata(&mut enc, path, entry, old);
+        }
+        enc.end();
+        enc.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use pretty_assertions::assert_eq;
+    #[cfg(feature = "diff")]
+    use sendstream_parser::Command;
+    use sendstream_parser::Sendstream;
+
+    use super::*;
+    #[cfg(feature = "diff")]
+    use crate::btrfs::tests::StreamBuilder;
+    use crate::btrfs::Subvols;
+
+    #[test]
+    fn crc() {
+        // first command of the test sendstream
+        let contents = include_bytes!("../../testdata/testdata.sendstream");
+        let len = u32::from_le_bytes(contents[17..21].try_into().unwrap()) as usize;
+        let expected = u32::from_le_bytes(contents[23..27].try_into().unwrap());
+        let mut cmd = contents[17..27 + len].to_vec();
+        cmd[6..10].copy_from_slice(&[0; 4]);
+        assert_eq!(crc32c(&cmd), expected);
+    }
+
+    #[test]
+    fn send_round_trip() {
+        let contents = Bytes::from_static(include_bytes!("../../testdata/testdata.sendstream"));
+        let mut subvols = Subvols::new();
+        for sendstream in Sendstream::parse_all(&contents).expect("failed to parse") {
+            subvols.receive(sendstream).expect("failed to receive");
+        }
+        for (uuid, subvol) in &subvols.0 {
+            let sent = subvol.send(*uuid);
+            let mut resent = Subvols::new();
+            for sendstream in Sendstream::parse_all(&sent).expect("failed to parse") {
+                resent.receive(sendstream).expect("failed to receive");
+            }
+            assert_eq!(subvol.fs, resent.0[uuid].fs);
+        }
+    }
+
+    #[cfg(feature = "diff")]
+    #[test]
+    fn send_incremental() {
+        let parent_uuid = Uuid::from_u128(1);
+        let child_uuid = Uuid::from_u128(2);
+        let mut subvols = Subvols::new();
+        StreamBuilder::subvol(parent_uuid)
+            .mkfile("lorem.txt")
+            .write("lorem.txt", 0, "Lorem ipsum dolor sit amet\n")
+            .mkfile("changed.txt")
+            .write("changed.txt", 0, "Lorem ipsum\n")
+            .mkfile("removed.txt")
+            .mkdir("olddir")
+            .mkfile("olddir/moved.txt")
+            .mkdir("replaced")
+            .mkfile("replaced/lorem.txt")
+            .receive_into(&mut subvols)
+            .expect("failed to receive parent");
+        StreamBuilder::snapshot(child_uuid, parent_uuid)
+            .write("changed.txt", 6, "IPSUM")
+            .unlink("removed.txt")
+            .rename("olddir", "newdir")
+            .unlink("replaced/lorem.txt")
+            .rmdir("replaced")
+            .mkfile("replaced")
+            .mkfile("ipsum.txt")
+            .clone(parent_uuid, "lorem.txt", 6, 5, "ipsum.txt", 0)
+            .chown("newdir", 1000, 1000)
+            .receive_into(&mut subvols)
+            .expect("failed to receive child");
+
+        let sent = subvols.0[&child_uuid].send_incremental(
+            child_uuid,
+            parent_uuid,
+            &subvols.0[&parent_uuid],
+        );
+        let commands: Vec<_> = Sendstream::parse_all(&sent)
+            .expect("failed to parse")
+            .into_iter()
+            .flat_map(Sendstream::into_commands)
+            .collect();
+        // only the changed range is written, the new file is a clone
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|c| matches!(c, Command::Write(_)))
+                .count(),
+            1,
+            "{commands:#?}",
+        );
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|c| matches!(c, Command::Clone(_)))
+                .count(),
+            1,
+            "{commands:#?}",
+        );
+
+        let mut received = Subvols::new();
+        let parent_sent = subvols.0[&parent_uuid].send(parent_uuid);
+        for sent in [&parent_sent, &sent] {
+            for sendstream in Sendstream::parse_all(sent).expect("failed to parse") {
+                received.receive(sendstream).expect("failed to receive");
+            }
+        }
+        assert_eq!(subvols.0[&child_uuid].fs, received.0[&child_uuid].fs);
+    }
+}
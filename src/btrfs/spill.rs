@@ -0,0 +1,90 @@
+use std::io::Result;
+use std::os::unix::fs::FileExt;
+
+use bytes::Bytes;
+use memmap::MmapOptions;
+
+/// File data is spilled into chunks of (at least) this size, each of which is
+/// mapped into memory once.
+pub const SPILL_CHUNK_SIZE: u64 = 64 << 20;
+
+/// Backing storage for [super::Subvols::receive_streaming]. Instead of
+/// holding the contents of every write in memory, they are written to a file
+/// and the resulting [Bytes] point into a read-only mmap of that file, so the
+/// kernel can page the data in and out as needed.
+/// The file must be opened for reading and writing, and must not be modified
+/// by anything else while any of the received [crate::Filesystem]s are alive.
+/// An anonymous temporary file (like `tempfile::tempfile()`) is ideal.
+pub struct Spill {
+    file: std::fs::File,
+    /// The chunk that is currently being filled, and how much of it is used
+    chunk: Option<(Bytes, usize)>,
+    /// Total size of all the chunks so far
+    file_len: u64,
+    /// Bytes of data actually stored
+    len: u64,
+}
+
+impl Spill {
+    pub fn new(file: std::fs::File) -> Self {
+        Self {
+            file,
+            chunk: None,
+            file_len: 0,
+            len: 0,
+        }
+    }
+
+    /// Total number of bytes of file data that have been spilled
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy `data` into the spill file, returning a zero-copy view of it.
+    pub(super) fn store(&mut self, data: &[u8]) -> Result<Bytes> {
+        if data.is_empty() {
+            return Ok(Bytes::new());
+        }
+        let fits = matches!(&self.chunk, Some((map, used)) if map.len() - used >= data.len());
+        if !fits {
+            self.new_chunk(data.len() as u64)?;
+        }
+        let (map, used) = self.chunk.as_mut().expect("chunk was just created");
+        let chunk_start = self.file_len - map.len() as u64;
+        self.file.write_all_at(data, chunk_start + *used as u64)?;
+        let stored = map.slice(*used..*used + data.len());
+        *used += data.len();
+        self.len += data.len() as u64;
+        Ok(stored)
+    }
+
+    fn new_chunk(&mut self, min_len: u64) -> Result<()> {
+        let len = std::cmp::max(SPILL_CHUNK_SIZE, min_len.next_multiple_of(4096));
+        let start = self.file_len;
+        self.file.set_len(start + len)?;
+        // Safety: the mapped range is only ever written to by [Spill::store],
+        // and only before any [Bytes] that refer to that part of it exist.
+        let map = unsafe {
+            MmapOptions::new()
+                .offset(start)
+                .len(len as usize)
+                .map(&self.file)?
+        };
+        self.file_len = start + len;
+        self.chunk = Some((Bytes::from_owner(map), 0));
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Spill {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Spill")
+            .field("file", &self.file)
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
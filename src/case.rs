@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::entry::Entry;
+use crate::path::normalize;
+use crate::Filesystem;
+
+/// Case-insensitive lookups, for filesystems that will end up on a target
+/// that does not distinguish between paths that only differ by case. Only
+/// ASCII letters are folded, since paths are not necessarily utf8.
+impl Filesystem {
+    /// Find the path of the entry at `path`, ignoring case. An exact match is
+    /// always preferred, otherwise it is an error if more than one path
+    /// matches. The returned path can then be used with the regular
+    /// (case-sensitive) methods like [Filesystem::rename] or
+    /// [Filesystem::unlink].
+    pub fn resolve_ci<P>(&self, path: P) -> Result<&Path>
+    where
+        P: AsRef<Path>,
+    {
+        let normalized = normalize(path.as_ref());
+        if let Some((found, _)) = self.paths.get_key_value(&*normalized) {
+            return Ok(found);
+        }
+        let wanted = normalized.as_os_str().as_bytes();
+        let mut matches = self
+            .paths
+            .keys()
+            .filter(|p| p.as_os_str().as_bytes().eq_ignore_ascii_case(wanted));
+        match (matches.next(), matches.next()) {
+            (Some(found), None) => Ok(found),
+            (Some(a), Some(b)) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "'{}' is ambiguous, it matches both '{}' and '{}'",
+                    path.as_ref().display(),
+                    a.display(),
+                    b.display()
+                ),
+            )),
+            (None, _) => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("'{}' not found", path.as_ref().display()),
+            )),
+        }
+    }
+
+    /// Like [Filesystem::get], but ignoring case. See [Filesystem::resolve_ci].
+    pub fn get_ci<P>(&self, path: P) -> Result<&Entry>
+    where
+        P: AsRef<Path>,
+    {
+        let path = self.resolve_ci(path)?;
+        self.get(path)
+    }
+
+    /// Groups of paths that only differ by case, and so could not coexist on
+    /// a case-insensitive filesystem.
+    pub fn case_collisions(&self) -> Vec<Vec<&Path>> {
+        let mut folded: BTreeMap<Vec<u8>, Vec<&Path>> = BTreeMap::new();
+        for path in self.paths.keys() {
+            folded
+                .entry(path.as_os_str().as_bytes().to_ascii_lowercase())
+                .or_default()
+                .push(path);
+        }
+        folded.into_values().filter(|p| p.len() > 1).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+    use crate::File;
+
+    #[test]
+    fn get_ci() {
+        let fs = demo_fs();
+        assert_eq!(
+            fs.resolve_ci("/TestData/Dir/LOREM.txt").unwrap(),
+            Path::new("testdata/dir/lorem.txt"),
+        );
+        assert_eq!(
+            fs.get_ci("TESTDATA/LOREM.TXT").unwrap(),
+            fs.get("testdata/lorem.txt").unwrap(),
+        );
+        assert_eq!(
+            fs.get_ci("testdata/ipsum.txt").unwrap_err().kind(),
+            ErrorKind::NotFound,
+        );
+        assert!(fs.case_collisions().is_empty());
+    }
+
+    #[test]
+    fn collisions() {
+        let mut fs = demo_fs();
+        fs.insert("testdata/Lorem.txt", File::new_empty());
+        fs.insert("testdata/LOREM.TXT", File::new_empty());
+        // exact matches are never ambiguous
+        assert!(fs.get_ci("testdata/Lorem.txt").is_ok());
+        assert_eq!(
+            fs.get_ci("testdata/lorem.TXT").unwrap_err().kind(),
+            ErrorKind::InvalidInput,
+        );
+        assert_eq!(
+            fs.case_collisions(),
+            vec![vec![
+                Path::new("testdata/LOREM.TXT"),
+                Path::new("testdata/Lorem.txt"),
+                Path::new("testdata/lorem.txt"),
+            ]],
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::entry::Entry;
+use crate::file::extent::Extent;
+use crate::Filesystem;
+use crate::InodeKey;
+
+impl Filesystem {
+    /// Make all files with identical contents share the same underlying
+    /// data. Only the extents are shared, so each file keeps its own
+    /// metadata. Returns the number of bytes of file data that are no longer
+    /// held in memory separately.
+    pub fn deduplicate(&mut self) -> u64 {
+        let mut by_hash: HashMap<[u8; 32], Vec<InodeKey>> = HashMap::new();
+        for (key, entry) in &self.inodes {
+            if let Entry::File(f) = entry {
+                if !f.is_empty() {
+                    by_hash.entry(f.content_hash()).or_default().push(key);
+                }
+            }
+        }
+        let mut saved = 0;
+        for keys in by_hash.into_values() {
+            let (canonical, duplicates) = keys.split_first().expect("groups are never empty");
+            let extents = match &self.inodes[*canonical] {
+                Entry::File(f) => f.extents.clone(),
+                _ => unreachable!("only files are grouped"),
+            };
+            let shared: HashSet<*const u8> = extents.values().map(|e| e.data().as_ptr()).collect();
+            for key in duplicates {
+                let f = match &mut self.inodes[*key] {
+                    Entry::File(f) => f,
+                    _ => unreachable!("only files are grouped"),
+                };
+                saved += f
+                    .extents
+                    .values()
+                    .map(Extent::data)
+                    .filter(|d| !shared.contains(&d.as_ptr()))
+                    .map(|d| d.len() as u64)
+                    .sum::<u64>();
+                f.extents = extents.clone();
+            }
+        }
+        saved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::stat::Mode;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+    use crate::File;
+
+    #[test]
+    fn deduplicate() {
+        let mut fs = demo_fs();
+        for (path, mode) in [
+            ("testdata/a", 0o644),
+            ("testdata/b", 0o600),
+            ("testdata/c", 0o755),
+        ] {
+            let mut f = File::builder()
+                .contents(b"Lorem ipsum dolor sit amet\n".to_vec())
+                .build();
+            f.set_mode(Mode::from_bits_truncate(mode));
+            fs.insert(path, f);
+        }
+        let expected = fs.clone();
+        // demo_fs has its own copy of the same contents in testdata/dir
+        assert_eq!(
+            fs.deduplicate(),
+            3 * "Lorem ipsum dolor sit amet\n".len() as u64
+        );
+        assert_eq!(fs, expected);
+        let data: HashSet<_> = [
+            "testdata/dir/lorem.txt",
+            "testdata/a",
+            "testdata/b",
+            "testdata/c",
+        ]
+        .into_iter()
+        .map(|path| {
+            let f = fs.get_file(path).unwrap();
+            assert_eq!(f.extents.len(), 1);
+            f.extents[&0].data().as_ptr()
+        })
+        .collect();
+        assert_eq!(data.len(), 1, "all copies share the same data");
+        assert_eq!(
+            fs.get_file("testdata/b").unwrap().mode(),
+            Mode::from_bits_truncate(0o600),
+        );
+        assert_eq!(fs.deduplicate(), 0, "already deduplicated");
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt::Write;
+
+use nix::sys::stat::major;
+use nix::sys::stat::minor;
+use nix::sys::stat::Mode;
+use nix::sys::stat::SFlag;
+
+use crate::entry::Entry;
+use crate::size::apparent_size;
+use crate::Filesystem;
+
+impl Filesystem {
+    /// Render an `ls -l`-style listing of every entry, one per line in path
+    /// order: permissions, link count, uid, gid, size (or major, minor for
+    /// device nodes) and the absolute path. Much easier to read in test
+    /// failures than the [std::fmt::Debug] output.
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+        for (path, key) in &self.paths {
+            let entry = &self.inodes[*key];
+            let metadata = entry.metadata();
+            let size = match entry {
+                Entry::Special(s) if s.rdev().is_some() => {
+                    let rdev = s.rdev().expect("checked above");
+                    format!("{}, {}", major(rdev), minor(rdev))
+                }
+                _ => apparent_size(entry).to_string(),
+            };
+            write!(
+                out,
+                "{} {} {} {} {:>8} /{}",
+                permissions(entry),
+                self.refcounts[*key],
+                metadata.uid().as_u32(),
+                metadata.gid().as_u32(),
+                size,
+                path.display(),
+            )
+            .expect("infallible");
+            if let Entry::Symlink(s) = entry {
+                write!(out, " -> {}", s.target().display()).expect("infallible");
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Permission string like "drwxr-xr-x"
+fn permissions(entry: &Entry) -> String {
+    let type_char = match entry {
+        Entry::Directory(_) => 'd',
+        Entry::File(_) => '-',
+        Entry::Special(s) => match s.file_type() {
+            SFlag::S_IFCHR => 'c',
+            SFlag::S_IFBLK => 'b',
+            SFlag::S_IFIFO => 'p',
+            SFlag::S_IFSOCK => 's',
+            _ => '?',
+        },
+        Entry::Symlink(_) => 'l',
+    };
+    let mode = entry.metadata().mode();
+    let bit = |m: Mode, c: char| if mode.contains(m) { c } else { '-' };
+    let exec = |x: Mode, special: Mode, set: char| match (mode.contains(x), mode.contains(special))
+    {
+        (true, true) => set,
+        (false, true) => set.to_ascii_uppercase(),
+        (true, false) => 'x',
+        (false, false) => '-',
+    };
+    [
+        type_char,
+        bit(Mode::S_IRUSR, 'r'),
+        bit(Mode::S_IWUSR, 'w'),
+        exec(Mode::S_IXUSR, Mode::S_ISUID, 's'),
+        bit(Mode::S_IRGRP, 'r'),
+        bit(Mode::S_IWGRP, 'w'),
+        exec(Mode::S_IXGRP, Mode::S_ISGID, 's'),
+        bit(Mode::S_IROTH, 'r'),
+        bit(Mode::S_IWOTH, 'w'),
+        exec(Mode::S_IXOTH, Mode::S_ISVTX, 't'),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::stat::makedev;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::entry::Metadata;
+    use crate::entry::Special;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn describe() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        fs.insert(
+            "testdata/null",
+            Special::new(
+                SFlag::S_IFCHR,
+                makedev(1, 3),
+                Metadata::builder()
+                    .mode(Mode::from_bits_truncate(0o666))
+                    .build(),
+            ),
+        );
+        fs.chmod("testdata/dir", Mode::from_bits_truncate(0o1777))
+            .expect("missing dir");
+        assert_eq!(
+            fs.describe(),
+            "\
+drwxr-xr-x 1 0 0     4096 /
+drwxr-xr-x 1 0 0     4096 /testdata
+drwxrwxrwt 1 0 0     4096 /testdata/dir
+-rw-r--r-- 1 0 0       27 /testdata/dir/lorem.txt
+lrwxrwxrwx 1 0 0       12 /testdata/dir/symlink -> ../lorem.txt
+-rw-r--r-- 2 0 0       12 /testdata/hardlink.txt
+-rw-r--r-- 2 0 0       12 /testdata/lorem.txt
+crw-rw-rw- 1 0 0     1, 3 /testdata/null
+"
+        );
+    }
+
+    #[test]
+    fn special_bits() {
+        let mut fs = Filesystem::new();
+        fs.insert(
+            "suid",
+            crate::File::builder()
+                .metadata(
+                    Metadata::builder()
+                        .mode(Mode::from_bits_truncate(0o6744))
+                        .build(),
+                )
+                .build(),
+        );
+        assert!(fs.describe().starts_with("-rwsr-Sr-- "));
+    }
+}
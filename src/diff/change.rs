@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::cmp::ApproxEq;
+use crate::cmp::Fields;
+use crate::Filesystem;
+
+/// A single difference between two [Filesystem]s, as produced by
+/// [Filesystem::diff].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Path only exists in the new filesystem
+    Added(PathBuf),
+    /// Path only exists in the old filesystem
+    Removed(PathBuf),
+    /// An identical entry was moved to a different path
+    Renamed { from: PathBuf, to: PathBuf },
+    /// File data (or symlink target, device number etc) is different
+    ContentsChanged(PathBuf),
+    /// Some of the metadata (mode, owner, xattrs, times) is different
+    MetadataChanged(PathBuf),
+}
+
+impl Change {
+    /// Metadata fields that are reported as [Change::MetadataChanged]
+    fn metadata_fields() -> Fields {
+        Fields::MODE | Fields::OWNER | Fields::XATTR | Fields::TIME
+    }
+}
+
+impl Filesystem {
+    /// Compute the set of [Change]s required to turn `self` into `other`.
+    /// An entry that changes type is reported as being removed and re-added.
+    /// Renames are detected by pairing up removed and added paths that have
+    /// exactly equal entries.
+    pub fn diff(&self, other: &Filesystem) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let mut removed: BTreeSet<&Path> = BTreeSet::new();
+        let mut added: BTreeSet<&Path> = BTreeSet::new();
+        for (path, entry) in self {
+            match other.get(path) {
+                Ok(other_entry) => {
+                    let f = entry.cmp(other_entry);
+                    if !f.contains(Fields::TYPE) {
+                        removed.insert(path);
+                        added.insert(path);
+                        continue;
+                    }
+                    if !f.contains(Fields::DATA | Fields::RDEV) {
+                        changes.push(Change::ContentsChanged(path.to_owned()));
+                    }
+                    if !f.contains(Change::metadata_fields()) {
+                        changes.push(Change::MetadataChanged(path.to_owned()));
+                    }
+                }
+                Err(_) => {
+                    removed.insert(path);
+                }
+            }
+        }
+        for (path, _) in other {
+            if self.get(path).is_err() {
+                added.insert(path);
+            }
+        }
+        for from in removed {
+            let from_entry = self.get(from).expect("removed paths came from self");
+            let renamed_to = added
+                .iter()
+                .find(|to| **to != from && other.get(to).ok() == Some(from_entry))
+                .copied();
+            match renamed_to {
+                Some(to) => {
+                    added.remove(to);
+                    changes.push(Change::Renamed {
+                        from: from.to_owned(),
+                        to: to.to_owned(),
+                    });
+                }
+                None => changes.push(Change::Removed(from.to_owned())),
+            }
+        }
+        changes.extend(added.into_iter().map(|p| Change::Added(p.to_owned())));
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::stat::Mode;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::entry::Symlink;
+    use crate::tests::demo_fs;
+    use crate::File;
+
+    #[test]
+    fn no_changes() {
+        assert_eq!(demo_fs().diff(&demo_fs()), vec![]);
+    }
+
+    #[test]
+    fn changes() {
+        let old = demo_fs();
+        let mut new = demo_fs();
+        new.insert("testdata/new.txt", File::builder().contents("new").build());
+        new.unlink("testdata/dir/lorem.txt").unwrap();
+        new.insert(
+            "testdata/lorem.txt",
+            File::builder()
+                .contents("consectetur adipiscing elit\n")
+                .metadata(old.get("testdata/lorem.txt").unwrap().metadata().clone())
+                .build(),
+        );
+        new.chmod("testdata/dir", Mode::from_bits_truncate(0o700))
+            .unwrap();
+        new.rename("testdata/dir/symlink", "testdata/symlink")
+            .unwrap();
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                Change::MetadataChanged("testdata/dir".into()),
+                Change::ContentsChanged("testdata/lorem.txt".into()),
+                Change::Removed("testdata/dir/lorem.txt".into()),
+                Change::Renamed {
+                    from: "testdata/dir/symlink".into(),
+                    to: "testdata/symlink".into(),
+                },
+                Change::Added("testdata/new.txt".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn type_change() {
+        let old = demo_fs();
+        let mut new = demo_fs();
+        new.insert("testdata/lorem.txt", Symlink::new("dir/lorem.txt", None));
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                Change::Removed("testdata/lorem.txt".into()),
+                Change::Added("testdata/lorem.txt".into()),
+            ],
+        );
+    }
+}
@@ -13,7 +13,9 @@ use crate::cmp::Fields;
 use crate::entry::Entry;
 use crate::Filesystem;
 
+mod change;
 mod diffable;
+pub use change::Change;
 use diffable::Diffable;
 
 #[derive(Debug)]
@@ -0,0 +1,879 @@
+//! Loading a [Filesystem] from a directory on the host and writing it back
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use nix::sys::stat::SFlag;
+
+use crate::cmp::ApproxEq;
+use crate::cmp::Fields;
+use crate::entry::Directory;
+use crate::entry::Entry;
+use crate::entry::Metadata;
+use crate::entry::Special;
+use crate::entry::Symlink;
+use crate::file::extent::Extent;
+use crate::File;
+use crate::Filesystem;
+use crate::InodeKey;
+use crate::XattrError;
+use crate::XattrFilter;
+
+/// A reasonable [FromDirOptions::mmap_threshold] for callers that opt in to
+/// memory-mapping: smaller files are cheaper to just read into memory.
+#[cfg(feature = "mmap")]
+pub const DEFAULT_MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// Options for [Filesystem::from_dir_with]. The defaults (used by
+/// [Filesystem::from_dir]) capture the directory exactly as it is on disk.
+#[derive(Debug, Clone, Default)]
+pub struct FromDirOptions {
+    /// Capture the target of a symlink instead of the symlink itself. Broken
+    /// symlinks and symlinks that would lead to a cycle are still captured as
+    /// symlinks.
+    pub follow_symlinks: bool,
+    /// Stop descending after this many levels, where the top-level directory
+    /// is at depth 0
+    pub max_depth: Option<usize>,
+    /// Regular files at least this big are memory-mapped instead of copied
+    /// into memory, falling back to reading them if they cannot be mapped.
+    /// The files must not be modified or truncated while the [Filesystem] is
+    /// alive, or its contents will change underneath it (or reading them will
+    /// crash with SIGBUS), so this is opt-in. The default of `None` always
+    /// reads files. Ignored without the `mmap` feature, so that enabling it
+    /// does not change this struct.
+    pub mmap_threshold: Option<u64>,
+}
+
+/// Failure to load a directory with [Filesystem::from_dir], along with the
+/// path on the host that caused it.
+#[derive(Debug)]
+pub struct Error {
+    pub path: PathBuf,
+    pub error: std::io::Error,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}': {}", self.path.display(), self.error)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        Self::new(e.error.kind(), e)
+    }
+}
+
+/// Attach the path that an io operation was working on to its error
+trait WithPath<T> {
+    fn with_path(self, path: &Path) -> Result<T>;
+}
+
+impl<T> WithPath<T> for std::io::Result<T> {
+    fn with_path(self, path: &Path) -> Result<T> {
+        self.map_err(|error| Error {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+impl Filesystem {
+    /// Recursively load a directory from the host filesystem. All paths are
+    /// relative to `dir`, which itself becomes the root entry at "" (see
+    /// [Filesystem] for how paths are normalized).
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::from_dir_with(dir, &FromDirOptions::default())
+    }
+
+    /// Like [Filesystem::from_dir], but with control over how the directory
+    /// is walked.
+    pub fn from_dir_with(dir: impl AsRef<Path>, options: &FromDirOptions) -> Result<Self> {
+        let mut fs = Self::new();
+        fs.insert_from_dir(dir.as_ref(), Path::new(""), options, 0, &mut Vec::new())?;
+        Ok(fs)
+    }
+
+    /// `ancestors` is the (dev, ino) of every directory currently being
+    /// walked, to detect symlink cycles.
+    fn insert_from_dir(
+        &mut self,
+        root: &Path,
+        relpath: &Path,
+        options: &FromDirOptions,
+        depth: usize,
+        ancestors: &mut Vec<(u64, u64)>,
+    ) -> Result<()> {
+        let path = root.join(relpath);
+        // symlink_metadata so that symlinks get their own metadata and not
+        // that of their target
+        let mut meta = std::fs::symlink_metadata(&path).with_path(&path)?;
+        let mut follow = false;
+        if options.follow_symlinks && meta.file_type().is_symlink() {
+            if let Ok(target) = std::fs::metadata(&path) {
+                if !(target.is_dir() && ancestors.contains(&(target.dev(), target.ino()))) {
+                    meta = target;
+                    follow = true;
+                }
+            }
+        }
+        let mut metadata = Metadata::from(meta.clone());
+        metadata.xattrs = match follow {
+            true => read_xattrs(
+                || xattr::list_deref(&path),
+                |name| xattr::get_deref(&path, name),
+            ),
+            false => read_xattrs(|| xattr::list(&path), |name| xattr::get(&path, name)),
+        }
+        .with_path(&path)?;
+        let file_type = meta.file_type();
+        if file_type.is_dir() {
+            self.insert(relpath, Directory::builder().metadata(metadata).build());
+            if options.max_depth.is_some_and(|max| depth >= max) {
+                return Ok(());
+            }
+            ancestors.push((meta.dev(), meta.ino()));
+            for entry in std::fs::read_dir(&path).with_path(&path)? {
+                self.insert_from_dir(
+                    root,
+                    &relpath.join(entry.with_path(&path)?.file_name()),
+                    options,
+                    depth + 1,
+                    ancestors,
+                )?;
+            }
+            ancestors.pop();
+        } else if file_type.is_file() {
+            self.insert(
+                relpath,
+                File::builder()
+                    .contents(read_file(&path, meta.len(), options)?)
+                    .metadata(metadata)
+                    .build(),
+            );
+        } else if file_type.is_symlink() {
+            self.insert(
+                relpath,
+                Symlink::new(std::fs::read_link(&path).with_path(&path)?, Some(metadata)),
+            );
+        } else {
+            self.insert(
+                relpath,
+                Special::new(
+                    SFlag::from_bits_truncate(meta.mode() & SFlag::S_IFMT.bits()),
+                    meta.rdev(),
+                    metadata,
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    /// Write the entire filesystem into `dir` on the host, creating `dir` if
+    /// it does not exist. Ownership is applied on a best-effort basis, since
+    /// it generally requires root. Every xattr is attempted, and any that
+    /// cannot be set are returned instead of failing the whole export.
+    /// Hardlinks are preserved.
+    pub fn write_to_dir(&self, dir: impl AsRef<Path>) -> std::io::Result<Vec<XattrError>> {
+        self.write_to_dir_with(dir, &XattrFilter::default())
+    }
+
+    /// Like [Filesystem::write_to_dir], but only setting the xattrs allowed
+    /// by `filter`.
+    pub fn write_to_dir_with(
+        &self,
+        dir: impl AsRef<Path>,
+        filter: &XattrFilter,
+    ) -> std::io::Result<Vec<XattrError>> {
+        let dir = dir.as_ref();
+        let mut xattr_errors = Vec::new();
+        std::fs::create_dir_all(dir)?;
+        // '..' could lead outside of `dir` through a symlink written earlier
+        if let Some(relpath) = self
+            .paths
+            .keys()
+            .find(|p| p.components().any(|c| c == Component::ParentDir))
+        {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("refusing to write '{}'", relpath.display()),
+            ));
+        }
+        // hardlinked entries are only written once, at their first path
+        let mut first_paths: HashMap<InodeKey, PathBuf> = HashMap::new();
+        // paths are sorted, so parents are always created before children
+        for (relpath, key) in &self.paths {
+            let path = dir.join(relpath);
+            if let Some(first) = first_paths.get(key) {
+                std::fs::hard_link(first, &path)?;
+                continue;
+            }
+            if self.refcounts[*key] > 1 {
+                first_paths.insert(*key, path.clone());
+            }
+            match &self.inodes[*key] {
+                Entry::Directory(_) => match std::fs::create_dir(&path) {
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists && path.is_dir() => Ok(()),
+                    res => res,
+                }?,
+                Entry::File(f) => {
+                    let mut out = std::fs::File::create(&path)?;
+                    std::io::copy(&mut f.reader(), &mut out)?;
+                }
+                Entry::Special(s) => {
+                    nix::sys::stat::mknod(
+                        &path,
+                        s.file_type(),
+                        s.metadata().mode(),
+                        s.rdev().unwrap_or(0),
+                    )?;
+                }
+                Entry::Symlink(s) => std::os::unix::fs::symlink(s.target(), &path)?,
+            }
+        }
+        // metadata is applied in reverse so that a directory is not made
+        // read-only before its children are written
+        for (relpath, entry) in self.iter().collect::<Vec<_>>().into_iter().rev() {
+            let path = dir.join(relpath);
+            let metadata = entry.metadata();
+            for (name, value) in metadata.xattrs() {
+                if let Some(value) = filter.apply(name, value) {
+                    if let Err(error) = xattr::set(&path, OsStr::from_bytes(name), &value) {
+                        xattr_errors.push(XattrError {
+                            path: path.clone(),
+                            name: name.clone(),
+                            error,
+                        });
+                    }
+                }
+            }
+            let _ = std::os::unix::fs::lchown(
+                &path,
+                Some(metadata.uid().as_u32()),
+                Some(metadata.gid().as_u32()),
+            );
+            // the mode of a symlink is meaningless and chmod would follow it
+            if !entry.is_symlink() {
+                std::fs::set_permissions(&path, metadata.permissions())?;
+            }
+        }
+        Ok(xattr_errors)
+    }
+}
+
+/// A difference found by [Filesystem::matches_dir]. Paths are relative to
+/// the root of the [Filesystem], like everywhere else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[remain::sorted]
+pub enum Mismatch {
+    /// The entry exists in both places, but these [Fields] differ. When the
+    /// [Fields::TYPE] is different, nothing else is compared.
+    Different { path: PathBuf, fields: Fields },
+    /// The entry only exists on disk
+    Extra(PathBuf),
+    /// The entry only exists in the [Filesystem]
+    Missing(PathBuf),
+}
+
+impl Mismatch {
+    pub fn path(&self) -> &Path {
+        #[remain::sorted]
+        match self {
+            Self::Different { path, .. } => path,
+            Self::Extra(path) => path,
+            Self::Missing(path) => path,
+        }
+    }
+}
+
+impl Filesystem {
+    /// Compare the filesystem against the directory `dir` on the host without
+    /// loading the whole directory into memory, returning every difference in
+    /// path order. File contents are streamed and compared only until the
+    /// first differing byte (and not at all if the lengths differ). Times are
+    /// not compared, since they are generally not reproducible, and neither is
+    /// the mode of symlinks.
+    pub fn matches_dir(&self, dir: impl AsRef<Path>) -> Result<Vec<Mismatch>> {
+        let dir = dir.as_ref();
+        let mut mismatches = Vec::new();
+        for (relpath, entry) in self {
+            let path = dir.join(relpath);
+            let meta = match std::fs::symlink_metadata(&path) {
+                Ok(meta) => meta,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    mismatches.push(Mismatch::Missing(relpath.to_path_buf()));
+                    continue;
+                }
+                Err(e) => return Err(e).with_path(&path),
+            };
+            let file_type = meta.file_type();
+            let same_type = match entry {
+                Entry::Directory(_) => file_type.is_dir(),
+                Entry::File(_) => file_type.is_file(),
+                Entry::Special(s) => s.file_type().bits() == meta.mode() & SFlag::S_IFMT.bits(),
+                Entry::Symlink(_) => file_type.is_symlink(),
+            };
+            if !same_type {
+                mismatches.push(Mismatch::Different {
+                    path: relpath.to_path_buf(),
+                    fields: Fields::TYPE,
+                });
+                continue;
+            }
+            let mut disk = Metadata::from(meta.clone());
+            disk.xattrs = read_xattrs(|| xattr::list(&path), |name| xattr::get(&path, name))
+                .with_path(&path)?;
+            let mut fields =
+                (Fields::MODE | Fields::OWNER | Fields::XATTR) - entry.metadata().cmp(&disk);
+            match entry {
+                Entry::Directory(_) => {
+                    for child in std::fs::read_dir(&path).with_path(&path)? {
+                        let child = relpath.join(child.with_path(&path)?.file_name());
+                        if !self.paths.contains_key(child.as_path()) {
+                            mismatches.push(Mismatch::Extra(child));
+                        }
+                    }
+                }
+                Entry::File(f) => {
+                    if meta.len() != f.len() || !same_contents(f, &path).with_path(&path)? {
+                        fields |= Fields::DATA;
+                    }
+                }
+                Entry::Special(s) => {
+                    if s.rdev().unwrap_or(0) != meta.rdev() {
+                        fields |= Fields::RDEV;
+                    }
+                }
+                Entry::Symlink(s) => {
+                    fields.remove(Fields::MODE);
+                    if std::fs::read_link(&path).with_path(&path)? != s.target() {
+                        fields |= Fields::DATA;
+                    }
+                }
+            }
+            if !fields.is_empty() {
+                mismatches.push(Mismatch::Different {
+                    path: relpath.to_path_buf(),
+                    fields,
+                });
+            }
+        }
+        mismatches.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(mismatches)
+    }
+}
+
+/// Whether the file at `path` has the same contents as `file`, stopping at
+/// the first difference
+fn same_contents(file: &File, path: &Path) -> std::io::Result<bool> {
+    let mut disk = BufReader::with_capacity(1 << 16, std::fs::File::open(path)?);
+    let mut mem = file.reader();
+    loop {
+        let expected = mem.fill_buf()?;
+        let actual = disk.fill_buf()?;
+        if expected.is_empty() || actual.is_empty() {
+            return Ok(expected.is_empty() && actual.is_empty());
+        }
+        let len = std::cmp::min(expected.len(), actual.len());
+        if expected[..len] != actual[..len] {
+            return Ok(false);
+        }
+        mem.consume(len);
+        disk.consume(len);
+    }
+}
+
+/// Collect every xattr with `list` and `get`. A filesystem that does not
+/// support xattrs at all just has none, and any attribute that disappears
+/// (or can't be read because of missing support) between `list` and `get`
+/// is skipped.
+fn read_xattrs<I>(
+    list: impl FnOnce() -> std::io::Result<I>,
+    get: impl Fn(&OsStr) -> std::io::Result<Option<Vec<u8>>>,
+) -> std::io::Result<BTreeMap<Bytes, Bytes>>
+where
+    I: IntoIterator,
+    I::Item: AsRef<OsStr>,
+{
+    let unsupported =
+        |e: &std::io::Error| e.raw_os_error() == Some(nix::errno::Errno::ENOTSUP as i32);
+    let names = match list() {
+        Ok(names) => names,
+        Err(e) if unsupported(&e) => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut xattrs = BTreeMap::new();
+    for name in names {
+        let name = name.as_ref();
+        match get(name) {
+            Ok(Some(value)) => {
+                xattrs.insert(Bytes::copy_from_slice(name.as_bytes()), value.into());
+            }
+            Ok(None) => {}
+            Err(e)
+                if unsupported(&e)
+                    || e.raw_os_error() == Some(nix::errno::Errno::ENODATA as i32) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Load the contents of a regular file, mapping it into memory if it is big
+/// enough (see [FromDirOptions::mmap_threshold])
+#[cfg_attr(not(feature = "mmap"), allow(unused_variables))]
+fn read_file(path: &Path, len: u64, options: &FromDirOptions) -> Result<Extent> {
+    #[cfg(feature = "mmap")]
+    if options.mmap_threshold.is_some_and(|min| len >= min) {
+        let f = std::fs::File::open(path).with_path(path)?;
+        // Safety: see the requirements documented on mmap_threshold
+        if let Ok(mmap) = unsafe { memmap::Mmap::map(&f) } {
+            let len = mmap.len();
+            return Ok(Extent::from_mmap(std::sync::Arc::new(mmap), 0..len));
+        }
+    }
+    Ok(std::fs::read(path).with_path(path)?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::FileTimes;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use nix::sys::stat::Mode;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::cmp::assert_approx_eq;
+    use crate::cmp::Fields;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn from_dir() {
+        let fs = Filesystem::from_dir("testdata/fs").expect("failed to load dir");
+        let expected = Filesystem::from([
+            ("", Directory::default().into()),
+            (
+                "lorem.txt",
+                File::builder().contents("Lorem ipsum\n").build().into(),
+            ),
+            ("dir", Directory::default().into()),
+            (
+                "dir/lorem.txt",
+                File::builder()
+                    .contents("Lorem ipsum dolor sit amet\n")
+                    .build()
+                    .into(),
+            ),
+            ("dir/symlink", Symlink::new("../lorem.txt", None).into()),
+        ]);
+        // ownership, permissions and times depend on the checkout
+        assert_approx_eq!(expected, fs, Fields::PATH | Fields::TYPE | Fields::DATA);
+    }
+
+    #[test]
+    fn error_path() {
+        let err = Filesystem::from_dir("testdata/missing").expect_err("dir does not exist");
+        assert_eq!(err.path, Path::new("testdata/missing"));
+        assert_eq!(err.error.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains("testdata/missing"), "{err}");
+        let io = std::io::Error::from(err);
+        assert_eq!(io.kind(), ErrorKind::NotFound);
+        assert!(io.to_string().contains("testdata/missing"), "{io}");
+
+        // permissions don't apply to root
+        if !nix::unistd::geteuid().is_root() {
+            let tmp = tempfile::tempdir().expect("failed to create tempdir");
+            let locked = tmp.path().join("locked");
+            std::fs::create_dir(&locked).expect("failed to mkdir");
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000))
+                .expect("failed to chmod");
+            let err = Filesystem::from_dir(tmp.path()).expect_err("dir is unreadable");
+            std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755))
+                .expect("failed to chmod");
+            assert_eq!(err.path, locked);
+            assert_eq!(err.error.kind(), ErrorKind::PermissionDenied);
+        }
+    }
+
+    #[test]
+    fn xattrs_unsupported() {
+        let enotsup = || std::io::Error::from_raw_os_error(nix::errno::Errno::ENOTSUP as i32);
+        let enodata = || std::io::Error::from_raw_os_error(nix::errno::Errno::ENODATA as i32);
+        assert_eq!(
+            read_xattrs(|| Err::<Vec<&OsStr>, _>(enotsup()), |_| unreachable!()).unwrap(),
+            BTreeMap::new(),
+        );
+        // attributes that vanish or can't be read after being listed are skipped
+        let xattrs = read_xattrs(
+            || Ok(["user.gone", "user.kept", "user.unsupported", "user.deleted"]),
+            |name| match name.as_bytes() {
+                b"user.gone" => Ok(None),
+                b"user.kept" => Ok(Some(b"lorem".to_vec())),
+                b"user.unsupported" => Err(enotsup()),
+                _ => Err(enodata()),
+            },
+        )
+        .expect("failed to read xattrs");
+        assert_eq!(
+            xattrs,
+            BTreeMap::from([(Bytes::from("user.kept"), Bytes::from("lorem"))]),
+        );
+        // anything else is still an error
+        assert!(read_xattrs(
+            || Ok(["user.demo"]),
+            |_| Err(std::io::Error::from(ErrorKind::PermissionDenied)),
+        )
+        .is_err());
+        assert!(read_xattrs(
+            || Err::<Vec<&OsStr>, _>(std::io::Error::from(ErrorKind::PermissionDenied)),
+            |_| unreachable!(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn write_to_dir_rejects_parent_dir() {
+        let mut fs = demo_fs();
+        fs.insert("testdata/../escape", File::new_empty());
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        assert_eq!(
+            fs.write_to_dir(tmp.path())
+                .expect_err("should not write '..'")
+                .kind(),
+            ErrorKind::InvalidInput,
+        );
+        assert!(!tmp.path().join("escape").exists());
+    }
+
+    #[test]
+    fn matches_dir() {
+        // ownership only sticks when running as root
+        let mut fs = demo_fs();
+        fs.chown_recursive(
+            "",
+            crate::Uid::from(nix::unistd::geteuid()),
+            crate::Gid::from(nix::unistd::getegid()),
+        )
+        .expect("failed to chown");
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        fs.write_to_dir(tmp.path()).expect("failed to write fs");
+        assert_eq!(
+            fs.matches_dir(tmp.path()).expect("failed to compare"),
+            vec![]
+        );
+
+        // same length, so the contents have to be compared
+        std::fs::write(
+            tmp.path().join("testdata/dir/lorem.txt"),
+            "Lorem ipsum dolor sit amen\n",
+        )
+        .expect("failed to write");
+        std::fs::set_permissions(
+            tmp.path().join("testdata/lorem.txt"),
+            std::fs::Permissions::from_mode(0o600),
+        )
+        .expect("failed to chmod");
+        std::fs::remove_file(tmp.path().join("testdata/dir/symlink")).expect("failed to rm");
+        std::fs::create_dir(tmp.path().join("testdata/dir/symlink")).expect("failed to mkdir");
+        std::fs::write(tmp.path().join("testdata/extra"), "").expect("failed to write");
+        assert_eq!(
+            fs.matches_dir(tmp.path()).expect("failed to compare"),
+            vec![
+                Mismatch::Different {
+                    path: "testdata/dir/lorem.txt".into(),
+                    fields: Fields::DATA,
+                },
+                Mismatch::Different {
+                    path: "testdata/dir/symlink".into(),
+                    fields: Fields::TYPE,
+                },
+                Mismatch::Extra("testdata/extra".into()),
+                Mismatch::Different {
+                    path: "testdata/lorem.txt".into(),
+                    fields: Fields::MODE,
+                },
+            ],
+        );
+
+        std::fs::remove_dir_all(tmp.path().join("testdata/dir")).expect("failed to rm");
+        let mismatches = fs.matches_dir(tmp.path()).expect("failed to compare");
+        assert_eq!(
+            mismatches[..3],
+            [
+                Mismatch::Missing("testdata/dir".into()),
+                Mismatch::Missing("testdata/dir/lorem.txt".into()),
+                Mismatch::Missing("testdata/dir/symlink".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn from_dir_root() {
+        let fs = Filesystem::from_dir("testdata/fs").expect("failed to load dir");
+        let root = fs.get("/").expect("missing root");
+        assert!(root.is_directory());
+        assert_eq!(fs.get(".").unwrap(), root);
+        assert_eq!(
+            fs.get("/dir/lorem.txt").unwrap(),
+            fs.get("./dir/lorem.txt").unwrap(),
+        );
+        assert!(fs.get("dir/lorem.txt").unwrap().is_file());
+    }
+
+    #[test]
+    fn symlink() {
+        let fs = Filesystem::from_dir("testdata/fs").expect("failed to load dir");
+        match fs.get("dir/symlink").expect("missing symlink") {
+            Entry::Symlink(s) => {
+                assert_eq!(s.target(), Path::new("../lorem.txt"));
+                // the symlink's own mode, not the target's
+                assert_eq!(s.metadata().mode(), Mode::from_bits_truncate(0o777));
+            }
+            e => panic!("expected symlink, got {e:?}"),
+        }
+    }
+
+    #[test]
+    fn special() {
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        nix::unistd::mkfifo(&tmp.path().join("fifo"), Mode::from_bits_truncate(0o644))
+            .expect("failed to create fifo");
+        let fs = Filesystem::from_dir(tmp.path()).expect("failed to load dir");
+        match fs.get("fifo").expect("missing fifo") {
+            Entry::Special(s) => {
+                assert_eq!(s.file_type(), SFlag::S_IFIFO);
+                assert_eq!(s.rdev(), None);
+                assert_eq!(s.metadata().mode(), Mode::from_bits_truncate(0o644));
+            }
+            e => panic!("expected special file, got {e:?}"),
+        }
+    }
+
+    #[test]
+    fn times() {
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        let accessed = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_234_567_890);
+        let f = std::fs::File::create(tmp.path().join("lorem.txt")).expect("failed to create");
+        f.set_times(
+            FileTimes::new()
+                .set_accessed(accessed)
+                .set_modified(modified),
+        )
+        .expect("failed to set times");
+        let fs = Filesystem::from_dir(tmp.path()).expect("failed to load dir");
+        let metadata = fs.get("lorem.txt").expect("missing file").metadata();
+        assert_eq!(metadata.accessed(), accessed);
+        assert_eq!(metadata.modified(), modified);
+    }
+
+    #[test]
+    fn write_to_dir() {
+        let mut demo_fs = demo_fs();
+        demo_fs
+            .link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        demo_fs
+            .write_to_dir(tmp.path())
+            .expect("failed to write fs");
+        let fs = Filesystem::from_dir(tmp.path()).expect("failed to load dir");
+        // ownership is best-effort and times are not preserved
+        assert_approx_eq!(demo_fs, fs, Fields::all() - Fields::OWNER - Fields::TIME);
+        for path in ["testdata/lorem.txt", "testdata/hardlink.txt"] {
+            let meta = std::fs::metadata(tmp.path().join(path)).expect("missing file");
+            assert_eq!(meta.nlink(), 2, "{path} should be hardlinked");
+        }
+    }
+
+    #[test]
+    fn write_to_dir_xattrs() {
+        let mut fs = demo_fs();
+        fs.get_mut("testdata/lorem.txt")
+            .unwrap()
+            .set_xattr("security.selinux", "system_u:object_r:etc_t:s0");
+        fs.get_mut("testdata/dir/lorem.txt")
+            .unwrap()
+            .set_xattr("bogus.lorem", "ipsum");
+
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        let errors = fs
+            .write_to_dir_with(tmp.path(), &XattrFilter::namespaces(["user"]))
+            .expect("failed to write fs");
+        assert!(errors.is_empty(), "{errors:?}");
+        let written = Filesystem::from_dir(tmp.path()).expect("failed to load dir");
+        assert_approx_eq!(
+            demo_fs(),
+            written,
+            Fields::all() - Fields::OWNER - Fields::TIME
+        );
+
+        // by default, everything is attempted and failures are reported
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        let errors = fs.write_to_dir(tmp.path()).expect("failed to write fs");
+        let failed: Vec<_> = errors.iter().map(|e| e.name.clone()).collect();
+        assert!(failed.contains(&Bytes::from("bogus.lorem")), "{errors:?}");
+        assert_eq!(
+            std::fs::read(tmp.path().join("testdata/dir/lorem.txt")).unwrap(),
+            b"Lorem ipsum dolor sit amet\n",
+        );
+    }
+
+    fn symlinked_dir() -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        std::fs::create_dir(tmp.path().join("dir")).expect("failed to mkdir");
+        std::fs::write(tmp.path().join("dir/lorem.txt"), "Lorem ipsum\n").expect("failed to write");
+        std::os::unix::fs::symlink("dir", tmp.path().join("link")).expect("failed to symlink");
+        // a cycle back to the top-level directory
+        std::os::unix::fs::symlink("..", tmp.path().join("dir/parent")).expect("failed to symlink");
+        tmp
+    }
+
+    #[test]
+    fn no_follow_symlinks() {
+        let tmp = symlinked_dir();
+        let fs = Filesystem::from_dir(tmp.path()).expect("failed to load dir");
+        assert!(fs.get("link").expect("missing link").is_symlink());
+        assert!(fs.get("link/lorem.txt").is_err());
+    }
+
+    #[test]
+    fn follow_symlinks() {
+        let tmp = symlinked_dir();
+        let fs = Filesystem::from_dir_with(
+            tmp.path(),
+            &FromDirOptions {
+                follow_symlinks: true,
+                ..Default::default()
+            },
+        )
+        .expect("failed to load dir");
+        assert!(fs.get("link").expect("missing link").is_directory());
+        assert_eq!(
+            fs.get_file("link/lorem.txt")
+                .expect("missing file")
+                .to_bytes()
+                .as_ref(),
+            b"Lorem ipsum\n",
+        );
+        // the cycle is not followed
+        assert!(fs.get("dir/parent").expect("missing link").is_symlink());
+        assert!(fs.get("link/parent").expect("missing link").is_symlink());
+    }
+
+    /// Whether `ptr` is in a mapping of `path` according to /proc/self/maps
+    #[cfg(feature = "mmap")]
+    fn is_mapped_from(ptr: *const u8, path: &Path) -> bool {
+        let path = path.canonicalize().expect("failed to canonicalize");
+        let maps = std::fs::read_to_string("/proc/self/maps").expect("failed to read maps");
+        maps.lines().any(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields
+                .next()
+                .and_then(|r| r.split_once('-'))
+                .expect("malformed maps line");
+            let start = usize::from_str_radix(start, 16).expect("bad address");
+            let end = usize::from_str_radix(end, 16).expect("bad address");
+            (start..end).contains(&(ptr as usize))
+                && fields.nth(4).map(Path::new) == Some(path.as_path())
+        })
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap() {
+        let tmp = tempfile::tempdir().expect("failed to create tempdir");
+        let big = vec![b'a'; DEFAULT_MMAP_THRESHOLD as usize];
+        std::fs::write(tmp.path().join("big"), &big).expect("failed to write");
+        std::fs::write(tmp.path().join("small"), "Lorem ipsum\n").expect("failed to write");
+        std::fs::write(tmp.path().join("empty"), "").expect("failed to write");
+
+        let data_ptr = |fs: &Filesystem, path| {
+            let f = fs.get_file(path).expect("missing file");
+            assert_eq!(f.extent_count(), 1);
+            f.extents
+                .values()
+                .next()
+                .expect("one extent")
+                .data()
+                .as_ptr()
+        };
+        let fs = Filesystem::from_dir_with(
+            tmp.path(),
+            &FromDirOptions {
+                mmap_threshold: Some(DEFAULT_MMAP_THRESHOLD),
+                ..Default::default()
+            },
+        )
+        .expect("failed to load dir");
+        assert_eq!(fs.get_file("big").unwrap().to_bytes().as_ref(), big);
+        assert!(is_mapped_from(
+            data_ptr(&fs, "big"),
+            &tmp.path().join("big")
+        ));
+        assert!(!is_mapped_from(
+            data_ptr(&fs, "small"),
+            &tmp.path().join("small")
+        ));
+
+        // unmappable files are read instead
+        let fs = Filesystem::from_dir_with(
+            tmp.path(),
+            &FromDirOptions {
+                mmap_threshold: Some(0),
+                ..Default::default()
+            },
+        )
+        .expect("failed to load dir");
+        assert_eq!(fs.get_file("empty").unwrap().len(), 0);
+        assert!(is_mapped_from(
+            data_ptr(&fs, "small"),
+            &tmp.path().join("small")
+        ));
+
+        // mmap is opt-in
+        let fs = Filesystem::from_dir(tmp.path()).expect("failed to load dir");
+        assert!(!is_mapped_from(
+            data_ptr(&fs, "big"),
+            &tmp.path().join("big")
+        ));
+    }
+
+    #[test]
+    fn max_depth() {
+        let fs = Filesystem::from_dir_with(
+            "testdata/fs",
+            &FromDirOptions {
+                max_depth: Some(1),
+                ..Default::default()
+            },
+        )
+        .expect("failed to load dir");
+        assert!(fs.get("dir").expect("missing dir").is_directory());
+        assert!(fs.get("lorem.txt").is_ok());
+        assert!(fs.get("dir/lorem.txt").is_err());
+    }
+}
@@ -20,10 +20,12 @@ use crate::cmp::Fields;
 use crate::BytesPath;
 use crate::File;
 use crate::Gid;
+use crate::Namespace;
 use crate::Uid;
 
 /// A single directory entry in the filesystem.
 #[derive(Debug, Clone, PartialEq, Eq, From, IsVariant)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[remain::sorted]
 pub enum Entry {
     Directory(Directory),
@@ -54,6 +56,32 @@ impl Entry {
         }
     }
 
+    /// Same as [Entry::is_directory], named after [std::fs::FileType::is_dir]
+    pub fn is_dir(&self) -> bool {
+        self.is_directory()
+    }
+
+    pub fn as_dir(&self) -> Option<&Directory> {
+        match self {
+            Self::Directory(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    pub fn as_file(&self) -> Option<&File> {
+        match self {
+            Self::File(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    pub fn as_symlink(&self) -> Option<&Symlink> {
+        match self {
+            Self::Symlink(s) => Some(s),
+            _ => None,
+        }
+    }
+
     pub fn chown(&mut self, uid: Uid, gid: Gid) {
         self.metadata_mut().chown(uid, gid);
     }
@@ -69,6 +97,29 @@ impl Entry {
     pub fn remove_xattr(&mut self, name: &Bytes) -> Option<Bytes> {
         self.metadata_mut().xattrs.remove(name)
     }
+
+    /// Copy all the data that this entry refers to (file contents, xattrs
+    /// and symlink targets) into new allocations that are not shared with
+    /// anything else. See [File::into_owned].
+    pub fn into_owned(self) -> Self {
+        #[remain::sorted]
+        match self {
+            Self::Directory(mut d) => {
+                d.metadata = d.metadata.into_owned();
+                Self::Directory(d)
+            }
+            Self::File(f) => Self::File(f.into_owned()),
+            Self::Special(mut s) => {
+                s.metadata = s.metadata.into_owned();
+                Self::Special(s)
+            }
+            Self::Symlink(mut s) => {
+                s.target = s.target.into_owned();
+                s.metadata = s.metadata.into_owned();
+                Self::Symlink(s)
+            }
+        }
+    }
 }
 
 impl ApproxEq for Entry {
@@ -79,7 +130,7 @@ impl ApproxEq for Entry {
             (Self::Directory(_), _) => f - Fields::TYPE,
             (Self::File(s), Self::File(o)) => f.intersection(s.cmp(o)),
             (Self::File(_), _) => f - Fields::TYPE,
-            (Self::Special(_), Self::Special(_)) => self.metadata().cmp(other.metadata()),
+            (Self::Special(s), Self::Special(o)) => f.intersection(s.cmp(o)),
             (Self::Special(_), _) => f - Fields::TYPE,
             (Self::Symlink(s), Self::Symlink(o)) => f.intersection(s.cmp(o)),
             (Self::Symlink(_), _) => f - Fields::TYPE,
@@ -88,15 +139,18 @@ impl ApproxEq for Entry {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters, Builder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[builder(default, setter(into), build_fn(private, name = "fallible_build"))]
 pub struct Metadata {
     #[get_copy = "pub"]
+    #[cfg_attr(feature = "serde", serde(with = "crate::ser::mode"))]
     pub(crate) mode: Mode,
     #[get_copy = "pub"]
     pub(crate) uid: Uid,
     #[get_copy = "pub"]
     pub(crate) gid: Gid,
     #[get = "pub"]
+    #[cfg_attr(feature = "serde", serde(with = "crate::ser::xattrs"))]
     pub(crate) xattrs: BTreeMap<Bytes, Bytes>,
     #[get_copy = "pub"]
     pub(crate) created: SystemTime,
@@ -129,6 +183,24 @@ impl Metadata {
         self.accessed = accessed;
         self.modified = modified;
     }
+
+    /// Iterate over the xattrs with their [Namespace] parsed out of the name,
+    /// yielding `(namespace, name without the prefix, value)`. xattrs whose
+    /// names are not in a known namespace are skipped.
+    pub fn namespaced_xattrs(&self) -> impl Iterator<Item = (Namespace, &[u8], &[u8])> {
+        self.xattrs.iter().filter_map(|(name, value)| {
+            Namespace::parse(name).map(|(namespace, name)| (namespace, name, &value[..]))
+        })
+    }
+
+    /// Copy the xattrs into their own allocations, see [Entry::into_owned]
+    pub(crate) fn into_owned(mut self) -> Self {
+        self.xattrs = std::mem::take(&mut self.xattrs)
+            .into_iter()
+            .map(|(k, v)| (Bytes::copy_from_slice(&k), Bytes::copy_from_slice(&v)))
+            .collect();
+        self
+    }
 }
 
 impl Default for Metadata {
@@ -233,6 +305,7 @@ impl ApproxEq for Metadata {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, Builder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[builder(default, setter(into), build_fn(private, name = "fallible_build"))]
 pub struct Directory {
     metadata: Metadata,
@@ -260,8 +333,10 @@ impl ApproxEq for Directory {
 
 /// A special file (device node, socket, fifo, etc)
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Special {
     /// Special file type
+    #[cfg_attr(feature = "serde", serde(with = "crate::ser::sflag"))]
     file_type: SFlag,
     rdev: u64,
     metadata: Metadata,
@@ -311,26 +386,38 @@ impl ApproxEq for Special {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Builder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[builder(setter(into), build_fn(private, name = "fallible_build"))]
 pub struct Symlink {
     /// Target path
     target: BytesPath,
+    #[builder(default = "Symlink::default_metadata()")]
     metadata: Metadata,
 }
 
 impl Symlink {
     pub fn new(target: impl Into<BytesPath>, metadata: Option<Metadata>) -> Self {
-        let metadata = metadata.unwrap_or_else(|| {
-            Metadata::builder()
-                .mode(Mode::from_bits_truncate(0o777))
-                .build()
-        });
         Self {
             target: target.into(),
-            metadata,
+            metadata: metadata.unwrap_or_else(Self::default_metadata),
         }
     }
 
+    /// Start building a symlink pointing at `target`. Like [Symlink::new],
+    /// the mode defaults to 0777, which is what Linux always uses.
+    pub fn builder(target: impl Into<BytesPath>) -> SymlinkBuilder {
+        let mut builder = SymlinkBuilder::default();
+        builder.target(target);
+        builder
+    }
+
+    fn default_metadata() -> Metadata {
+        Metadata::builder()
+            .mode(Mode::from_bits_truncate(0o777))
+            .build()
+    }
+
     pub fn target(&self) -> &Path {
         &self.target
     }
@@ -340,6 +427,46 @@ impl Symlink {
     }
 }
 
+impl SymlinkBuilder {
+    fn metadata_mut(&mut self) -> &mut Metadata {
+        self.metadata.get_or_insert_with(Symlink::default_metadata)
+    }
+
+    pub fn mode(&mut self, mode: Mode) -> &mut Self {
+        self.metadata_mut().mode = mode;
+        self
+    }
+
+    pub fn uid(&mut self, uid: impl Into<Uid>) -> &mut Self {
+        self.metadata_mut().uid = uid.into();
+        self
+    }
+
+    pub fn gid(&mut self, gid: impl Into<Gid>) -> &mut Self {
+        self.metadata_mut().gid = gid.into();
+        self
+    }
+
+    pub fn xattr(&mut self, name: impl Into<Bytes>, value: impl Into<Bytes>) -> &mut Self {
+        self.metadata_mut().xattrs.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn build(&mut self) -> Symlink {
+        self.fallible_build()
+            .expect("target is always set by Symlink::builder")
+    }
+}
+
+impl<T> From<T> for Symlink
+where
+    T: Into<BytesPath>,
+{
+    fn from(target: T) -> Self {
+        Self::new(target, None)
+    }
+}
+
 impl ApproxEq for Symlink {
     #[deny(unused_variables)]
     fn cmp(&self, other: &Self) -> Fields {
@@ -351,3 +478,67 @@ impl ApproxEq for Symlink {
         f
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn type_helpers() {
+        let fs = demo_fs();
+        let dir = fs.get("testdata/dir").unwrap();
+        assert!(dir.is_dir());
+        assert!(!dir.is_file());
+        assert!(!dir.is_symlink());
+        assert!(dir.as_dir().is_some());
+        assert_eq!(dir.as_file(), None);
+        assert_eq!(dir.as_symlink(), None);
+
+        let file = fs.get("testdata/lorem.txt").unwrap();
+        assert!(!file.is_dir());
+        assert!(file.is_file());
+        assert!(!file.is_symlink());
+        assert_eq!(
+            file.as_file().map(File::to_bytes).as_deref(),
+            Some(&b"Lorem ipsum\n"[..]),
+        );
+        assert_eq!(file.as_dir(), None);
+        assert_eq!(file.as_symlink(), None);
+
+        let symlink = fs.get("testdata/dir/symlink").unwrap();
+        assert!(!symlink.is_dir());
+        assert!(!symlink.is_file());
+        assert!(symlink.is_symlink());
+        assert_eq!(symlink.as_dir(), None);
+        assert_eq!(symlink.as_file(), None);
+        assert_eq!(
+            symlink.as_symlink().map(Symlink::target),
+            Some(Path::new("../lorem.txt")),
+        );
+    }
+
+    #[test]
+    fn symlink_builder() {
+        let s = Symlink::from("../lorem.txt");
+        assert_eq!(s.target(), Path::new("../lorem.txt"));
+        assert_eq!(s.metadata().mode(), Mode::from_bits_truncate(0o777));
+        assert_eq!(s, Symlink::from(String::from("../lorem.txt")));
+        assert_eq!(s, Symlink::builder("../lorem.txt").build());
+
+        let s = Symlink::builder("../lorem.txt")
+            .uid(1000)
+            .gid(100)
+            .xattr("user.lorem", "ipsum")
+            .build();
+        assert_eq!(s.metadata().mode(), Mode::from_bits_truncate(0o777));
+        assert_eq!(s.metadata().uid(), Uid::from_raw(1000));
+        assert_eq!(s.metadata().gid(), Gid::from_raw(100));
+        assert_eq!(
+            s.metadata().xattrs(),
+            &BTreeMap::from([(Bytes::from("user.lorem"), Bytes::from("ipsum"))]),
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use std::fmt::Display;
+use std::ops::Range;
+
+/// Errors from operations on a [super::File] that can't be satisfied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The range is reversed or otherwise not a valid range of the file
+    InvalidRange(Range<u64>),
+    /// Writing `len` bytes at `offset` would grow the file past [u64::MAX]
+    TooLarge { offset: u64, len: u64 },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidRange(r) => write!(f, "invalid range {}..{}", r.start, r.end),
+            Self::TooLarge { offset, len } => {
+                write!(f, "writing {len} bytes at {offset} overflows the file size")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::InvalidRange(_) => std::io::ErrorKind::InvalidInput,
+            Error::TooLarge { .. } => std::io::ErrorKind::FileTooLarge,
+        };
+        Self::new(kind, e)
+    }
+}
@@ -1,64 +1,121 @@
+#[cfg(feature = "mmap")]
+use std::ops::Range;
+#[cfg(feature = "mmap")]
+use std::sync::Arc;
+
 use bytes::Bytes;
+#[cfg(feature = "mmap")]
+use memmap::Mmap;
 
 use super::File;
 
 /// A single piece of data that makes up a file. Immutable but can be composed
 /// with other Extents in order to implement mutable files on top of immutable
 /// extent chunks.
-#[derive(Clone, PartialEq, Eq)]
+/// Extents are compared by their contents, so an [Extent::Owned] is equal to
+/// an [Extent::Cloned] or [Extent::Compressed] with the same data, and an
+/// [Extent::Hole] is equal to the same number of zeroes. This means that
+/// comparisons read (and possibly decompress) all of the data.
+#[derive(Clone)]
 pub enum Extent {
     /// The source-of-truth for this data is the file that contains it. It
     /// originated from a write to that File, not a clone from another.
     Owned(Bytes),
     /// This extent came from part of another File.
     Cloned(Cloned),
+    /// Data that was compressed in memory with [super::File::compress_extents]
+    #[cfg(feature = "zstd")]
+    Compressed(Compressed),
     /// This extent was created with 'truncate' and is actually empty
     Hole(u64),
 }
 
 impl Extent {
+    /// Logical length of this extent. This never has to look at the data
+    /// itself, so it is cheap even for compressed extents.
     pub fn len(&self) -> u64 {
         match self {
+            Self::Owned(o) => o.len() as u64,
+            Self::Cloned(c) => c.data.len() as u64,
+            #[cfg(feature = "zstd")]
+            Self::Compressed(c) => c.len,
             Self::Hole(s) => *s,
-            _ => self.data().len() as u64,
         }
     }
 
+    /// Zero-copy view of `range` within a memory-mapped file. The mapping is
+    /// kept alive for as long as any extent (or [Bytes]) refers to it.
+    /// Panics if `range` is out of bounds of the mapping.
+    #[cfg(feature = "mmap")]
+    pub fn from_mmap(mmap: Arc<Mmap>, range: Range<usize>) -> Self {
+        Self::Owned(Bytes::from_owner(SharedMmap(mmap)).slice(range))
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.data().is_empty()
+        self.len() == 0
     }
 
+    /// Raw data backing this extent. A [Extent::Hole] has no backing data, so
+    /// this is always empty for holes even though they have a non-zero
+    /// [Extent::len]. Similarly, the backing data of a compressed extent is
+    /// the compressed data, use [Extent::bytes] to get the real contents.
     pub fn data(&self) -> &[u8] {
         match self {
             Self::Owned(c) => c,
             Self::Cloned(c) => &c.data,
+            #[cfg(feature = "zstd")]
+            Self::Compressed(c) => &c.data,
             Self::Hole(_) => &[],
         }
     }
 
+    /// Contents of this extent. This is zero-copy except for compressed
+    /// extents, which have to be decompressed.
     pub fn bytes(&self) -> Bytes {
         match self {
             Self::Owned(c) => c.clone(),
             Self::Cloned(c) => c.data.clone(),
+            #[cfg(feature = "zstd")]
+            Self::Compressed(c) => c.decompress(),
             Self::Hole(_) => Bytes::new(),
         }
     }
 
+    /// Copy the data into a new allocation, see [File::into_owned]. Cloned
+    /// extents become owned, since the copy no longer shares anything with
+    /// the source file.
+    pub(super) fn into_owned(self) -> Self {
+        match self {
+            Self::Owned(_) | Self::Cloned(_) => Self::Owned(Bytes::copy_from_slice(self.data())),
+            #[cfg(feature = "zstd")]
+            Self::Compressed(c) => Self::Compressed(Compressed {
+                data: Bytes::copy_from_slice(&c.data),
+                len: c.len,
+            }),
+            Self::Hole(h) => Self::Hole(h),
+        }
+    }
+
+    /// Split this extent in two at `at`, leaving the left side in `self` and
+    /// returning the right side. Compressed extents are decompressed first.
     pub(super) fn split_at(&mut self, at: usize) -> Self {
         match self {
+            #[cfg(feature = "zstd")]
+            Self::Compressed(c) => {
+                *self = Self::Owned(c.decompress());
+                self.split_at(at)
+            }
             Self::Owned(ref mut data) => {
                 let right = data.split_off(at);
                 Self::Owned(right)
             }
             Self::Cloned(ref mut c) => {
                 let right = c.data.split_off(at);
-                c.src_range = (c.src_range.0, std::cmp::min(at as u64, c.src_range.1));
+                let (src_start, src_end) = c.src_range;
+                c.src_range = (src_start, src_start + at as u64);
                 Self::Cloned(Cloned {
                     src_file: c.src_file.clone(),
-                    src_range: (
-                        std::cmp::max(at as u64, c.src_range.0),
-                        std::cmp::min(at as u64, c.src_range.1),
-                    ),
+                    src_range: (src_start + at as u64, src_end),
                     data: right,
                 })
             }
@@ -71,6 +128,32 @@ impl Extent {
     }
 }
 
+/// [Bytes::from_owner] needs something that is `AsRef<[u8]>`
+#[cfg(feature = "mmap")]
+struct SharedMmap(Arc<Mmap>);
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for SharedMmap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for Extent {
+    fn eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
+        match (self, other) {
+            (Self::Hole(_), Self::Hole(_)) => true,
+            (Self::Hole(_), data) | (data, Self::Hole(_)) => data.bytes().iter().all(|b| *b == 0),
+            _ => self.bytes() == other.bytes(),
+        }
+    }
+}
+
+impl Eq for Extent {}
+
 /// A Cloned [Extent] comes from another file. This extent references the
 /// original [File] and the location in that file for debuggability of BTRFS
 /// sendstreams.
@@ -82,6 +165,46 @@ pub struct Cloned {
     pub(super) data: Bytes,
 }
 
+impl Cloned {
+    /// The [File] that this data was cloned from, as it was at the time of
+    /// the clone.
+    pub fn src_file(&self) -> &File {
+        &self.src_file
+    }
+
+    /// Byte range (start, end) in [Cloned::src_file] that this data came from
+    pub fn src_range(&self) -> (u64, u64) {
+        self.src_range
+    }
+}
+
+/// Zstd-compressed data that is transparently decompressed when read.
+#[cfg(feature = "zstd")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct Compressed {
+    pub(super) data: Bytes,
+    /// Length of the decompressed data
+    pub(super) len: u64,
+}
+
+#[cfg(feature = "zstd")]
+impl Compressed {
+    pub(super) fn new(data: &[u8], level: i32) -> Self {
+        Self {
+            data: zstd::bulk::compress(data, level)
+                .expect("compressing into memory is infallible")
+                .into(),
+            len: data.len() as u64,
+        }
+    }
+
+    pub(super) fn decompress(&self) -> Bytes {
+        zstd::bulk::decompress(&self.data, self.len as usize)
+            .expect("data was compressed by us")
+            .into()
+    }
+}
+
 impl std::fmt::Debug for Extent {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -98,6 +221,12 @@ impl std::fmt::Debug for Extent {
                 d.finish()
             }
             Self::Cloned(c) => f.debug_tuple("Cloned").field(&c).finish(),
+            #[cfg(feature = "zstd")]
+            Self::Compressed(c) => f
+                .debug_struct("Compressed")
+                .field("compressed_len", &c.data.len())
+                .field("len", &c.len)
+                .finish(),
             Self::Hole(h) => f.debug_tuple("Hole").field(&h).finish(),
         }
     }
@@ -148,4 +277,101 @@ mod tests {
         assert_eq!(left, "Lorem".into());
         assert_eq!(right, " ipsum".into());
     }
+
+    #[test]
+    fn cloned_split() {
+        let mut ext = Extent::Cloned(Cloned {
+            src_file: File::new_empty(),
+            src_range: (6, 17),
+            data: Bytes::from("ipsum dolor"),
+        });
+        let right = ext.split_at("ipsum".len());
+        match (ext, right) {
+            (Extent::Cloned(left), Extent::Cloned(right)) => {
+                assert_eq!(left.src_range, (6, 11));
+                assert_eq!(left.data, "ipsum");
+                assert_eq!(right.src_range, (11, 17));
+                assert_eq!(right.data, " dolor");
+            }
+            _ => unreachable!("split cloned extents are still cloned"),
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn from_mmap() {
+        use std::io::BufRead;
+
+        let f = std::fs::File::open("testdata/fs/lorem.txt").expect("failed to open");
+        // Safety: nothing modifies the fixture while the test is running
+        let mmap = Arc::new(unsafe { Mmap::map(&f) }.expect("failed to mmap"));
+        let ext = Extent::from_mmap(mmap.clone(), 6..11);
+        assert_eq!(ext, "ipsum".into());
+        assert_eq!(ext.data().as_ptr(), mmap[6..].as_ptr());
+
+        let file = File::builder()
+            .contents(Extent::from_mmap(mmap.clone(), 0..mmap.len()))
+            .build();
+        drop(ext);
+        assert_eq!(Arc::strong_count(&mmap), 2);
+        let mut reader = file.reader();
+        let buf = reader.fill_buf().expect("infallible");
+        assert_eq!(buf, b"Lorem ipsum\n");
+        assert_eq!(buf.as_ptr(), mmap.as_ptr());
+    }
+
+    #[test]
+    fn hole() {
+        let mut ext = Extent::Hole(10);
+        assert_eq!(ext.len(), 10);
+        assert!(!ext.is_empty());
+        let right = ext.split_at(4);
+        assert_eq!(ext, Extent::Hole(4));
+        assert_eq!(right, Extent::Hole(6));
+    }
+
+    #[test]
+    fn content_eq() {
+        let cloned = Extent::Cloned(Cloned {
+            src_file: File::new_empty(),
+            src_range: (6, 17),
+            data: Bytes::from("ipsum dolor"),
+        });
+        assert_eq!(cloned, "ipsum dolor".into());
+        assert_ne!(cloned, "ipsum".into());
+        assert_ne!(cloned, "dolor ipsum".into());
+        assert_eq!(Extent::Hole(3), Extent::from(&b"\0\0\0"[..]));
+        assert_ne!(Extent::Hole(3), Extent::from(&b"\0\0"[..]));
+        assert_ne!(Extent::Hole(3), Extent::from(&b"\0a\0"[..]));
+        #[cfg(feature = "zstd")]
+        assert_eq!(
+            Extent::Compressed(Compressed::new(b"ipsum dolor", 3)),
+            cloned
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_len() {
+        // not valid zstd data, so this would panic if len() decompressed it
+        let ext = Extent::Compressed(Compressed {
+            data: Bytes::from_static(b"not zstd"),
+            len: 1 << 20,
+        });
+        assert_eq!(ext.len(), 1 << 20);
+        assert!(!ext.is_empty());
+        let f = File::builder().contents(ext).build();
+        assert_eq!(f.len(), 1 << 20);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_split() {
+        let mut ext = Extent::Compressed(Compressed::new(b"Lorem ipsum", 3));
+        assert_eq!(ext.len(), 11);
+        assert_eq!(ext.bytes(), "Lorem ipsum");
+        let right = ext.split_at("Lorem".len());
+        assert_eq!(ext, "Lorem".into());
+        assert_eq!(right, " ipsum".into());
+    }
 }
@@ -1,20 +1,37 @@
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::ops::Range;
 
+use bytes::Bytes;
+use bytes::BytesMut;
 use derive_builder::Builder;
+use nix::sys::stat::Mode;
 
+mod error;
 pub mod extent;
 pub mod reader;
 pub mod writer;
 
+pub use error::Error;
+pub use error::Result;
+
 use extent::Cloned;
 use extent::Extent;
 
 use crate::cmp::ApproxEq;
 use crate::cmp::Fields;
 use crate::entry::Metadata;
+use crate::Gid;
+use crate::Namespace;
+use crate::Uid;
+
+/// Extents smaller than this are not worth compressing in
+/// [File::compress_extents]
+#[cfg(feature = "zstd")]
+pub const COMPRESS_THRESHOLD: usize = 4096;
 
 /// A single file in the filesystem. This has a number of metadata attributes
 /// alongside the file contents.
@@ -23,6 +40,7 @@ use crate::entry::Metadata;
 /// but also be mutable (useful for things like BTRFS sendstreams that contain a
 /// sequence of mutation operations instead of raw file contents).
 #[derive(Debug, Clone, PartialEq, Eq, Default, Builder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[builder(default, setter(into), build_fn(private, name = "fallible_build"))]
 pub struct File {
     pub(crate) extents: BTreeMap<u64, Extent>,
@@ -35,6 +53,14 @@ impl FileBuilder {
         self.extents(BTreeMap::from([(0, contents.into())]))
     }
 
+    /// Read all of `reader` into a single owned [Extent] and use that as the
+    /// contents of the [File].
+    pub fn contents_from_reader<R: Read>(&mut self, mut reader: R) -> std::io::Result<&mut Self> {
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents)?;
+        Ok(self.contents(contents))
+    }
+
     pub fn build(&mut self) -> File {
         self.fallible_build().expect("infallible")
     }
@@ -53,6 +79,43 @@ impl File {
         self.extents.is_empty()
     }
 
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.metadata.mode
+    }
+
+    pub fn uid(&self) -> Uid {
+        self.metadata.uid
+    }
+
+    pub fn gid(&self) -> Gid {
+        self.metadata.gid
+    }
+
+    pub fn xattrs(&self) -> &BTreeMap<Bytes, Bytes> {
+        &self.metadata.xattrs
+    }
+
+    /// See [Metadata::namespaced_xattrs]
+    pub fn namespaced_xattrs(&self) -> impl Iterator<Item = (Namespace, &[u8], &[u8])> {
+        self.metadata.namespaced_xattrs()
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.metadata.mode = mode;
+    }
+
+    pub fn set_uid(&mut self, uid: Uid) {
+        self.metadata.uid = uid;
+    }
+
+    pub fn set_gid(&mut self, gid: Gid) {
+        self.metadata.gid = gid;
+    }
+
     pub fn len(&self) -> u64 {
         self.extents
             .last_key_value()
@@ -60,12 +123,50 @@ impl File {
             .unwrap_or(0)
     }
 
+    /// Compare only the contents as they would appear to read(2), unlike
+    /// [PartialEq] which also requires the extents to be laid out the same
+    /// way.
+    pub fn content_eq(&self, other: &File) -> bool {
+        self.len() == other.len() && self.to_bytes() == other.to_bytes()
+    }
+
+    /// Compare only the mode, ownership and xattrs, ignoring the contents and
+    /// times.
+    pub fn metadata_eq(&self, other: &File) -> bool {
+        self.metadata.approx_eq(
+            &other.metadata,
+            Fields::MODE | Fields::OWNER | Fields::XATTR,
+        )
+    }
+
+    /// Number of [Extent]s (including holes) that make up this file
+    pub fn extent_count(&self) -> usize {
+        self.extents.len()
+    }
+
+    /// Number of extents per byte of file data. A file that is a single
+    /// extent has the lowest possible ratio for its size, while a file that
+    /// was built up out of lots of small writes approaches 1.0 and is a good
+    /// candidate for [File::coalesce]. Empty files have a ratio of 0.
+    pub fn fragmentation_ratio(&self) -> f64 {
+        match self.len() {
+            0 => 0.0,
+            len => self.extent_count() as f64 / len as f64,
+        }
+    }
+
     /// Copy all of the extents in this file into a single contiguous array of
     /// bytes.
     pub fn to_bytes(&self) -> Cow<'_, [u8]> {
         match self.extents.len() {
             0 => Cow::Borrowed(&[]),
-            1 => Cow::Borrowed(self.extents[&0].data()),
+            1 if matches!(
+                self.extents.get(&0),
+                Some(Extent::Owned(_) | Extent::Cloned(_))
+            ) =>
+            {
+                Cow::Borrowed(self.extents[&0].data())
+            }
             _ => {
                 let mut v = Vec::with_capacity(self.len() as usize);
                 self.reader().read_to_end(&mut v).expect("infallible");
@@ -92,29 +193,202 @@ impl File {
             .filter(|(start, e)| pos <= start + e.len())
     }
 
-    pub fn clone_range(&self, range: Range<u64>) -> Vec<Extent> {
+    /// Share the data in `range` as a list of [Extent::Cloned]s (or holes)
+    /// that can be written into another file. The range is clamped to the end
+    /// of the file, so a range that starts past the end yields no extents,
+    /// but a reversed range is an error.
+    pub fn clone_range(&self, range: Range<u64>) -> Result<Vec<Extent>> {
+        if range.start > range.end {
+            return Err(Error::InvalidRange(range));
+        }
+        let end = std::cmp::min(range.end, self.len());
         let mut v = Vec::new();
-        let (start, _) = self.extent_for_byte(range.start).expect("invalid range");
-        for (ext_start, ext) in self.extents.range(start..).take_while(|(start, ext)| {
-            [
-                std::cmp::max(**start, range.start),
-                std::cmp::min(**start + (ext.len()), range.end),
-            ]
-            .iter()
-            .any(|point| range.contains(point))
-        }) {
+        if range.start >= end {
+            return Ok(v);
+        }
+        let first = self
+            .extent_for_byte(range.start)
+            .map_or(range.start, |(start, _)| start);
+        for (ext_start, ext) in self.extents.range(first..end) {
             let start = std::cmp::max(range.start, *ext_start);
-            let end = std::cmp::min(range.end, ext_start + ext.len());
-            let cloned = Extent::Cloned(Cloned {
-                src_file: self.clone(),
-                src_range: (start, end),
-                data: ext
-                    .bytes()
-                    .slice((start - ext_start) as usize..(end - ext_start) as usize),
-            });
+            let end = std::cmp::min(end, ext_start + ext.len());
+            if start == end {
+                continue;
+            }
+            let cloned = match ext {
+                // there is no data to share for a hole
+                Extent::Hole(_) => Extent::Hole(end - start),
+                _ => Extent::Cloned(Cloned {
+                    src_file: self.clone(),
+                    src_range: (start, end),
+                    data: ext
+                        .bytes()
+                        .slice((start - ext_start) as usize..(end - ext_start) as usize),
+                }),
+            };
             v.push(cloned);
         }
-        v
+        Ok(v)
+    }
+
+    /// Iterate over the contents of `range` as the slices of data that back
+    /// each extent, without copying anything. Holes yield chunks of a static
+    /// zeroed buffer. Compressed extents have to be decompressed, so they are
+    /// the only ones that yield owned data. The range is clamped to the end of
+    /// the file.
+    pub fn slices(&self, range: Range<u64>) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        let end = std::cmp::min(range.end, self.len());
+        let first = self
+            .extent_for_byte(range.start)
+            .map_or(range.start, |(start, _)| start);
+        let mut slices = Vec::new();
+        if range.start >= end {
+            return slices.into_iter();
+        }
+        for (ext_start, ext) in self.extents.range(first..end) {
+            let start = (std::cmp::max(range.start, *ext_start) - ext_start) as usize;
+            let end = (std::cmp::min(end, ext_start + ext.len()) - ext_start) as usize;
+            match ext {
+                Extent::Hole(_) => {
+                    let zeroes = &reader::ZEROES;
+                    for chunk in (start..end).step_by(zeroes.len()) {
+                        let len = std::cmp::min(zeroes.len(), end - chunk);
+                        slices.push(Cow::Borrowed(&zeroes[..len]));
+                    }
+                }
+                #[cfg(feature = "zstd")]
+                Extent::Compressed(_) => {
+                    slices.push(Cow::Owned(ext.bytes()[start..end].to_vec()));
+                }
+                _ => slices.push(Cow::Borrowed(&ext.data()[start..end])),
+            }
+        }
+        slices.into_iter()
+    }
+
+    /// Iterate over every extent that has data as its absolute offset in the
+    /// file and its entire contents, to preserve the extent boundaries when
+    /// sending the file somewhere else. Holes are skipped, so the receiver
+    /// should [File::truncate] the file to [File::len] afterwards. Like
+    /// [File::slices], only compressed extents yield owned data.
+    pub fn chunks(&self) -> impl Iterator<Item = (u64, Cow<'_, [u8]>)> {
+        self.extents
+            .iter()
+            .filter(|(_, ext)| !matches!(ext, Extent::Hole(_)))
+            .map(|(start, ext)| {
+                let data = match ext {
+                    #[cfg(feature = "zstd")]
+                    Extent::Compressed(_) => Cow::Owned(ext.bytes().into()),
+                    _ => Cow::Borrowed(ext.data()),
+                };
+                (*start, data)
+            })
+    }
+
+    /// Merge runs of adjacent extents that can be combined (owned data with
+    /// owned data, holes with holes) into single extents. This does not
+    /// change the file contents, but reduces the number of extents after lots
+    /// of small writes. Owned data is copied into a new buffer when merged,
+    /// and cloned extents are left alone to keep their source reference.
+    pub fn coalesce(&mut self) {
+        let mut runs: Vec<(u64, u64, Vec<Extent>)> = Vec::new();
+        for (start, ext) in std::mem::take(&mut self.extents) {
+            let end = start + ext.len();
+            match runs.last_mut() {
+                Some((_, run_end, run))
+                    if *run_end == start
+                        && matches!(
+                            (run.last().expect("runs are never empty"), &ext),
+                            (Extent::Owned(_), Extent::Owned(_))
+                                | (Extent::Hole(_), Extent::Hole(_))
+                        ) =>
+                {
+                    *run_end = end;
+                    run.push(ext);
+                }
+                _ => runs.push((start, end, vec![ext])),
+            }
+        }
+        self.extents = runs
+            .into_iter()
+            .map(|(start, end, mut run)| {
+                let ext = match (run.len(), &run[0]) {
+                    (1, _) => run.pop().expect("run has one extent"),
+                    (_, Extent::Hole(_)) => Extent::Hole(end - start),
+                    _ => {
+                        let mut data = BytesMut::with_capacity((end - start) as usize);
+                        for ext in &run {
+                            data.extend_from_slice(ext.data());
+                        }
+                        Extent::Owned(data.freeze())
+                    }
+                };
+                (start, ext)
+            })
+            .collect();
+    }
+
+    /// Copy all the file data (and xattrs) into new allocations. [Bytes] are
+    /// zero-copy views, so a [File] loaded from an archive, sendstream or
+    /// mmap'd file keeps that entire buffer (or mapping) alive and sees any
+    /// changes made to a mapped file. The returned file is fully independent
+    /// of where it came from, at the cost of a copy.
+    pub fn into_owned(self) -> Self {
+        Self {
+            extents: self
+                .extents
+                .into_iter()
+                .map(|(start, ext)| (start, ext.into_owned()))
+                .collect(),
+            metadata: self.metadata.into_owned(),
+        }
+    }
+
+    /// Compress every owned extent that is at least [COMPRESS_THRESHOLD]
+    /// bytes long with zstd at the given `level`, as long as that actually
+    /// makes it smaller. Cloned extents are left alone since they share their
+    /// data with another file. Reads transparently decompress the data again.
+    #[cfg(feature = "zstd")]
+    pub fn compress_extents(&mut self, level: i32) {
+        for ext in self.extents.values_mut() {
+            if let Extent::Owned(data) = ext {
+                if data.len() >= COMPRESS_THRESHOLD {
+                    let compressed = extent::Compressed::new(data, level);
+                    if compressed.data.len() < data.len() {
+                        *ext = Extent::Compressed(compressed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replace the data in `range` with a hole, like
+    /// `fallocate(FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE)`. The file
+    /// length never changes, so any part of `range` past the end is ignored.
+    pub fn punch_hole(&mut self, range: Range<u64>) -> Result<()> {
+        if range.start > range.end {
+            return Err(Error::InvalidRange(range));
+        }
+        let end = std::cmp::min(range.end, self.len());
+        if range.start < end {
+            let mut w = self.writer();
+            w.seek(SeekFrom::Start(range.start)).expect("infallible");
+            w.write(Extent::Hole(end - range.start));
+        }
+        Ok(())
+    }
+
+    /// Preallocate `range` like a plain `fallocate`. Existing data is left
+    /// alone, but the file is grown with a hole if `range` extends past the
+    /// end of it.
+    pub fn allocate(&mut self, range: Range<u64>) -> Result<()> {
+        if range.start > range.end {
+            return Err(Error::InvalidRange(range));
+        }
+        if range.end > self.len() {
+            self.truncate(range.end);
+        }
+        Ok(())
     }
 
     /// Force the file length to be this value. Extents are shrunk or deleted if
@@ -131,7 +405,7 @@ impl File {
                     .expect("definitely exists")
                     .split_at(pos as usize);
             }
-        } else {
+        } else if len > self.len() {
             self.extents
                 .insert(self.len(), Extent::Hole(len - self.len()));
         }
@@ -154,7 +428,11 @@ impl ApproxEq for File {
 }
 
 #[cfg(test)]
-pub(self) mod tests {
+mod tests {
+    use std::io::Seek;
+    use std::io::SeekFrom;
+    use std::time::SystemTime;
+
     use super::*;
 
     pub(crate) fn test_file() -> File {
@@ -167,6 +445,154 @@ pub(self) mod tests {
         }
     }
 
+    #[test]
+    fn content_eq() {
+        let owned = File::builder().contents("Lorem ipsum").build();
+        let mut cloned = File::new_empty();
+        for range in [
+            0.."Lorem".len() as u64,
+            "Lorem".len() as u64.."Lorem ipsum".len() as u64,
+        ] {
+            for ext in test_file().clone_range(range).expect("valid range") {
+                cloned.writer().write(ext);
+            }
+        }
+        // the same contents, but split up differently
+        assert_ne!(owned, cloned);
+        assert!(owned.content_eq(&cloned));
+        assert!(owned.metadata_eq(&cloned));
+        assert!(!owned.content_eq(&test_file()));
+
+        cloned.set_mode(Mode::from_bits_truncate(0o755));
+        assert!(owned.content_eq(&cloned));
+        assert!(!owned.metadata_eq(&cloned));
+
+        let mut touched = owned.clone();
+        touched
+            .metadata
+            .set_times(SystemTime::now(), SystemTime::now(), SystemTime::now());
+        assert!(owned.metadata_eq(&touched));
+        touched
+            .metadata
+            .xattrs
+            .insert("user.demo".into(), "lorem".into());
+        assert!(!owned.metadata_eq(&touched));
+    }
+
+    #[test]
+    fn extent_metrics() {
+        let mut f = test_file();
+        assert_eq!(f.extent_count(), 2);
+        assert_eq!(
+            f.fragmentation_ratio(),
+            2.0 / "Lorem ipsum dolor sit amet".len() as f64
+        );
+        f.coalesce();
+        assert_eq!(f.extent_count(), 1);
+        assert_eq!(File::new_empty().extent_count(), 0);
+        assert_eq!(File::new_empty().fragmentation_ratio(), 0.0);
+    }
+
+    #[test]
+    fn contents_from_reader() {
+        let f = File::builder()
+            .contents_from_reader(std::io::Cursor::new("Lorem ipsum dolor sit amet"))
+            .expect("infallible")
+            .build();
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ipsum dolor sit amet");
+        assert_eq!(f.extents.len(), 1);
+    }
+
+    #[test]
+    fn metadata_accessors() {
+        let mut f = File::builder()
+            .metadata(
+                Metadata::builder()
+                    .mode(Mode::from_bits_truncate(0o644))
+                    .uid(Uid::from_raw(1000))
+                    .gid(Gid::from_raw(100))
+                    .xattr("user.lorem", "ipsum")
+                    .build(),
+            )
+            .build();
+        assert_eq!(f.mode(), Mode::from_bits_truncate(0o644));
+        assert_eq!(f.uid(), Uid::from_raw(1000));
+        assert_eq!(f.gid(), Gid::from_raw(100));
+        assert_eq!(
+            f.xattrs(),
+            &BTreeMap::from([(Bytes::from("user.lorem"), Bytes::from("ipsum"))]),
+        );
+        f.set_mode(Mode::from_bits_truncate(0o600));
+        f.set_uid(Uid::from_raw(0));
+        f.set_gid(Gid::from_raw(0));
+        assert_eq!(f.metadata().mode(), Mode::from_bits_truncate(0o600));
+        assert_eq!(f.metadata().uid(), Uid::from_raw(0));
+        assert_eq!(f.metadata().gid(), Gid::from_raw(0));
+    }
+
+    #[test]
+    fn namespaced_xattrs() {
+        let f = File::builder()
+            .metadata(
+                Metadata::builder()
+                    .xattr("user.foo", "bar")
+                    .xattr("security.selinux", "system_u:object_r:etc_t:s0")
+                    .xattr("lorem.ipsum", "dolor")
+                    .build(),
+            )
+            .build();
+        assert_eq!(
+            f.namespaced_xattrs().collect::<Vec<_>>(),
+            vec![
+                (
+                    Namespace::Security,
+                    &b"selinux"[..],
+                    &b"system_u:object_r:etc_t:s0"[..]
+                ),
+                (Namespace::User, &b"foo"[..], &b"bar"[..]),
+            ],
+        );
+    }
+
+    #[test]
+    fn slices() {
+        let mut f = test_file();
+        f.truncate(f.len() + 5000);
+        f.write_at(f.len(), b"consectetur")
+            .expect("failed to write");
+        let contents = f.to_bytes().into_owned();
+        for range in [0..f.len(), 3..8, 6..20, 20..5030, 26..5026, 5020..10000] {
+            let sliced = f.slices(range.clone()).collect::<Vec<_>>().concat();
+            let end = std::cmp::min(range.end, f.len()) as usize;
+            assert_eq!(sliced, &contents[range.start as usize..end], "{range:?}");
+        }
+        assert_eq!(f.slices(10000..20000).count(), 0);
+        // data is not copied
+        let slice = f.slices(6..11).next().expect("one slice");
+        assert!(matches!(slice, Cow::Borrowed(_)));
+        assert_eq!(slice.as_ptr(), f.extents[&0].data()[6..].as_ptr());
+    }
+
+    #[test]
+    fn chunks() {
+        let mut f = test_file();
+        f.truncate(f.len() + 5000);
+        f.write_at(f.len(), b"consectetur")
+            .expect("failed to write");
+        let chunks: Vec<_> = f.chunks().collect();
+        assert_eq!(
+            chunks.iter().map(|(off, _)| *off).collect::<Vec<_>>(),
+            vec![0, 11, 5026],
+        );
+        let mut rebuilt = File::new_empty();
+        for (offset, data) in chunks {
+            rebuilt.write_at(offset, &data).expect("failed to write");
+        }
+        rebuilt.truncate(f.len());
+        assert_eq!(rebuilt.to_bytes(), f.to_bytes());
+        assert_eq!(rebuilt.extent_count(), f.extent_count());
+    }
+
     #[test]
     fn to_bytes() {
         let f = test_file();
@@ -181,7 +607,8 @@ pub(self) mod tests {
     fn cloning() {
         let f = test_file();
         let extents = f
-            .clone_range("Lorem ".len() as u64.."Lorem ".len() as u64 + "ipsum dolor".len() as u64);
+            .clone_range("Lorem ".len() as u64.."Lorem ".len() as u64 + "ipsum dolor".len() as u64)
+            .expect("valid range");
         let mut f2 = File::new_empty();
         let mut w = f2.writer();
         assert_eq!(extents.len(), 2, "{extents:?}");
@@ -195,6 +622,88 @@ pub(self) mod tests {
         );
     }
 
+    #[test]
+    fn holes() {
+        let f = File {
+            extents: BTreeMap::from([
+                (0, "Lorem".into()),
+                (5, Extent::Hole(3)),
+                (8, "ipsum".into()),
+            ]),
+            metadata: Default::default(),
+        };
+        assert_eq!(f.len(), 13);
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem\0\0\0ipsum");
+
+        let extents = f.clone_range(3..10).expect("valid range");
+        let mut f2 = File::new_empty();
+        let mut w = f2.writer();
+        for ex in extents {
+            w.write(ex)
+        }
+        assert_eq!(f2.to_bytes().as_ref(), b"em\0\0\0ip");
+        assert_eq!(f2.extents[&2], Extent::Hole(3));
+    }
+
+    #[test]
+    fn clone_range_bounds() {
+        let f = test_file();
+        let len = f.len();
+        assert_eq!(f.clone_range(len..len + 10), Ok(vec![]));
+        assert_eq!(f.clone_range(len + 5..len + 10), Ok(vec![]));
+        assert_eq!(f.clone_range(3..3), Ok(vec![]));
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 10..3;
+        assert_eq!(
+            f.clone_range(reversed.clone()),
+            Err(Error::InvalidRange(reversed))
+        );
+        // clamped to the end of the file
+        let extents = f.clone_range(6..len + 10).expect("valid range");
+        let mut f2 = File::new_empty();
+        let mut w = f2.writer();
+        for ex in extents {
+            w.write(ex)
+        }
+        assert_eq!(f2.to_bytes().as_ref(), b"ipsum dolor sit amet");
+    }
+
+    #[test]
+    fn punch_hole() {
+        let mut f = test_file();
+        f.punch_hole(6..11).expect("valid range");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem \0\0\0\0\0 dolor sit amet");
+        assert_eq!(
+            f.extents,
+            BTreeMap::from([
+                (0, "Lorem ".into()),
+                (6, Extent::Hole(5)),
+                (11, " dolor sit amet".into()),
+            ]),
+        );
+        // punching past the end never grows the file
+        f.punch_hole(20..100).expect("valid range");
+        assert_eq!(f.len(), 26);
+        assert_eq!(f.extents[&20], Extent::Hole(6));
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 10..3;
+        assert_eq!(
+            f.punch_hole(reversed.clone()),
+            Err(Error::InvalidRange(reversed))
+        );
+    }
+
+    #[test]
+    fn allocate() {
+        let mut f = test_file();
+        f.allocate(0..10).expect("valid range");
+        assert_eq!(f, test_file());
+        f.allocate(20..100).expect("valid range");
+        assert_eq!(f.len(), 100);
+        assert_eq!(f.extents[&26], Extent::Hole(74));
+        assert_eq!(&f.to_bytes()[..26], test_file().to_bytes().as_ref());
+    }
+
     #[test]
     fn truncate() {
         let mut f = test_file();
@@ -206,5 +715,86 @@ pub(self) mod tests {
         f.truncate(("Lorem ipsum dolor sit amet".len() + 128) as u64);
         assert_eq!(f.len(), ("Lorem ipsum dolor sit amet".len() + 128) as u64);
         assert_eq!(f.extents.len(), 3);
+        let mut expected = b"Lorem ipsum dolor sit amet".to_vec();
+        expected.resize(expected.len() + 128, 0);
+        assert_eq!(f.to_bytes().as_ref(), expected);
+    }
+
+    #[test]
+    fn truncate_on_extent_boundary() {
+        let mut f = test_file();
+        f.truncate("Lorem ipsum".len() as u64);
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ipsum");
+        assert_eq!(f.extents.len(), 1);
+
+        let mut f = test_file();
+        f.truncate(f.len());
+        assert_eq!(f, test_file());
+    }
+
+    #[test]
+    fn truncate_to_zero() {
+        let mut f = test_file();
+        f.truncate(0);
+        assert_eq!(f.len(), 0);
+        assert!(f.is_empty());
+        assert_eq!(f.to_bytes().as_ref(), b"");
+    }
+
+    #[test]
+    fn coalesce() {
+        let mut f = File::new_empty();
+        let mut w = f.writer();
+        for word in "Lorem ipsum dolor sit amet".split_inclusive(' ') {
+            w.write(word.to_owned());
+        }
+        w.seek(SeekFrom::Current(2)).expect("infallible");
+        for word in ["consectetur", " ", "adipiscing"] {
+            w.write(word);
+        }
+        f.truncate(f.len() + 2);
+        f.truncate(f.len() + 2);
+        let before = f.to_bytes().into_owned();
+        assert_eq!(f.extents.len(), 11);
+        f.coalesce();
+        assert_eq!(f.to_bytes().as_ref(), before);
+        assert_eq!(
+            f.extents,
+            BTreeMap::from([
+                (0, "Lorem ipsum dolor sit amet".into()),
+                (26, Extent::Hole(2)),
+                (28, "consectetur adipiscing".into()),
+                (50, Extent::Hole(4)),
+            ]),
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compress_extents() {
+        let lorem = "Lorem ipsum dolor sit amet\n".repeat(1000);
+        let mut f = File::builder().contents(lorem.clone()).build();
+        let mut w = f.writer();
+        w.write("consectetur adipiscing elit\n");
+        f.truncate(f.len() + 10);
+        let before = f.to_bytes().into_owned();
+        let memory = |f: &File| -> usize { f.extents.values().map(|e| e.data().len()).sum() };
+        let uncompressed = memory(&f);
+
+        f.compress_extents(3);
+        assert!(matches!(f.extents[&0], Extent::Compressed(_)));
+        // too small to bother with
+        assert!(matches!(f.extents[&(lorem.len() as u64)], Extent::Owned(_)));
+        assert!(memory(&f) < uncompressed / 10);
+        assert_eq!(f.to_bytes().as_ref(), before);
+        let mut buf = [0; 11];
+        assert_eq!(f.read_at(&mut buf, 6), buf.len());
+        assert_eq!(&buf, b"ipsum dolor");
+
+        // writes still work on top of compressed extents
+        let mut w = f.writer();
+        w.seek(SeekFrom::Start(6)).expect("infallible");
+        w.write("IPSUM");
+        assert_eq!(&f.to_bytes()[..17], b"Lorem IPSUM dolor");
     }
 }
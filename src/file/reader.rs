@@ -1,34 +1,90 @@
+use std::io::BufRead;
+use std::io::Error;
+use std::io::ErrorKind;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 
+use super::Extent;
 use super::File;
 
 /// [Read] implementation for [File]
 pub struct Reader<'r> {
     file: &'r File,
     pos: u64,
+    /// Decompressed contents of the last compressed extent that was read,
+    /// keyed by the start of the extent
+    #[cfg(feature = "zstd")]
+    decompressed: Option<(u64, bytes::Bytes)>,
 }
 
 impl File {
     pub fn reader(&self) -> Reader<'_> {
-        Reader { file: self, pos: 0 }
+        Reader {
+            file: self,
+            pos: 0,
+            #[cfg(feature = "zstd")]
+            decompressed: None,
+        }
+    }
+
+    /// Fill `buf` with the file contents starting at `offset`, returning the
+    /// number of bytes read. This is only less than the length of `buf` if
+    /// the end of the file was reached.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> usize {
+        let mut reader = self.reader();
+        reader.pos = offset;
+        let mut read = 0;
+        while read < buf.len() {
+            match reader.read(&mut buf[read..]).expect("infallible") {
+                0 => break,
+                n => read += n,
+            }
+        }
+        read
     }
 }
 
+/// Holes have no backing data, so [BufRead::fill_buf] hands out chunks of
+/// this instead
+pub(super) static ZEROES: [u8; 4096] = [0; 4096];
+
 impl<'r> Read for Reader<'r> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.fill_buf()?;
+        let read_len = std::cmp::min(buf.len(), data.len());
+        buf[..read_len].copy_from_slice(&data[..read_len]);
+        self.consume(read_len);
+        Ok(read_len)
+    }
+}
+
+impl<'r> BufRead for Reader<'r> {
+    /// Return the rest of the extent at the current position without
+    /// copying it (unless it has to be decompressed).
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
         if self.pos >= self.file.len() {
-            return Ok(0);
+            return Ok(&[]);
         }
         match self.file.extent_for_byte(self.pos) {
             Some((extent_start, ext)) => {
-                let remaining_in_extent = extent_start + ext.len() - self.pos;
-                let read_len = std::cmp::min(buf.len(), remaining_in_extent as usize);
                 let extent_offset = self.pos - extent_start;
-                buf[..read_len].copy_from_slice(
-                    &ext.data()[extent_offset as usize..extent_offset as usize + read_len],
-                );
-                self.pos += read_len as u64;
-                Ok(read_len)
+                Ok(match ext {
+                    Extent::Hole(len) => {
+                        let remaining = std::cmp::min(len - extent_offset, ZEROES.len() as u64);
+                        &ZEROES[..remaining as usize]
+                    }
+                    #[cfg(feature = "zstd")]
+                    Extent::Compressed(c) => {
+                        if !matches!(&self.decompressed, Some((start, _)) if *start == extent_start)
+                        {
+                            self.decompressed = Some((extent_start, c.decompress()));
+                        }
+                        let (_, data) = self.decompressed.as_ref().expect("just decompressed");
+                        &data[extent_offset as usize..]
+                    }
+                    _ => &ext.data()[extent_offset as usize..],
+                })
             }
             // this is impossible due to the length check above
             None => {
@@ -39,12 +95,40 @@ impl<'r> Read for Reader<'r> {
             }
         }
     }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+impl<'r> Seek for Reader<'r> {
+    /// Seeking past the end of the file is allowed, subsequent reads will
+    /// simply return EOF.
+    fn seek(&mut self, seek: SeekFrom) -> std::io::Result<u64> {
+        let (base_pos, offset) = match seek {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.file.len(), n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        match base_pos.checked_add_signed(offset) {
+            Some(n) => {
+                self.pos = n;
+                Ok(self.pos)
+            }
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Seek;
-    use std::io::SeekFrom;
+    use std::collections::BTreeMap;
 
     use super::super::tests::test_file;
     use super::*;
@@ -75,4 +159,83 @@ mod tests {
         );
         assert_eq!(f.extents.len(), 2);
     }
+
+    #[test]
+    fn seek() {
+        let f = File {
+            extents: BTreeMap::from([
+                (0, "Lorem ipsum".into()),
+                (11, Extent::Hole(4)),
+                (15, " dolor sit amet".into()),
+            ]),
+            metadata: Default::default(),
+        };
+        let bytes = f.to_bytes();
+        let mut r = f.reader();
+        for (seek, pos) in [
+            (SeekFrom::Start(6), 6),
+            (SeekFrom::Current(-21), 9),
+            (SeekFrom::Start(12), 12),
+            (SeekFrom::End(-4), f.len() - 4),
+        ] {
+            assert_eq!(r.seek(seek).expect("valid seek"), pos);
+            let mut buf = Vec::new();
+            r.read_to_end(&mut buf).expect("infallible");
+            assert_eq!(buf, &bytes[pos as usize..], "{seek:?}");
+        }
+
+        assert!(r.seek(SeekFrom::Current(-(f.len() as i64) - 1)).is_err());
+        assert_eq!(r.seek(SeekFrom::End(10)).expect("valid seek"), f.len() + 10);
+        let mut buf = Vec::new();
+        assert_eq!(r.read_to_end(&mut buf).expect("infallible"), 0);
+    }
+
+    #[test]
+    fn read_at() {
+        let f = File {
+            extents: BTreeMap::from([
+                (0, "Lorem ipsum".into()),
+                (11, Extent::Hole(4)),
+                (15, " dolor sit amet".into()),
+            ]),
+            metadata: Default::default(),
+        };
+        let bytes = f.to_bytes();
+        for (offset, len) in [(0, 5), (6, 10), (12, 2), (13, 8), (20, 100), (40, 1)] {
+            let mut buf = vec![0xff; len];
+            let read = f.read_at(&mut buf, offset as u64);
+            let expected = &bytes
+                [std::cmp::min(offset, bytes.len())..std::cmp::min(offset + len, bytes.len())];
+            assert_eq!(read, expected.len(), "offset={offset} len={len}");
+            assert_eq!(&buf[..read], expected, "offset={offset} len={len}");
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let f = File {
+            extents: BTreeMap::from([
+                (0, "Lorem ipsum\ndol".into()),
+                (15, "or sit\n".into()),
+                (22, Extent::Hole(2)),
+                (24, "\namet\n".into()),
+            ]),
+            metadata: Default::default(),
+        };
+        let lines: Vec<String> = f
+            .reader()
+            .lines()
+            .collect::<std::io::Result<_>>()
+            .expect("infallible");
+        assert_eq!(lines, vec!["Lorem ipsum", "dolor sit", "\0\0", "amet"]);
+
+        let mut r = f.reader();
+        let mut buf = Vec::new();
+        r.read_until(b' ', &mut buf).expect("infallible");
+        assert_eq!(buf, b"Lorem ");
+        // the buffer points directly at the extent data
+        let rest = r.fill_buf().expect("infallible");
+        assert_eq!(rest, b"ipsum\ndol");
+        assert_eq!(rest.as_ptr(), f.extents[&0].data()[6..].as_ptr());
+    }
 }
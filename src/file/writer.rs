@@ -3,6 +3,9 @@ use std::io::ErrorKind;
 use std::io::Result;
 use std::io::Seek;
 use std::io::SeekFrom;
+use std::ops::Range;
+
+use bytes::Bytes;
 
 use super::Extent;
 use super::File;
@@ -22,37 +25,237 @@ impl File {
             file: self,
         }
     }
+
+    /// Overwrite the file contents starting at `offset` with a copy of
+    /// `data`. Writing past the end of the file grows it, leaving a hole
+    /// between the old end and `offset`.
+    pub fn write_at(&mut self, offset: u64, data: &[u8]) -> super::Result<()> {
+        let mut writer = Writer {
+            file: self,
+            pos: offset,
+        };
+        writer.check_write(data.len() as u64)?;
+        writer.write(Bytes::copy_from_slice(data));
+        Ok(())
+    }
+
+    /// Reflink `range` of `src` into this file at `offset`, like the
+    /// `FICLONERANGE` ioctl. The new extents are [Extent::Cloned]s that share
+    /// the data with `src` without copying it (see [File::clone_range]). To
+    /// clone within the same file, pass a clone of it as `src`, which is
+    /// cheap since the data is shared.
+    pub fn clone_range_from(
+        &mut self,
+        offset: u64,
+        src: &File,
+        range: Range<u64>,
+    ) -> super::Result<()> {
+        let extents = src.clone_range(range)?;
+        let mut writer = Writer {
+            file: self,
+            pos: offset,
+        };
+        writer.check_write(extents.iter().map(Extent::len).sum())?;
+        for ext in extents {
+            writer.write(ext);
+        }
+        Ok(())
+    }
+
+    /// Add `data` to the end of the file without touching any of the existing
+    /// extents. When both `data` and the last extent are owned, and nothing
+    /// else shares the last extent's buffer, `data` is copied onto the end of
+    /// it instead of adding a new extent. Holes are merged the same way.
+    pub fn append(&mut self, data: impl Into<Extent>) -> super::Result<()> {
+        let data = data.into();
+        let end = self.len();
+        if data.is_empty() {
+            return Ok(());
+        }
+        if end.checked_add(data.len()).is_none() {
+            return Err(super::Error::TooLarge {
+                offset: end,
+                len: data.len(),
+            });
+        }
+        if let Some(mut last) = self.extents.last_entry() {
+            match (last.get_mut(), &data) {
+                (Extent::Hole(len), Extent::Hole(more)) => {
+                    *len += more;
+                    return Ok(());
+                }
+                (Extent::Owned(buf), Extent::Owned(more)) => {
+                    match std::mem::take(buf).try_into_mut() {
+                        Ok(mut unique) => {
+                            unique.extend_from_slice(more);
+                            *buf = unique.freeze();
+                            return Ok(());
+                        }
+                        Err(shared) => *buf = shared,
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.extents.insert(end, data);
+        Ok(())
+    }
+
+    /// Split the file in two at `at`, like [Vec::split_off]. `self` keeps the
+    /// data before `at` and the returned [File] (with the same metadata) has
+    /// everything after it. An extent that straddles `at` is split in two.
+    /// Panics if `at` is past the end of the file.
+    pub fn split_off(&mut self, at: u64) -> File {
+        assert!(
+            at <= self.len(),
+            "split_off at {at} is past the end of the file ({})",
+            self.len(),
+        );
+        self.split_extent_at(at);
+        File {
+            extents: self
+                .extents
+                .split_off(&at)
+                .into_iter()
+                .map(|(start, ext)| (start - at, ext))
+                .collect(),
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// Insert a copy of `data` at `offset`, shifting everything after it
+    /// forward instead of overwriting it like [File::write_at] does. Inserting
+    /// past the end of the file leaves a hole between the old end and
+    /// `offset`.
+    pub fn insert_bytes(&mut self, offset: u64, data: &[u8]) -> super::Result<()> {
+        let len = data.len() as u64;
+        if std::cmp::max(offset, self.len()).checked_add(len).is_none() {
+            return Err(super::Error::TooLarge { offset, len });
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        if offset > self.len() {
+            self.truncate(offset);
+        }
+        self.split_extent_at(offset);
+        let tail = self.extents.split_off(&offset);
+        self.extents
+            .insert(offset, Extent::Owned(Bytes::copy_from_slice(data)));
+        self.extents
+            .extend(tail.into_iter().map(|(start, ext)| (start + len, ext)));
+        Ok(())
+    }
+
+    /// Delete the bytes in `range`, shifting everything after it backward and
+    /// shrinking the file. Extents that are partially in the range are
+    /// trimmed. The range is clamped to the end of the file, but a reversed
+    /// range is an error.
+    pub fn remove_range(&mut self, range: Range<u64>) -> super::Result<()> {
+        if range.start > range.end {
+            return Err(super::Error::InvalidRange(range));
+        }
+        let end = std::cmp::min(range.end, self.len());
+        if range.start >= end {
+            return Ok(());
+        }
+        self.split_extent_at(range.start);
+        self.split_extent_at(end);
+        let tail = self.extents.split_off(&end);
+        self.extents.split_off(&range.start);
+        let removed = end - range.start;
+        self.extents
+            .extend(tail.into_iter().map(|(start, ext)| (start - removed, ext)));
+        Ok(())
+    }
+
+    /// Make sure that an extent starts exactly at `pos` (unless `pos` is at
+    /// or past the end of the file), splitting the extent that contains it.
+    fn split_extent_at(&mut self, pos: u64) {
+        if let Some((start, ext)) = self.extent_for_byte_mut(pos) {
+            if start < pos && pos < start + ext.len() {
+                let right = ext.split_at((pos - start) as usize);
+                self.extents.insert(pos, right);
+            }
+        }
+    }
 }
 
 impl<'r> Writer<'r> {
+    /// Make sure that `len` bytes can be written at the current position
+    pub(crate) fn check_write(&self, len: u64) -> super::Result<()> {
+        match self.pos.checked_add(len) {
+            Some(_) => Ok(()),
+            None => Err(super::Error::TooLarge {
+                offset: self.pos,
+                len,
+            }),
+        }
+    }
+
     /// Write some bytes into the [File] without making a copy of the underlying
     /// data like the [std::io::Write] implementation is forced to do.
+    /// Panics if the write would grow the file past [u64::MAX] bytes, which
+    /// can only happen after seeking there.
     pub fn write<E>(&mut self, extent: E)
     where
         E: Into<Extent>,
     {
         let extent = extent.into();
         let ext_len = extent.len();
+        if ext_len == 0 {
+            return;
+        }
         let write_start = self.pos;
         let write_end = write_start + ext_len;
+        let file_len = self.file.len();
+        if write_start > file_len {
+            // writing past the end of the file leaves a hole in between
+            self.file
+                .extents
+                .insert(file_len, Extent::Hole(write_start - file_len));
+        }
+        // keep the tail of an extent that extends beyond the end of this write
         if let Some((existing_start, existing_ext)) = self.file.extent_for_byte_mut(write_end) {
-            let right = existing_ext.split_at((write_end - existing_start) as usize);
-            self.file.extents.insert(write_end, right);
-        }
-        if let Some((existing_start, existing_ext)) = self.file.extent_for_byte_mut(self.pos) {
-            // TODO: handle overlapping writes after implementing seek
-            // shrink this extent to end where the overlap is
-            let split_idx = write_start - existing_start;
-            let right_split_idx = write_end - split_idx;
-            let mut right = existing_ext.split_at(split_idx as usize);
-            if right_split_idx < right.len() {
-                right.split_at(right_split_idx as usize);
-                let right_start = write_end;
-                self.file.extents.insert(right_start, right);
+            if existing_start < write_end && write_end < existing_start + existing_ext.len() {
+                let right = existing_ext.split_at((write_end - existing_start) as usize);
+                self.file.extents.insert(write_end, right);
             }
         }
-        self.file.extents.insert(self.pos, extent);
-        self.pos += ext_len;
+        // shrink an extent that starts before this write to end where the
+        // overlap begins
+        if let Some((existing_start, existing_ext)) = self.file.extent_for_byte_mut(write_start) {
+            if existing_start < write_start && write_start < existing_start + existing_ext.len() {
+                existing_ext.split_at((write_start - existing_start) as usize);
+            }
+        }
+        // anything left that starts within the written range is completely
+        // replaced by this write
+        let overwritten: Vec<u64> = self
+            .file
+            .extents
+            .range(write_start..write_end)
+            .map(|(start, _)| *start)
+            .collect();
+        for start in overwritten {
+            self.file.extents.remove(&start);
+        }
+        self.file.extents.insert(write_start, extent);
+        self.pos = write_end;
+    }
+}
+
+/// Each write copies `buf` into a new [Extent::Owned], prefer
+/// [Writer::write] when the data is already in a [Bytes].
+impl<'r> std::io::Write for Writer<'r> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.check_write(buf.len() as u64)?;
+        Writer::write(self, Bytes::copy_from_slice(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
     }
 }
 
@@ -84,6 +287,165 @@ mod tests {
     use std::collections::BTreeMap;
 
     use super::*;
+    use crate::file::tests::test_file;
+
+    #[test]
+    fn append() {
+        let mut f = File::new_empty();
+        f.append(b"Lorem".to_vec()).expect("failed to append");
+        f.append(b" ipsum".to_vec()).expect("failed to append");
+        f.append(Extent::Hole(1)).expect("failed to append");
+        f.append(Extent::Hole(2)).expect("failed to append");
+        // static data is never unique, so it can't be appended to
+        f.append("dolor").expect("failed to append");
+        f.append(b" sit".to_vec()).expect("failed to append");
+        f.append("").expect("failed to append");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ipsum\0\0\0dolor sit");
+        assert_eq!(
+            f.extents,
+            BTreeMap::from([
+                (0, "Lorem ipsum".into()),
+                (11, Extent::Hole(3)),
+                (14, "dolor".into()),
+                (19, " sit".into()),
+            ])
+        );
+
+        // data that is shared with something else is not modified
+        let shared = Bytes::from(b"Lorem".to_vec());
+        let mut f = File::builder().contents(shared.clone()).build();
+        f.append(b" ipsum".to_vec()).expect("failed to append");
+        assert_eq!(shared, "Lorem");
+        assert_eq!(f.extents.len(), 2);
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ipsum");
+    }
+
+    #[test]
+    fn split_off() {
+        let mut left = test_file();
+        let right = left.split_off("Lorem".len() as u64);
+        assert_eq!(left.to_bytes().as_ref(), b"Lorem");
+        assert_eq!(right.to_bytes().as_ref(), b" ipsum dolor sit amet");
+        assert_eq!(
+            right.extents,
+            BTreeMap::from([(0, " ipsum".into()), (6, " dolor sit amet".into())])
+        );
+        assert_eq!(left.extents, BTreeMap::from([(0, "Lorem".into())]));
+
+        // on an extent boundary nothing has to be split
+        let mut left = test_file();
+        let right = left.split_off("Lorem ipsum".len() as u64);
+        assert_eq!(left.extents.len(), 1);
+        assert_eq!(
+            right.extents,
+            BTreeMap::from([(0, " dolor sit amet".into())])
+        );
+
+        let mut f = test_file();
+        assert!(f.split_off(f.len()).is_empty());
+        assert_eq!(f, test_file());
+        let all = f.split_off(0);
+        assert_eq!(f.len(), 0);
+        assert_eq!(all, test_file());
+    }
+
+    #[test]
+    fn insert_bytes() {
+        let mut f = test_file();
+        f.insert_bytes("Lorem ".len() as u64, b"dolor ")
+            .expect("failed to insert");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem dolor ipsum dolor sit amet");
+        assert_eq!(f.len(), test_file().len() + 6);
+        assert_eq!(
+            f.extents,
+            BTreeMap::from([
+                (0, "Lorem ".into()),
+                (6, "dolor ".into()),
+                (12, "ipsum".into()),
+                (17, " dolor sit amet".into()),
+            ])
+        );
+
+        f.insert_bytes(0, b">").expect("failed to insert");
+        f.insert_bytes(f.len(), b"<").expect("failed to insert");
+        f.insert_bytes(f.len() + 1, b"!").expect("failed to insert");
+        f.insert_bytes(3, b"").expect("failed to insert");
+        assert_eq!(
+            f.to_bytes().as_ref(),
+            b">Lorem dolor ipsum dolor sit amet<\0!"
+        );
+        assert!(f.insert_bytes(u64::MAX, b"!").is_err());
+    }
+
+    #[test]
+    fn remove_range() {
+        let mut f = test_file();
+        f.remove_range(3.."Lorem ipsum dolor".len() as u64)
+            .expect("failed to remove");
+        assert_eq!(f.to_bytes().as_ref(), b"Lor sit amet");
+        assert_eq!(f.len(), 12);
+        assert_eq!(
+            f.extents,
+            BTreeMap::from([(0, "Lor".into()), (3, " sit amet".into())])
+        );
+
+        // entirely contained extents are dropped
+        let mut f = test_file();
+        f.remove_range(0.."Lorem ipsum".len() as u64)
+            .expect("failed to remove");
+        assert_eq!(f.extents, BTreeMap::from([(0, " dolor sit amet".into())]));
+
+        let mut f = test_file();
+        f.remove_range(6..1000).expect("failed to remove");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ");
+        f.remove_range(100..200).expect("failed to remove");
+        f.remove_range(2..2).expect("failed to remove");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ");
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 4..2;
+        assert!(f.remove_range(reversed).is_err());
+
+        let mut f = test_file();
+        f.insert_bytes(5, b", consectetur")
+            .expect("failed to insert");
+        f.remove_range(5..18).expect("failed to remove");
+        assert!(f.content_eq(&test_file()));
+    }
+
+    #[test]
+    fn clone_range_from() {
+        let src = test_file();
+        let mut dst = File::builder().contents("consectetur").build();
+        dst.clone_range_from(3, &src, 6.."Lorem ipsum dolor".len() as u64)
+            .expect("failed to clone");
+        assert_eq!(dst.to_bytes().as_ref(), b"conipsum dolor");
+        let (src_ranges, ptrs): (Vec<_>, Vec<_>) = dst
+            .extents
+            .values()
+            .filter_map(|ext| match ext {
+                Extent::Cloned(c) => Some((c.src_range(), c.data.as_ptr())),
+                _ => None,
+            })
+            .unzip();
+        assert_eq!(src_ranges, vec![(6, 11), (11, 17)]);
+        // the data is shared, not copied
+        assert_eq!(ptrs[0], src.extents[&0].data()[6..].as_ptr());
+        assert_eq!(ptrs[1], src.extents[&11].data().as_ptr());
+
+        // past the end of the destination leaves a hole
+        dst.clone_range_from(16, &src, 0..5)
+            .expect("failed to clone");
+        assert_eq!(dst.to_bytes().as_ref(), b"conipsum dolor\0\0Lorem");
+        // cloning from (a copy of) the same file
+        let copy = dst.clone();
+        dst.clone_range_from(0, &copy, 18..21)
+            .expect("failed to clone");
+        assert_eq!(dst.to_bytes().as_ref(), b"remipsum dolor\0\0Lorem");
+        assert!(dst.clone_range_from(u64::MAX, &src, 0..5).is_err());
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 5..0;
+        assert!(dst.clone_range_from(0, &src, reversed).is_err());
+    }
 
     #[test]
     fn appending_writes() {
@@ -137,4 +499,155 @@ mod tests {
             ]),
         );
     }
+
+    #[test]
+    fn overwrite_multiple_extents() {
+        let mut f = File::new_empty();
+        let mut w = f.writer();
+        w.write("Lorem ");
+        w.write("lorem ");
+        w.write("lorem ");
+        w.write("sit amet");
+        w.seek(SeekFrom::Start("Lorem ".len() as u64))
+            .expect("infallible");
+        w.write("ipsum dolor ");
+        assert_eq!(
+            std::str::from_utf8(&f.to_bytes()).expect("valid"),
+            "Lorem ipsum dolor sit amet",
+            "{f:?}",
+        );
+        assert_eq!(
+            &f.extents,
+            &BTreeMap::from([
+                (0, "Lorem ".into()),
+                ("Lorem ".len() as u64, "ipsum dolor ".into()),
+                ("Lorem ipsum dolor ".len() as u64, "sit amet".into()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn write_past_end() {
+        let mut f = File::new_empty();
+        let mut w = f.writer();
+        w.write("Lorem");
+        w.seek(SeekFrom::Current(3)).expect("infallible");
+        w.write("ipsum");
+        assert_eq!(f.len(), "Lorem".len() as u64 + 3 + "ipsum".len() as u64);
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem\0\0\0ipsum");
+        assert_eq!(
+            &f.extents,
+            &BTreeMap::from([
+                (0, "Lorem".into()),
+                ("Lorem".len() as u64, Extent::Hole(3)),
+                ("Lorem".len() as u64 + 3, "ipsum".into()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn write_at_within_extent() {
+        let mut f = File::builder().contents("Lorem lorem dolor").build();
+        f.write_at("Lorem ".len() as u64, b"ipsum")
+            .expect("failed to write");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ipsum dolor");
+        assert_eq!(
+            &f.extents,
+            &BTreeMap::from([
+                (0, "Lorem ".into()),
+                ("Lorem ".len() as u64, "ipsum".into()),
+                ("Lorem ipsum".len() as u64, " dolor".into()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn write_at_across_extents() {
+        let mut f = File::new_empty();
+        let mut w = f.writer();
+        w.write("Lorem lorem");
+        w.write(" lorem sit amet");
+        f.write_at("Lorem ".len() as u64, b"ipsum dolor")
+            .expect("failed to write");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem ipsum dolor sit amet");
+        assert_eq!(
+            &f.extents,
+            &BTreeMap::from([
+                (0, "Lorem ".into()),
+                ("Lorem ".len() as u64, "ipsum dolor".into()),
+                ("Lorem ipsum dolor".len() as u64, " sit amet".into()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn write_at_past_end() {
+        let mut f = File::builder().contents("Lorem").build();
+        f.write_at("Lorem".len() as u64 + 3, b"ipsum")
+            .expect("failed to write");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem\0\0\0ipsum");
+        assert_eq!(
+            &f.extents,
+            &BTreeMap::from([
+                (0, "Lorem".into()),
+                ("Lorem".len() as u64, Extent::Hole(3)),
+                ("Lorem".len() as u64 + 3, "ipsum".into()),
+            ]),
+        );
+
+        // overwriting the tail and extending in the same write
+        f.write_at(f.len() - 2, b"um dolor")
+            .expect("failed to write");
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem\0\0\0ipsum dolor");
+    }
+
+    /// Extents must be contiguous and never overlap
+    fn assert_contiguous(f: &File) {
+        let mut expected_start = 0;
+        for (start, ext) in &f.extents {
+            assert_eq!(*start, expected_start, "{f:?}");
+            expected_start = start + ext.len();
+        }
+        assert_eq!(expected_start, f.len());
+    }
+
+    #[test]
+    fn io_write_seek() {
+        use std::io::Write;
+
+        let mut f = File::new_empty();
+        let mut w = f.writer();
+        w.write_all(b"Lorem").expect("infallible");
+        w.seek(SeekFrom::Start(12)).expect("infallible");
+        w.write_all(b"dolor").expect("infallible");
+        w.seek(SeekFrom::Start(5)).expect("infallible");
+        w.write_all(b" ipsum ").expect("infallible");
+        w.seek(SeekFrom::Start(3)).expect("infallible");
+        w.write_all(b"EM IP").expect("infallible");
+        w.seek(SeekFrom::End(0)).expect("infallible");
+        w.write_all(b" sit amet").expect("infallible");
+        assert_eq!(f.to_bytes().as_ref(), b"LorEM IPsum dolor sit amet");
+        assert_contiguous(&f);
+    }
+
+    #[test]
+    fn write_too_large() {
+        let mut f = File::builder().contents("Lorem").build();
+        assert_eq!(
+            f.write_at(u64::MAX - 2, b"ipsum"),
+            Err(crate::file::Error::TooLarge {
+                offset: u64::MAX - 2,
+                len: 5
+            }),
+        );
+        let mut w = f.writer();
+        w.seek(SeekFrom::Start(u64::MAX)).expect("infallible");
+        assert_eq!(
+            std::io::Write::write(&mut w, b"ipsum")
+                .expect_err("too large")
+                .kind(),
+            ErrorKind::FileTooLarge,
+        );
+        assert_eq!(f.to_bytes().as_ref(), b"Lorem");
+    }
 }
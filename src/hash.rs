@@ -0,0 +1,145 @@
+use std::os::unix::ffi::OsStrExt;
+use std::time::SystemTime;
+
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::entry::Entry;
+use crate::entry::Metadata;
+use crate::file::File;
+use crate::Filesystem;
+
+/// Hash a variable-length field with its length first, so that the
+/// boundaries between consecutive fields are unambiguous.
+fn update_field(hasher: &mut Sha256, data: &[u8]) {
+    hasher.update((data.len() as u64).to_le_bytes());
+    hasher.update(data);
+}
+
+fn update_time(hasher: &mut Sha256, time: SystemTime) {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.update(since_epoch.as_secs().to_le_bytes());
+    hasher.update(since_epoch.subsec_nanos().to_le_bytes());
+}
+
+impl Metadata {
+    #[deny(unused_variables)]
+    fn update_hash(&self, hasher: &mut Sha256) {
+        let Self {
+            mode,
+            uid,
+            gid,
+            xattrs,
+            created,
+            accessed,
+            modified,
+        } = self;
+        hasher.update(mode.bits().to_le_bytes());
+        hasher.update(uid.to_le_bytes());
+        hasher.update(gid.to_le_bytes());
+        // xattrs is a BTreeMap, so this order is stable
+        hasher.update((xattrs.len() as u64).to_le_bytes());
+        for (name, value) in xattrs {
+            update_field(hasher, name);
+            update_field(hasher, value);
+        }
+        update_time(hasher, *created);
+        update_time(hasher, *accessed);
+        update_time(hasher, *modified);
+    }
+}
+
+impl File {
+    /// SHA-256 of the file contents as they would appear to read(2). Holes
+    /// are hashed as zeroes. Metadata is not included.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut self.reader(), &mut hasher).expect("infallible");
+        hasher.finalize().into()
+    }
+}
+
+impl Filesystem {
+    /// SHA-256 over every path, entry type, metadata and contents in the
+    /// filesystem. Two filesystems that are equal have the same hash,
+    /// regardless of how their file data is split up into extents.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        // paths are iterated in sorted order, so this is stable
+        for (path, entry) in self {
+            update_field(&mut hasher, path.as_os_str().as_bytes());
+            #[remain::sorted]
+            match entry {
+                Entry::Directory(_) => {
+                    hasher.update(b"d");
+                }
+                Entry::File(f) => {
+                    hasher.update(b"f");
+                    hasher.update(f.content_hash());
+                }
+                Entry::Special(s) => {
+                    hasher.update(b"s");
+                    hasher.update(s.file_type().bits().to_le_bytes());
+                    hasher.update(s.rdev().unwrap_or(0).to_le_bytes());
+                }
+                Entry::Symlink(s) => {
+                    hasher.update(b"l");
+                    update_field(&mut hasher, s.target().as_os_str().as_bytes());
+                }
+            }
+            entry.metadata().update_hash(&mut hasher);
+        }
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::file::extent::Extent;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn file_content_hash() {
+        let f = File {
+            extents: BTreeMap::from([
+                (0, "Lorem".into()),
+                (5, Extent::Hole(3)),
+                (8, "ipsum".into()),
+            ]),
+            metadata: Default::default(),
+        };
+        let expected: [u8; 32] = Sha256::digest(f.to_bytes()).into();
+        assert_eq!(f.content_hash(), expected);
+        assert_eq!(
+            File::builder()
+                .contents("Lorem\0\0\0ipsum")
+                .build()
+                .content_hash(),
+            expected,
+        );
+    }
+
+    #[test]
+    fn fs_content_hash() {
+        assert_eq!(demo_fs().content_hash(), demo_fs().content_hash());
+
+        let mut changed = demo_fs();
+        changed
+            .get_file_mut("testdata/lorem.txt")
+            .expect("missing file")
+            .write_at(0, b"l")
+            .expect("failed to write");
+        assert_ne!(demo_fs().content_hash(), changed.content_hash());
+
+        let mut chmodded = demo_fs();
+        chmodded
+            .chmod("testdata", nix::sys::stat::Mode::from_bits_truncate(0o700))
+            .expect("missing dir");
+        assert_ne!(demo_fs().content_hash(), chmodded.content_hash());
+    }
+}
@@ -1,5 +1,13 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ops::Bound;
+use std::ops::ControlFlow;
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 
+use bytes::Bytes;
+
+use crate::path::normalize;
 use crate::BytesPath;
 use crate::Entry;
 use crate::Filesystem;
@@ -14,13 +22,89 @@ impl<'f> IntoIterator for &'f Filesystem {
     }
 }
 
+impl<'f> IntoIterator for &'f mut Filesystem {
+    type Item = (&'f Path, &'f mut Entry);
+    type IntoIter = IterMut<'f>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
 impl Filesystem {
-    pub fn iter(&self) -> Iter {
+    pub fn iter(&self) -> Iter<'_> {
         Iter {
             iter: self.paths.iter(),
             fs: self,
         }
     }
+
+    /// Mutable version of [Filesystem::iter]. An entry that is hardlinked at
+    /// multiple paths is only yielded once, for the first of its paths.
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
+        let mut entries: HashMap<InodeKey, &mut Entry> = self.inodes.iter_mut().collect();
+        let items: Vec<_> = self
+            .paths
+            .iter()
+            .filter_map(|(path, inode)| entries.remove(inode).map(|e| (path.as_path(), e)))
+            .collect();
+        IterMut {
+            iter: items.into_iter(),
+        }
+    }
+
+    /// List the direct children of the directory at `path`, in sorted order.
+    /// Nothing is yielded if `path` does not exist or is not a directory.
+    pub fn read_dir<P>(&self, path: P) -> impl Iterator<Item = (&OsStr, &Entry)>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = normalize(path.as_ref()).as_os_str().as_bytes().to_vec();
+        // descendants of 'dir' are exactly the paths between 'dir/' and 'dir0'
+        // ('0' being the byte after '/'), except for the root which is the
+        // parent of everything
+        let (range, prefix_len) = match dir.is_empty() {
+            true => (
+                (
+                    Bound::Excluded(BytesPath::from(Bytes::new())),
+                    Bound::Unbounded,
+                ),
+                0,
+            ),
+            false => {
+                let prefix_len = dir.len() + 1;
+                let mut start = dir.clone();
+                start.push(b'/');
+                let mut end = dir;
+                end.push(b'/' + 1);
+                (
+                    (
+                        Bound::Included(BytesPath::from(Bytes::from(start))),
+                        Bound::Excluded(BytesPath::from(Bytes::from(end))),
+                    ),
+                    prefix_len,
+                )
+            }
+        };
+        self.paths.range(range).filter_map(move |(path, inode)| {
+            let name = &path.as_os_str().as_bytes()[prefix_len..];
+            match name.contains(&b'/') {
+                true => None,
+                false => Some((OsStr::from_bytes(name), &self.inodes[*inode])),
+            }
+        })
+    }
+
+    /// Call `f` for every entry in sorted path order (the same order as
+    /// [Filesystem::iter]), stopping as soon as it returns
+    /// [ControlFlow::Break]. Returns [ControlFlow::Break] if the walk was
+    /// stopped early.
+    pub fn walk<F>(&self, mut f: F) -> ControlFlow<()>
+    where
+        F: FnMut(&Path, &Entry) -> ControlFlow<()>,
+    {
+        self.iter().try_for_each(|(path, entry)| f(path, entry))
+    }
 }
 
 pub struct Iter<'f> {
@@ -40,3 +124,117 @@ impl<'f> Iterator for Iter<'f> {
         })
     }
 }
+
+pub struct IterMut<'f> {
+    iter: std::vec::IntoIter<(&'f Path, &'f mut Entry)>,
+}
+
+impl<'f> Iterator for IterMut<'f> {
+    type Item = (&'f Path, &'f mut Entry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::stat::Mode;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn iter() {
+        let fs = demo_fs();
+        let entries: Vec<_> = fs
+            .iter()
+            .map(|(path, entry)| {
+                let kind = match entry {
+                    Entry::Directory(_) => "dir",
+                    Entry::File(_) => "file",
+                    Entry::Special(_) => "special",
+                    Entry::Symlink(_) => "symlink",
+                };
+                (path, kind)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (Path::new(""), "dir"),
+                (Path::new("testdata"), "dir"),
+                (Path::new("testdata/dir"), "dir"),
+                (Path::new("testdata/dir/lorem.txt"), "file"),
+                (Path::new("testdata/dir/symlink"), "symlink"),
+                (Path::new("testdata/lorem.txt"), "file"),
+            ],
+        );
+    }
+
+    #[test]
+    fn iter_mut() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        let mut paths = Vec::new();
+        for (path, entry) in &mut fs {
+            paths.push(path.to_owned());
+            entry.chmod(Mode::from_bits_truncate(0o600));
+        }
+        // the hardlink is only visited once
+        assert_eq!(paths.len(), demo_fs().iter().count());
+        for (_, entry) in &fs {
+            assert_eq!(entry.metadata().mode(), Mode::from_bits_truncate(0o600));
+        }
+    }
+
+    #[test]
+    fn walk() {
+        let fs = demo_fs();
+        let mut visited = Vec::new();
+        let flow = fs.walk(|path, entry| {
+            visited.push(path.to_owned());
+            match entry.is_file() {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            }
+        });
+        assert_eq!(flow, ControlFlow::Break(()));
+        assert_eq!(
+            visited,
+            vec![
+                Path::new(""),
+                Path::new("testdata"),
+                Path::new("testdata/dir"),
+                Path::new("testdata/dir/lorem.txt"),
+            ],
+        );
+        assert_eq!(
+            fs.walk(|_, _| ControlFlow::Continue(())),
+            ControlFlow::Continue(())
+        );
+    }
+
+    #[test]
+    fn read_dir() {
+        let fs = demo_fs();
+        let children = |path| -> Vec<_> {
+            fs.read_dir(path)
+                .map(|(name, entry)| (name.to_str().unwrap(), entry.is_directory()))
+                .collect()
+        };
+        assert_eq!(children("/"), vec![("testdata", true)]);
+        assert_eq!(
+            children("testdata"),
+            vec![("dir", true), ("lorem.txt", false)]
+        );
+        assert_eq!(
+            children("testdata/dir"),
+            vec![("lorem.txt", false), ("symlink", false)]
+        );
+        assert_eq!(children("testdata/lorem.txt"), vec![]);
+        assert_eq!(children("missing"), vec![]);
+    }
+}
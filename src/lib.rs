@@ -6,11 +6,8 @@
 //! etc) and get a complete picture of the entire FS (or at least the parts that
 //! can be represented in the archive format).
 
-#![feature(io_error_more)]
-#![feature(io_error_other)]
 #![feature(proc_macro_hygiene)]
 #![feature(stmt_expr_attributes)]
-#![feature(unix_chown)]
 
 use std::collections::BTreeMap;
 use std::collections::HashSet;
@@ -30,22 +27,66 @@ pub mod archive;
 #[cfg(feature = "btrfs")]
 pub mod btrfs;
 mod bytes_ext;
+mod case;
 pub mod cmp;
+#[cfg(feature = "hash")]
+mod dedupe;
+mod describe;
 #[cfg(feature = "diff")]
 pub mod diff;
+pub mod dir;
 mod entry;
 pub mod file;
+#[cfg(feature = "hash")]
+mod hash;
 mod iter;
+#[cfg(feature = "glob")]
+mod matching;
+mod merge;
+mod ops;
+mod overlay;
+mod parents;
 mod path;
+mod prefix;
+#[cfg(feature = "serde")]
+mod ser;
+mod size;
+mod stream;
+mod validate;
+mod xattrs;
 
 pub(crate) use bytes_ext::BytesExt;
+pub use dir::FromDirOptions;
+#[cfg(feature = "mmap")]
+pub use dir::DEFAULT_MMAP_THRESHOLD;
 pub use entry::Entry;
 use file::File;
+pub use merge::MergePolicy;
+pub use ops::Op;
+pub use overlay::OverlayFs;
+pub use overlay::OPAQUE_XATTR;
+pub use parents::ParentOptions;
+use path::normalize;
 pub use path::BytesPath;
+pub use size::FsStats;
+pub use size::DIRECTORY_SIZE;
+pub use stream::FilesystemReader;
+pub use validate::ValidationError;
+pub use xattrs::Namespace;
+pub use xattrs::XattrError;
+pub use xattrs::XattrFilter;
 
 slotmap::new_key_type! { pub struct InodeKey; }
 
 /// Full view of a filesystem.
+/// Every path is relative to the root of the filesystem, which is itself
+/// stored at the empty path "". Paths passed to any method are normalized
+/// first (see [BytesPath]), so "/", "." and "" all refer to the root, and
+/// "/a/./b/" is the same as "a/b". Paths can never refer to anything above
+/// the root: methods that create a path fail with
+/// [ErrorKind::InvalidInput] if a '..' would escape it.
+/// All file data is reference-counted [bytes::Bytes], so a Filesystem is
+/// `Send + Sync` and can be shared between threads without copying.
 #[derive(Clone)]
 pub struct Filesystem {
     inodes: SlotMap<InodeKey, Entry>,
@@ -62,10 +103,30 @@ impl Filesystem {
         }
     }
 
+    /// Insert `entry` at `path`, replacing anything that was already there.
+    ///
+    /// # Panics
+    /// If `path` escapes the root (see [BytesPath]).
     pub fn insert(&mut self, path: impl Into<BytesPath>, entry: impl Into<Entry>) -> InodeKey {
-        let key = self.inodes.insert(entry.into());
-        self.paths.insert(path.into(), key);
+        self.insert_normalized(path.into().normalize(), entry.into())
+    }
+
+    /// Fallible version of [Filesystem::insert] for paths that come from
+    /// untrusted input, like an archive being imported.
+    pub(crate) fn try_insert(
+        &mut self,
+        path: impl Into<BytesPath>,
+        entry: impl Into<Entry>,
+    ) -> Result<InodeKey> {
+        Ok(self.insert_normalized(path.into().try_normalize()?, entry.into()))
+    }
+
+    fn insert_normalized(&mut self, path: BytesPath, entry: Entry) -> InodeKey {
+        let key = self.inodes.insert(entry);
         self.refcounts.insert(key, 1);
+        if let Some(replaced) = self.paths.insert(path, key) {
+            self.release(replaced);
+        }
         key
     }
 
@@ -73,8 +134,8 @@ impl Filesystem {
     where
         P: AsRef<Path>,
     {
-        if let Some(key) = self.paths.remove(path.as_ref()) {
-            self.refcounts[key] -= 1;
+        if let Some(key) = self.paths.remove(&*normalize(path.as_ref())) {
+            self.release(key);
             Ok(())
         } else {
             Err(Error::new(
@@ -84,12 +145,109 @@ impl Filesystem {
         }
     }
 
+    /// Copy every path and entry into new allocations, so that the result no
+    /// longer keeps alive (or depends on) whatever buffer the filesystem was
+    /// loaded from. Hardlinks are preserved. See [File::into_owned].
+    pub fn into_owned(mut self) -> Self {
+        for entry in self.inodes.values_mut() {
+            *entry = entry.clone().into_owned();
+        }
+        self.paths = std::mem::take(&mut self.paths)
+            .into_iter()
+            .map(|(path, key)| (path.into_owned(), key))
+            .collect();
+        self
+    }
+
+    /// Drop a single reference to an inode, returning the entry if that was
+    /// the last reference to it.
+    fn release(&mut self, key: InodeKey) -> Option<Entry> {
+        self.refcounts[key] -= 1;
+        match self.refcounts[key] {
+            0 => {
+                self.refcounts.remove(key);
+                self.inodes.remove(key)
+            }
+            _ => None,
+        }
+    }
+
+    /// Remove a single non-directory entry, returning it. If the entry is
+    /// still hardlinked at another path, a copy of it is returned.
+    pub fn remove<P>(&mut self, path: P) -> Result<Entry>
+    where
+        P: AsRef<Path>,
+    {
+        let path = &*normalize(path.as_ref());
+        let entry = self.get(path)?;
+        if entry.is_directory() {
+            return Err(Error::new(
+                ErrorKind::IsADirectory,
+                format!("'{}' is a directory", path.display()),
+            ));
+        }
+        let entry = entry.clone();
+        let key = self.paths.remove(path).expect("path definitely exists");
+        Ok(self.release(key).unwrap_or(entry))
+    }
+
+    /// Remove a directory and everything underneath it.
+    pub fn remove_dir_all<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = &*normalize(path.as_ref());
+        if !self.get(dir)?.is_directory() {
+            return Err(Error::new(
+                ErrorKind::NotADirectory,
+                format!("'{}' is not a directory", dir.display()),
+            ));
+        }
+        let removed: Vec<BytesPath> = self
+            .paths
+            .keys()
+            .filter(|p| p.starts_with(dir))
+            .cloned()
+            .collect();
+        for path in removed {
+            let key = self.paths.remove(&path).expect("path definitely exists");
+            self.release(key);
+        }
+        Ok(())
+    }
+
+    /// Keep only the entries for which `f` returns true, in a single pass in
+    /// sorted path order. An entry that is hardlinked at multiple paths is
+    /// only removed once the last of its paths is dropped.
+    ///
+    /// Like [Filesystem::insert], this does not check the tree structure:
+    /// removing a directory does not remove its descendants, so if `f`
+    /// rejects a directory it should also reject everything underneath it
+    /// (or use [Filesystem::remove_dir_all]) unless orphaned entries are
+    /// wanted.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Path, &Entry) -> bool,
+    {
+        let mut removed = Vec::new();
+        self.paths.retain(|path, key| {
+            let keep = f(path, &self.inodes[*key]);
+            if !keep {
+                removed.push(*key);
+            }
+            keep
+        });
+        for key in removed {
+            self.release(key);
+        }
+    }
+
     pub fn get<P>(&self, path: P) -> Result<&Entry>
     where
         P: AsRef<Path>,
     {
         self.paths
-            .get(path.as_ref())
+            .get(&*normalize(path.as_ref()))
             .and_then(|key| self.inodes.get(*key))
             .ok_or_else(|| {
                 Error::new(
@@ -104,7 +262,7 @@ impl Filesystem {
         P: AsRef<Path>,
     {
         self.paths
-            .get(path.as_ref())
+            .get(&*normalize(path.as_ref()))
             .and_then(|key| self.inodes.get_mut(*key))
             .ok_or_else(|| {
                 Error::new(
@@ -114,6 +272,34 @@ impl Filesystem {
             })
     }
 
+    pub fn exists<P>(&self, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        self.paths.contains_key(&*normalize(path.as_ref()))
+    }
+
+    pub fn is_dir<P>(&self, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        self.get(path).is_ok_and(Entry::is_directory)
+    }
+
+    pub fn is_file<P>(&self, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        self.get(path).is_ok_and(Entry::is_file)
+    }
+
+    pub fn is_symlink<P>(&self, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        self.get(path).is_ok_and(Entry::is_symlink)
+    }
+
     pub fn get_file<P>(&self, path: P) -> Result<&File>
     where
         P: AsRef<Path>,
@@ -148,33 +334,134 @@ impl Filesystem {
         Ok(())
     }
 
-    pub fn chown<P>(&mut self, path: P, uid: Uid, gid: Gid) -> Result<()>
+    /// Change the owner and/or group of an entry, leaving either one alone if
+    /// it is `None`. Like `chown -h`, a symlink itself is changed, not its
+    /// target.
+    pub fn chown<P>(
+        &mut self,
+        path: P,
+        uid: impl Into<Option<Uid>>,
+        gid: impl Into<Option<Gid>>,
+    ) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let metadata = self.get_mut(path)?.metadata_mut();
+        if let Some(uid) = uid.into() {
+            metadata.uid = uid;
+        }
+        if let Some(gid) = gid.into() {
+            metadata.gid = gid;
+        }
+        Ok(())
+    }
+
+    /// [Filesystem::chmod] `path` and everything underneath it, like
+    /// `chmod -R`. Symlinks are skipped, since their mode is meaningless.
+    pub fn chmod_recursive<P>(&mut self, path: P, mode: Mode) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        for key in self.subtree(path.as_ref())? {
+            let entry = &mut self.inodes[key];
+            if !entry.is_symlink() {
+                entry.chmod(mode);
+            }
+        }
+        Ok(())
+    }
+
+    /// [Filesystem::chown] `path` and everything underneath it, like
+    /// `chown -R -h`.
+    pub fn chown_recursive<P>(
+        &mut self,
+        path: P,
+        uid: impl Into<Option<Uid>>,
+        gid: impl Into<Option<Gid>>,
+    ) -> Result<()>
     where
         P: AsRef<Path>,
     {
-        self.get_mut(path)?.chown(uid, gid);
+        let (uid, gid) = (uid.into(), gid.into());
+        for key in self.subtree(path.as_ref())? {
+            let metadata = self.inodes[key].metadata_mut();
+            metadata.uid = uid.unwrap_or(metadata.uid);
+            metadata.gid = gid.unwrap_or(metadata.gid);
+        }
         Ok(())
     }
 
+    /// Every unique inode at or underneath `path`
+    fn subtree(&self, path: &Path) -> Result<HashSet<InodeKey>> {
+        let path = &*normalize(path);
+        if !self.paths.contains_key(path) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("'{}' not found", path.display()),
+            ));
+        }
+        Ok(self
+            .paths
+            .iter()
+            .filter(|(p, _)| p.starts_with(path))
+            .map(|(_, key)| *key)
+            .collect())
+    }
+
+    /// Move an entry to a new path. If the entry is a directory, all of its
+    /// descendants are moved along with it. Fails if `to` already exists or
+    /// its parent directory does not.
     pub fn rename<P1, P2>(&mut self, from: P1, to: P2) -> Result<()>
     where
         P1: AsRef<Path>,
         P2: Into<BytesPath>,
     {
-        let inode = self.paths.remove(from.as_ref()).ok_or_else(|| {
-            Error::new(
+        let from = &*normalize(from.as_ref());
+        if !self.paths.contains_key(from) {
+            return Err(Error::new(
                 ErrorKind::NotFound,
-                format!("'{}' not found", from.as_ref().display()),
-            )
-        })?;
-        let to = to.into();
+                format!("'{}' not found", from.display()),
+            ));
+        }
+        let to = to.into().try_normalize()?;
         if self.paths.contains_key(&to) {
             return Err(Error::new(
                 ErrorKind::AlreadyExists,
                 format!("'{}' already exists", to.display()),
             ));
         }
-        self.paths.insert(to, inode);
+        if let Some(parent) = to.parent() {
+            if !self.get(parent).is_ok_and(Entry::is_directory) {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("parent of '{}' is not a directory", to.display()),
+                ));
+            }
+        }
+        if to.starts_with(from) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "cannot move '{}' into itself at '{}'",
+                    from.display(),
+                    to.display()
+                ),
+            ));
+        }
+        let moved: Vec<BytesPath> = self
+            .paths
+            .keys()
+            .filter(|p| p.starts_with(from))
+            .cloned()
+            .collect();
+        for old in moved {
+            let inode = self.paths.remove(&old).expect("path definitely exists");
+            let new = match old.strip_prefix(from).expect("path starts with prefix") {
+                rel if rel.as_os_str().is_empty() => to.clone(),
+                rel => to.join(rel).into(),
+            };
+            self.paths.insert(new, inode);
+        }
         Ok(())
     }
 
@@ -195,20 +482,21 @@ impl Filesystem {
     }
 
     /// Create a hard link to an existing file. This increments the refcount of
-    /// the original inode. The the original path is later unlinked, this
+    /// the original inode. If the original path is later unlinked, this
     /// reference will keep the underlying entry alive.
     pub fn link<P1, P2>(&mut self, old: P1, new: P2) -> Result<()>
     where
         P1: AsRef<Path>,
         P2: Into<BytesPath>,
     {
-        let key = self.paths.get(old.as_ref()).ok_or_else(|| {
+        let new = new.into().try_normalize()?;
+        let key = self.paths.get(&*normalize(old.as_ref())).ok_or_else(|| {
             Error::new(
                 ErrorKind::NotFound,
                 format!("'{}' not found", old.as_ref().display()),
             )
         })?;
-        if !self.inodes[*key].is_directory() {
+        if self.inodes[*key].is_directory() {
             return Err(Error::new(
                 ErrorKind::IsADirectory,
                 "directory cannot be hardlink target",
@@ -218,10 +506,68 @@ impl Filesystem {
             .entry(*key)
             .expect("refcount impossibly None")
             .and_modify(|r| *r += 1);
-        self.paths.insert(new.into(), *key);
+        let key = *key;
+        if let Some(replaced) = self.paths.insert(new, key) {
+            self.release(replaced);
+        }
         Ok(())
     }
 
+    /// Create `new` as another path to the same entry as `existing`, like
+    /// [Filesystem::link], but fail if `new` already exists or its parent is
+    /// not a directory instead of replacing it.
+    pub fn hardlink<P1, P2>(&mut self, existing: P1, new: P2) -> Result<()>
+    where
+        P1: AsRef<Path>,
+        P2: Into<BytesPath>,
+    {
+        let new = new.into().try_normalize()?;
+        self.check_new_path(&new)?;
+        self.link(existing, new)
+    }
+
+    /// Create a symlink at `path` pointing to `target`, with the default
+    /// symlink metadata. Fails if `path` already exists or its parent is not
+    /// a directory. `target` is stored as-is and does not have to exist.
+    pub fn symlink<P, T>(&mut self, path: P, target: T) -> Result<()>
+    where
+        P: Into<BytesPath>,
+        T: Into<BytesPath>,
+    {
+        self.create(path.into(), entry::Symlink::new(target, None))
+    }
+
+    /// Copy the regular file at `from` to a new path `to`, like
+    /// `cp --reflink`. The copy shares the data of the original instead of
+    /// duplicating it, but is otherwise independent: changes to the contents
+    /// or metadata of one are not visible in the other. Fails if `to` already
+    /// exists or its parent is not a directory.
+    pub fn copy<P1, P2>(&mut self, from: P1, to: P2) -> Result<()>
+    where
+        P1: AsRef<Path>,
+        P2: Into<BytesPath>,
+    {
+        let file = self.get_file(from)?.clone();
+        self.create(to.into(), file)
+    }
+
+    /// Number of paths that refer to the same entry as `path` (including
+    /// `path` itself).
+    pub fn nlink<P>(&self, path: P) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        self.paths
+            .get(&*normalize(path.as_ref()))
+            .map(|key| self.refcounts[*key])
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("'{}' not found", path.as_ref().display()),
+                )
+            })
+    }
+
     pub fn truncate<P>(&mut self, path: P, len: u64) -> Result<()>
     where
         P: AsRef<Path>,
@@ -230,12 +576,12 @@ impl Filesystem {
         Ok(())
     }
 
-    /// Remove a directory, failing if it is not empty
+    /// Remove a directory, failing if it is not empty.
     pub fn rmdir<P>(&mut self, path: P) -> Result<()>
     where
         P: AsRef<Path>,
     {
-        let dir = path.as_ref();
+        let dir = &*normalize(path.as_ref());
         if !self.get(dir)?.is_directory() {
             return Err(Error::new(
                 ErrorKind::NotADirectory,
@@ -252,7 +598,7 @@ impl Filesystem {
                 format!("'{}' is not empty", dir.display()),
             ));
         }
-        Ok(())
+        self.unlink(dir)
     }
 }
 
@@ -348,6 +694,11 @@ impl Debug for Filesystem {
 macro_rules! id_type {
     ($i:ident, $nix:ty) => {
         #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[cfg_attr(
+            feature = "serde",
+            derive(serde::Serialize, serde::Deserialize),
+            serde(transparent)
+        )]
         #[repr(transparent)]
         pub struct $i(u32);
 
@@ -398,10 +749,6 @@ macro_rules! id_type {
 id_type!(Uid, nix::unistd::Uid);
 id_type!(Gid, nix::unistd::Gid);
 
-mod __private {
-    pub trait Sealed {}
-}
-
 #[cfg(test)]
 pub(crate) mod tests {
     use nix::sys::stat::Mode;
@@ -500,6 +847,529 @@ pub(crate) mod tests {
         ])
     }
 
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<Filesystem>();
+        assert_send_sync::<Entry>();
+        assert_send_sync::<File>();
+        assert_send_sync::<OverlayFs>();
+
+        let fs = demo_fs();
+        let lens: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = ["testdata/lorem.txt", "testdata/dir/lorem.txt"]
+                .into_iter()
+                .map(|path| {
+                    let fs = &fs;
+                    s.spawn(move || fs.get_file(path).expect("missing file").len())
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(lens, vec![12, 27]);
+    }
+
+    #[test]
+    fn into_owned() {
+        let buf = bytes::Bytes::from(b"Lorem ipsum\nuser.demolorem ipsum".to_vec());
+        let mut fs = demo_fs();
+        fs.insert(
+            "testdata/lorem.txt",
+            File::builder().contents(buf.slice(..12)).build(),
+        );
+        fs.get_mut("testdata/lorem.txt")
+            .unwrap()
+            .set_xattr(buf.slice(12..21), buf.slice(21..));
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        let expected = fs.clone();
+
+        let owned = fs.into_owned();
+        let data = owned
+            .get_file("testdata/lorem.txt")
+            .unwrap()
+            .extents
+            .values()
+            .next()
+            .expect("one extent")
+            .data()
+            .as_ptr_range();
+        assert!(!buf.as_ptr_range().contains(&data.start));
+        drop(buf);
+        assert_eq!(owned, expected);
+        assert_eq!(owned.nlink("testdata/hardlink.txt").unwrap(), 2);
+    }
+
+    #[test]
+    fn predicates() {
+        let fs = demo_fs();
+        for (path, dir, file, symlink) in [
+            ("", true, false, false),
+            ("/testdata/", true, false, false),
+            ("testdata/lorem.txt", false, true, false),
+            ("./testdata/dir/symlink", false, false, true),
+        ] {
+            assert!(fs.exists(path), "{path}");
+            assert_eq!(fs.is_dir(path), dir, "{path}");
+            assert_eq!(fs.is_file(path), file, "{path}");
+            assert_eq!(fs.is_symlink(path), symlink, "{path}");
+        }
+        assert!(!fs.exists("testdata/missing.txt"));
+        assert!(!fs.is_dir("testdata/missing"));
+        assert!(!fs.is_file("testdata/missing.txt"));
+        assert!(!fs.is_symlink("testdata/missing"));
+    }
+
+    #[test]
+    fn get() {
+        let mut fs = demo_fs();
+        assert!(fs
+            .get("testdata/lorem.txt")
+            .expect("missing file")
+            .is_file());
+        assert!(fs
+            .get("/testdata/lorem.txt")
+            .expect("missing file")
+            .is_file());
+        assert_eq!(
+            fs.get("testdata/missing.txt")
+                .expect_err("file does not exist")
+                .kind(),
+            ErrorKind::NotFound,
+        );
+        fs.get_mut("/testdata/lorem.txt")
+            .expect("missing file")
+            .chmod(Mode::from_bits_truncate(0o600));
+        assert_eq!(
+            fs.get("testdata/lorem.txt")
+                .expect("missing file")
+                .metadata()
+                .mode(),
+            Mode::from_bits_truncate(0o600),
+        );
+        fs.insert("/etc", Directory::default());
+        assert!(fs.get("etc").expect("missing dir").is_directory());
+    }
+
+    #[test]
+    fn normalized_paths() {
+        let mut fs = demo_fs();
+        for root in ["", "/", ".", "./", "//"] {
+            assert_eq!(
+                fs.get(root).expect("missing root"),
+                demo_fs().get("").unwrap(),
+            );
+        }
+        for path in [
+            "testdata/lorem.txt",
+            "./testdata/lorem.txt",
+            "/testdata//lorem.txt",
+            "testdata/./lorem.txt",
+        ] {
+            assert!(fs.get(path).expect("missing file").is_file());
+        }
+        fs.insert("./testdata/new/", Directory::default());
+        assert!(fs.get("testdata/new").expect("missing dir").is_directory());
+        assert_eq!(
+            fs.paths.keys().filter(|p| p.ends_with("new")).count(),
+            1,
+            "path should only be stored once",
+        );
+
+        // '..' that escapes the root is rejected instead of clamped
+        for path in [
+            "../testdata/lorem.txt",
+            "/../..//./testdata/lorem.txt",
+            "..",
+        ] {
+            assert!(fs.get(path).is_err(), "{path} should not resolve");
+        }
+        for to in ["../escape", "testdata/../../escape"] {
+            assert_eq!(
+                fs.rename("testdata/lorem.txt", to)
+                    .expect_err("rename should fail")
+                    .kind(),
+                ErrorKind::InvalidInput,
+            );
+            assert_eq!(
+                fs.link("testdata/lorem.txt", to)
+                    .expect_err("link should fail")
+                    .kind(),
+                ErrorKind::InvalidInput,
+            );
+            assert_eq!(
+                fs.hardlink("testdata/lorem.txt", to)
+                    .expect_err("hardlink should fail")
+                    .kind(),
+                ErrorKind::InvalidInput,
+            );
+            assert_eq!(
+                fs.symlink(to, "target")
+                    .expect_err("symlink should fail")
+                    .kind(),
+                ErrorKind::InvalidInput,
+            );
+        }
+        assert!(fs.get("testdata/lorem.txt").is_ok());
+        assert!(std::panic::catch_unwind(move || {
+            demo_fs().insert("a/../../escape", File::new_empty());
+        })
+        .is_err());
+        // '..' that stays inside the root is kept
+        assert_eq!(
+            BytesPath::from("./a/../x")
+                .try_normalize()
+                .expect("should not escape"),
+            BytesPath::from("a/../x"),
+        );
+        // but anywhere else it is not resolved, because it could be a symlink
+        assert!(fs.get("testdata/dir/../lorem.txt").is_err());
+    }
+
+    #[test]
+    fn chown() {
+        let mut fs = demo_fs();
+        fs.chown("testdata/lorem.txt", None, Gid::from_raw(100))
+            .expect("failed to chown");
+        let metadata = fs.get("testdata/lorem.txt").unwrap().metadata();
+        assert_eq!(metadata.uid(), Uid::from_raw(0));
+        assert_eq!(metadata.gid(), Gid::from_raw(100));
+
+        fs.chown("testdata/dir/symlink", Uid::from_raw(1000), None)
+            .expect("failed to chown");
+        let metadata = fs.get("testdata/dir/symlink").unwrap().metadata();
+        assert_eq!(metadata.uid(), Uid::from_raw(1000));
+        assert_eq!(metadata.gid(), Gid::from_raw(0));
+        // the symlink target is left alone
+        assert_eq!(
+            fs.get("testdata/lorem.txt").unwrap().metadata().uid(),
+            Uid::from_raw(0),
+        );
+
+        fs.chown("testdata/dir", Uid::from_raw(1), Gid::from_raw(2))
+            .expect("failed to chown");
+        let metadata = fs.get("testdata/dir").unwrap().metadata();
+        assert_eq!(
+            (metadata.uid(), metadata.gid()),
+            (Uid::from_raw(1), Gid::from_raw(2))
+        );
+        assert_eq!(
+            fs.chown("missing", None, None).unwrap_err().kind(),
+            ErrorKind::NotFound,
+        );
+    }
+
+    #[test]
+    fn recursive() {
+        let mut fs = demo_fs();
+        fs.chmod_recursive("testdata/dir", Mode::from_bits_truncate(0o700))
+            .expect("failed to chmod");
+        fs.chown_recursive("/testdata/dir/", Uid::from_raw(1000), None)
+            .expect("failed to chown");
+        for (path, entry) in &fs {
+            let metadata = entry.metadata();
+            let (mode, uid) = match path.to_str().unwrap() {
+                "testdata/dir" | "testdata/dir/lorem.txt" => (0o700, 1000),
+                "testdata/dir/symlink" => (0o777, 1000),
+                _ => (demo_fs().get(path).unwrap().metadata().mode().bits(), 0),
+            };
+            assert_eq!(metadata.mode(), Mode::from_bits_truncate(mode), "{path:?}");
+            assert_eq!(metadata.uid(), Uid::from_raw(uid), "{path:?}");
+            assert_eq!(metadata.gid(), Gid::from_raw(0), "{path:?}");
+        }
+
+        // the root covers everything, and names that share a prefix with
+        // the directory are not part of it
+        let mut fs = demo_fs();
+        fs.insert("testdata/dir-2", File::new_empty());
+        fs.chown_recursive("testdata/dir", None, Gid::from_raw(5))
+            .expect("failed to chown");
+        assert_eq!(
+            fs.get("testdata/dir-2").unwrap().metadata().gid(),
+            Gid::from_raw(0)
+        );
+        fs.chown_recursive("", None, Gid::from_raw(5))
+            .expect("failed to chown");
+        assert!(fs
+            .iter()
+            .all(|(_, e)| e.metadata().gid() == Gid::from_raw(5)));
+        assert_eq!(
+            fs.chmod_recursive("missing", Mode::empty())
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound,
+        );
+    }
+
+    #[test]
+    fn symlink() {
+        let mut fs = demo_fs();
+        fs.symlink("/testdata/link", "dir/lorem.txt")
+            .expect("failed to symlink");
+        match fs.get("testdata/link").expect("missing symlink") {
+            Entry::Symlink(s) => {
+                assert_eq!(s.target(), Path::new("dir/lorem.txt"));
+                assert_eq!(s.metadata().mode(), Mode::from_bits_truncate(0o777));
+            }
+            e => panic!("expected symlink, got {e:?}"),
+        }
+        fs.symlink("testdata/dangling", "../missing")
+            .expect("targets don't have to exist");
+        assert_eq!(
+            fs.symlink("testdata/lorem.txt", "dir").unwrap_err().kind(),
+            ErrorKind::AlreadyExists,
+        );
+        assert_eq!(
+            fs.symlink("missing/link", "dir").unwrap_err().kind(),
+            ErrorKind::NotFound,
+        );
+    }
+
+    #[test]
+    fn rename() {
+        let mut fs = demo_fs();
+        fs.rename("testdata/lorem.txt", "testdata/ipsum.txt")
+            .expect("failed to rename file");
+        assert_eq!(
+            fs.get("testdata/ipsum.txt").unwrap(),
+            demo_fs().get("testdata/lorem.txt").unwrap(),
+        );
+        assert!(fs.get("testdata/lorem.txt").is_err());
+
+        fs.rename("/testdata/dir", "/dir")
+            .expect("failed to rename dir");
+        for path in ["dir", "dir/lorem.txt", "dir/symlink"] {
+            assert_eq!(
+                fs.get(path).expect("missing moved entry"),
+                demo_fs().get(Path::new("testdata").join(path)).unwrap(),
+            );
+        }
+        assert!(fs.get("testdata/dir").is_err());
+        assert!(fs.get("testdata/dir/lorem.txt").is_err());
+    }
+
+    #[test]
+    fn rename_errors() {
+        let mut fs = demo_fs();
+        for (from, to, kind) in [
+            ("testdata/missing", "testdata/new", ErrorKind::NotFound),
+            (
+                "testdata/lorem.txt",
+                "missing/lorem.txt",
+                ErrorKind::NotFound,
+            ),
+            (
+                "testdata/lorem.txt",
+                "testdata/lorem.txt/x",
+                ErrorKind::NotFound,
+            ),
+            (
+                "testdata/lorem.txt",
+                "testdata/dir/lorem.txt",
+                ErrorKind::AlreadyExists,
+            ),
+            ("testdata", "testdata/dir/testdata", ErrorKind::InvalidInput),
+        ] {
+            assert_eq!(
+                fs.rename(from, to).expect_err("rename should fail").kind(),
+                kind,
+                "{from} -> {to}",
+            );
+        }
+        assert_eq!(fs, demo_fs());
+    }
+
+    #[test]
+    fn remove() {
+        let mut fs = demo_fs();
+        let entry = fs.remove("testdata/lorem.txt").expect("failed to remove");
+        assert_eq!(&entry, demo_fs().get("testdata/lorem.txt").unwrap());
+        assert!(fs.get("testdata/lorem.txt").is_err());
+        assert_eq!(
+            fs.remove("testdata/lorem.txt")
+                .expect_err("already removed")
+                .kind(),
+            ErrorKind::NotFound,
+        );
+        assert_eq!(
+            fs.remove("testdata").expect_err("is a directory").kind(),
+            ErrorKind::IsADirectory,
+        );
+        assert_eq!(fs.inodes.len(), fs.paths.len());
+    }
+
+    #[test]
+    fn remove_dir_all() {
+        let mut fs = demo_fs();
+        fs.remove_dir_all("testdata/dir").expect("failed to remove");
+        let paths: Vec<_> = fs.iter().map(|(p, _)| p).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new(""),
+                Path::new("testdata"),
+                Path::new("testdata/lorem.txt"),
+            ],
+        );
+        assert_eq!(fs.inodes.len(), 3);
+        assert_eq!(
+            fs.remove_dir_all("testdata/lorem.txt")
+                .expect_err("not a directory")
+                .kind(),
+            ErrorKind::NotADirectory,
+        );
+    }
+
+    #[test]
+    fn retain() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        fs.retain(|path, entry| entry.is_file() && path != Path::new("testdata/lorem.txt"));
+        let paths: Vec<_> = fs.iter().map(|(p, _)| p).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("testdata/dir/lorem.txt"),
+                Path::new("testdata/hardlink.txt"),
+            ],
+        );
+        assert!(fs.iter().all(|(_, e)| e.is_file()));
+        assert_eq!(fs.inodes.len(), 2);
+        assert_eq!(fs.nlink("testdata/hardlink.txt").unwrap(), 1);
+        assert_eq!(
+            fs.get_file("testdata/hardlink.txt")
+                .unwrap()
+                .to_bytes()
+                .as_ref(),
+            b"Lorem ipsum\n",
+        );
+    }
+
+    #[test]
+    fn copy() {
+        let mut fs = demo_fs();
+        fs.copy("testdata/lorem.txt", "testdata/copy.txt")
+            .expect("failed to copy");
+        let original = fs.get_file("testdata/lorem.txt").unwrap();
+        let copy = fs.get_file("testdata/copy.txt").unwrap();
+        assert_eq!(copy, original);
+        assert_eq!(copy.to_bytes().as_ref(), b"Lorem ipsum\n");
+        // data is shared, not copied
+        assert_eq!(
+            copy.extents[&0].data().as_ptr(),
+            original.extents[&0].data().as_ptr(),
+        );
+        assert_eq!(fs.nlink("testdata/copy.txt").unwrap(), 1);
+
+        fs.chmod("testdata/lorem.txt", Mode::from_bits_truncate(0o600))
+            .expect("failed to chmod");
+        fs.get_file_mut("testdata/lorem.txt")
+            .unwrap()
+            .write_at(0, b"l")
+            .expect("failed to write");
+        let copy = fs.get_file("testdata/copy.txt").unwrap();
+        assert_eq!(copy.mode(), Mode::from_bits_truncate(0o644));
+        assert_eq!(copy.to_bytes().as_ref(), b"Lorem ipsum\n");
+
+        for (from, to, kind) in [
+            ("testdata/missing", "testdata/new", ErrorKind::NotFound),
+            ("testdata/dir", "testdata/new", ErrorKind::Other),
+            (
+                "testdata/lorem.txt",
+                "testdata/dir/lorem.txt",
+                ErrorKind::AlreadyExists,
+            ),
+            ("testdata/lorem.txt", "missing/new", ErrorKind::NotFound),
+        ] {
+            assert_eq!(
+                fs.copy(from, to).expect_err("copy should fail").kind(),
+                kind,
+                "{from} -> {to}",
+            );
+        }
+    }
+
+    #[test]
+    fn hardlink() {
+        let mut fs = demo_fs();
+        fs.hardlink("testdata/lorem.txt", "testdata/dir/hardlink.txt")
+            .expect("failed to hardlink");
+        assert_eq!(fs.nlink("testdata/lorem.txt").unwrap(), 2);
+        fs.get_file_mut("testdata/dir/hardlink.txt")
+            .unwrap()
+            .write_at(0, b"l")
+            .expect("failed to write");
+        assert_eq!(
+            fs.get_file("testdata/lorem.txt")
+                .unwrap()
+                .to_bytes()
+                .as_ref(),
+            b"lorem ipsum\n",
+        );
+
+        for (existing, new, kind) in [
+            ("testdata/missing", "testdata/new", ErrorKind::NotFound),
+            ("testdata/dir", "testdata/new", ErrorKind::IsADirectory),
+            (
+                "testdata/lorem.txt",
+                "testdata/dir/lorem.txt",
+                ErrorKind::AlreadyExists,
+            ),
+            ("testdata/lorem.txt", "missing/new", ErrorKind::NotFound),
+        ] {
+            assert_eq!(
+                fs.hardlink(existing, new)
+                    .expect_err("hardlink should fail")
+                    .kind(),
+                kind,
+                "{existing} -> {new}",
+            );
+        }
+        assert_eq!(fs.nlink("testdata/lorem.txt").unwrap(), 2);
+        assert_eq!(fs.nlink("testdata/dir/lorem.txt").unwrap(), 1);
+    }
+
+    #[test]
+    fn link() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        assert_eq!(fs.nlink("testdata/lorem.txt").unwrap(), 2);
+        assert_eq!(fs.nlink("testdata/hardlink.txt").unwrap(), 2);
+        fs.get_file_mut("testdata/hardlink.txt")
+            .unwrap()
+            .write_at(0, b"l")
+            .expect("failed to write");
+        assert_eq!(
+            fs.get_file("testdata/lorem.txt")
+                .unwrap()
+                .to_bytes()
+                .as_ref(),
+            b"lorem ipsum\n",
+        );
+        fs.unlink("testdata/lorem.txt").unwrap();
+        assert_eq!(fs.nlink("testdata/hardlink.txt").unwrap(), 1);
+        assert_eq!(fs.inodes.len(), demo_fs().inodes.len());
+        fs.unlink("testdata/hardlink.txt").unwrap();
+        assert_eq!(fs.inodes.len(), demo_fs().inodes.len() - 1);
+        // replacing an existing path releases the entry it pointed to
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/dir/lorem.txt")
+            .expect("failed to link");
+        assert_eq!(fs.nlink("testdata/lorem.txt").unwrap(), 2);
+        assert_eq!(fs.inodes.len(), demo_fs().inodes.len() - 1);
+        fs.link("testdata/lorem.txt", "testdata/lorem.txt")
+            .expect("failed to link");
+        assert_eq!(fs.nlink("testdata/lorem.txt").unwrap(), 2);
+        assert_eq!(
+            fs.link("testdata", "testdata2")
+                .expect_err("directories cannot be linked")
+                .kind(),
+            ErrorKind::IsADirectory,
+        );
+    }
+
     #[test]
     fn partial_eq() {
         assert_eq!(demo_fs(), demo_fs());
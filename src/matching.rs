@@ -0,0 +1,77 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::path::Path;
+
+use glob::MatchOptions;
+use glob::Pattern;
+
+use crate::Entry;
+use crate::Filesystem;
+
+impl Filesystem {
+    /// Every entry whose path matches the glob `pattern`, in path order.
+    /// Like a shell, `*` and `?` never match a '/', while `**` matches any
+    /// number of directories, so "**/*.so" finds every ".so" file. Patterns
+    /// are matched against normalized paths, so any leading '/' is ignored.
+    pub fn matching(&self, pattern: &str) -> Result<impl Iterator<Item = (&Path, &Entry)>> {
+        let pattern = Pattern::new(pattern.trim_start_matches('/')).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid pattern '{pattern}': {e}"),
+            )
+        })?;
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        Ok(self
+            .iter()
+            .filter(move |(path, _)| pattern.matches_path_with(path, options)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+
+    fn matched(fs: &Filesystem, pattern: &str) -> Vec<String> {
+        fs.matching(pattern)
+            .expect("invalid pattern")
+            .map(|(path, _)| path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn matching() {
+        let fs = demo_fs();
+        assert_eq!(
+            matched(&fs, "**/*.txt"),
+            vec!["testdata/dir/lorem.txt", "testdata/lorem.txt"],
+        );
+        assert_eq!(matched(&fs, "testdata/*.txt"), vec!["testdata/lorem.txt"]);
+        assert_eq!(
+            matched(&fs, "/testdata/*"),
+            vec!["testdata/dir", "testdata/lorem.txt"],
+        );
+        assert_eq!(
+            matched(&fs, "testdata/**"),
+            vec![
+                "testdata/dir",
+                "testdata/dir/lorem.txt",
+                "testdata/dir/symlink",
+                "testdata/lorem.txt",
+            ],
+        );
+        assert_eq!(matched(&fs, "**/sym?ink"), vec!["testdata/dir/symlink"]);
+        assert!(matched(&fs, "**/*.so").is_empty());
+        assert_eq!(
+            fs.matching("[").err().map(|e| e.kind()),
+            Some(ErrorKind::InvalidInput),
+        );
+    }
+}
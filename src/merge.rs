@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+use crate::BytesPath;
+use crate::Filesystem;
+use crate::InodeKey;
+
+/// How to resolve paths that exist in both [Filesystem]s passed to
+/// [Filesystem::merge].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Replace the existing entry with the one being merged in
+    Overwrite,
+    /// Keep the existing entry and ignore the one being merged in
+    KeepExisting,
+    /// Fail the merge without changing anything
+    Error,
+}
+
+impl Filesystem {
+    /// Overlay all the entries of `other` on top of `self`. Directories that
+    /// exist in both are merged, so both of their contents end up in `self`,
+    /// but the directory metadata is resolved like any other conflict
+    /// according to `policy`. With [MergePolicy::Error], directories may still
+    /// overlap as long as they are identical.
+    /// Replacing a directory with a non-directory removes everything that was
+    /// underneath it.
+    pub fn merge(&mut self, other: Filesystem, policy: MergePolicy) -> Result<()> {
+        if policy == MergePolicy::Error {
+            for (path, entry) in &other {
+                match self.get(path) {
+                    Ok(existing) if existing.is_directory() && existing == entry => {}
+                    Ok(_) => {
+                        return Err(Error::new(
+                            ErrorKind::AlreadyExists,
+                            format!("'{}' already exists", path.display()),
+                        ));
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        // paths in self of entries that were already merged from other, so
+        // that hardlinks stay linked
+        let mut merged: HashMap<InodeKey, BytesPath> = HashMap::new();
+        // directories in other that lost to an existing non-directory, whose
+        // contents would otherwise end up underneath a file
+        let mut skipped: Vec<&BytesPath> = Vec::new();
+        for (path, key) in &other.paths {
+            if skipped.iter().any(|dir| path.starts_with(dir)) {
+                continue;
+            }
+            let entry = &other.inodes[*key];
+            if let Ok(existing) = self.get(path) {
+                match policy {
+                    MergePolicy::Overwrite => {
+                        if existing.is_directory() && !entry.is_directory() {
+                            self.remove_dir_all(path)?;
+                        } else if !existing.is_directory() {
+                            self.unlink(path)?;
+                        }
+                    }
+                    MergePolicy::KeepExisting => {
+                        if entry.is_directory() && !existing.is_directory() {
+                            skipped.push(path);
+                        }
+                        continue;
+                    }
+                    // only identical directories can get this far
+                    MergePolicy::Error => continue,
+                }
+            }
+            match merged.get(key) {
+                Some(target) => self.link(target, path.clone())?,
+                None => {
+                    self.insert(path.clone(), entry.clone());
+                    merged.insert(*key, path.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::stat::Mode;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::entry::Directory;
+    use crate::entry::Metadata;
+    use crate::tests::demo_fs;
+    use crate::File;
+
+    fn other_fs() -> Filesystem {
+        let mut other = Filesystem::new();
+        other.insert(
+            "testdata",
+            Directory::builder()
+                .metadata(
+                    Metadata::builder()
+                        .mode(Mode::from_bits_truncate(0o700))
+                        .build(),
+                )
+                .build(),
+        );
+        other.insert(
+            "testdata/lorem.txt",
+            File::builder().contents("consectetur\n").build(),
+        );
+        other.insert(
+            "testdata/new.txt",
+            File::builder().contents("adipiscing\n").build(),
+        );
+        other
+            .link("testdata/new.txt", "testdata/new_link.txt")
+            .expect("failed to link");
+        other
+    }
+
+    #[test]
+    fn overwrite() {
+        let mut fs = demo_fs();
+        fs.merge(other_fs(), MergePolicy::Overwrite)
+            .expect("failed to merge");
+        assert_eq!(
+            fs.get_file("testdata/lorem.txt")
+                .unwrap()
+                .to_bytes()
+                .as_ref(),
+            b"consectetur\n",
+        );
+        assert_eq!(
+            fs.get("testdata").unwrap().metadata().mode(),
+            Mode::from_bits_truncate(0o700),
+        );
+        // everything else is still there
+        assert!(fs.get("testdata/dir/lorem.txt").is_ok());
+        assert_eq!(fs.nlink("testdata/new_link.txt").unwrap(), 2);
+        assert_eq!(fs.inodes.len(), demo_fs().inodes.len() + 1);
+    }
+
+    #[test]
+    fn keep_existing() {
+        let mut fs = demo_fs();
+        fs.merge(other_fs(), MergePolicy::KeepExisting)
+            .expect("failed to merge");
+        assert_eq!(
+            fs.get("testdata/lorem.txt").unwrap(),
+            demo_fs().get("testdata/lorem.txt").unwrap(),
+        );
+        assert_eq!(
+            fs.get("testdata").unwrap(),
+            demo_fs().get("testdata").unwrap(),
+        );
+        assert_eq!(
+            fs.get_file("testdata/new.txt").unwrap().to_bytes().as_ref(),
+            b"adipiscing\n",
+        );
+        assert_eq!(fs.nlink("testdata/new_link.txt").unwrap(), 2);
+    }
+
+    #[test]
+    fn keep_existing_file_over_directory() {
+        let mut fs = demo_fs();
+        let other = Filesystem::from([
+            ("testdata/lorem.txt", Directory::default().into()),
+            (
+                "testdata/lorem.txt/ipsum.txt",
+                File::builder().contents("ipsum\n").build().into(),
+            ),
+            (
+                "testdata/lorem.txt.bak",
+                File::builder().contents("Lorem ipsum\n").build().into(),
+            ),
+        ]);
+        fs.merge(other, MergePolicy::KeepExisting)
+            .expect("failed to merge");
+        assert!(fs.get("testdata/lorem.txt").unwrap().is_file());
+        assert!(fs.get("testdata/lorem.txt/ipsum.txt").is_err());
+        // siblings that only share a name prefix are still merged
+        assert!(fs.get("testdata/lorem.txt.bak").is_ok());
+        assert_eq!(fs.validate(), Ok(()));
+    }
+
+    #[test]
+    fn error() {
+        let mut fs = demo_fs();
+        assert_eq!(
+            fs.merge(other_fs(), MergePolicy::Error)
+                .expect_err("conflicting paths")
+                .kind(),
+            ErrorKind::AlreadyExists,
+        );
+        assert_eq!(fs, demo_fs());
+
+        // identical directories are allowed to overlap
+        let mut other = other_fs();
+        other.unlink("testdata/lorem.txt").unwrap();
+        other.insert("testdata", demo_fs().get("testdata").unwrap().clone());
+        fs.merge(other, MergePolicy::Error)
+            .expect("failed to merge");
+        assert!(fs.get("testdata/new.txt").is_ok());
+    }
+
+    #[test]
+    fn overwrite_directory_with_file() {
+        let mut fs = demo_fs();
+        let other = Filesystem::from([(
+            "testdata/dir",
+            File::builder().contents("Lorem ipsum\n").build().into(),
+        )]);
+        fs.merge(other, MergePolicy::Overwrite)
+            .expect("failed to merge");
+        assert!(fs.get("testdata/dir").unwrap().is_file());
+        assert!(fs.get("testdata/dir/lorem.txt").is_err());
+        assert_eq!(fs.inodes.len(), fs.paths.len());
+    }
+}
@@ -0,0 +1,190 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+use bytes::Bytes;
+use nix::sys::stat::Mode;
+
+use crate::entry::Directory;
+use crate::entry::Entry;
+use crate::BytesPath;
+use crate::File;
+use crate::Filesystem;
+use crate::Gid;
+use crate::Uid;
+
+/// A single mutation that can be applied to a [Filesystem] with
+/// [Filesystem::apply].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[remain::sorted]
+pub enum Op {
+    Chmod {
+        path: BytesPath,
+        mode: Mode,
+    },
+    Chown {
+        path: BytesPath,
+        uid: Uid,
+        gid: Gid,
+    },
+    /// Create a new regular file with default metadata
+    CreateFile {
+        path: BytesPath,
+        contents: Bytes,
+    },
+    /// Create a new directory with default metadata
+    Mkdir(BytesPath),
+    /// Remove a non-directory or an empty directory
+    Remove(BytesPath),
+    Rename {
+        from: BytesPath,
+        to: BytesPath,
+    },
+    Symlink {
+        path: BytesPath,
+        target: BytesPath,
+    },
+}
+
+impl Filesystem {
+    /// Apply every [Op] in order, stopping at the first one that fails. Any
+    /// ops before the failing one are left applied.
+    pub fn apply(&mut self, ops: impl IntoIterator<Item = Op>) -> Result<()> {
+        for op in ops {
+            self.apply_op(op)?;
+        }
+        Ok(())
+    }
+
+    fn apply_op(&mut self, op: Op) -> Result<()> {
+        #[remain::sorted]
+        match op {
+            Op::Chmod { path, mode } => self.chmod(path, mode),
+            Op::Chown { path, uid, gid } => self.chown(path, uid, gid),
+            Op::CreateFile { path, contents } => {
+                self.create(path, File::builder().contents(contents).build())
+            }
+            Op::Mkdir(path) => self.create(path, Directory::default()),
+            Op::Remove(path) => match self.get(&path)?.is_directory() {
+                true => self.rmdir(path),
+                false => self.unlink(path),
+            },
+            Op::Rename { from, to } => self.rename(from, to),
+            Op::Symlink { path, target } => self.symlink(path, target),
+        }
+    }
+
+    /// Insert a new entry, failing if `path` already exists or its parent is
+    /// not a directory.
+    pub(crate) fn create(&mut self, path: BytesPath, entry: impl Into<Entry>) -> Result<()> {
+        let path = path.try_normalize()?;
+        self.check_new_path(&path)?;
+        self.insert(path, entry);
+        Ok(())
+    }
+
+    /// Make sure that a new entry can be created at the (normalized) `path`:
+    /// it must not already exist and its parent must be a directory.
+    pub(crate) fn check_new_path(&self, path: &BytesPath) -> Result<()> {
+        if self.exists(path) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("'{}' already exists", path.display()),
+            ));
+        }
+        if let Some(parent) = path.parent() {
+            if !self.is_dir(parent) {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("parent of '{}' is not a directory", path.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn apply() {
+        let mut fs = Filesystem::new();
+        fs.apply([
+            Op::Mkdir("".into()),
+            Op::Mkdir("testdata".into()),
+            Op::Mkdir("testdata/dir".into()),
+            Op::CreateFile {
+                path: "testdata/lorem.txt".into(),
+                contents: "Lorem ipsum\n".into(),
+            },
+            Op::CreateFile {
+                path: "testdata/dir/ipsum.txt".into(),
+                contents: "Lorem ipsum dolor sit amet\n".into(),
+            },
+            Op::Rename {
+                from: "testdata/dir/ipsum.txt".into(),
+                to: "testdata/dir/lorem.txt".into(),
+            },
+            Op::Symlink {
+                path: "testdata/dir/symlink".into(),
+                target: "../lorem.txt".into(),
+            },
+            Op::CreateFile {
+                path: "testdata/extra".into(),
+                contents: Bytes::new(),
+            },
+            Op::Remove("testdata/extra".into()),
+        ])
+        .expect("failed to apply");
+        let mut ops = Vec::new();
+        for (path, entry) in &demo_fs() {
+            let path = BytesPath::from(path);
+            ops.push(Op::Chmod {
+                path: path.clone(),
+                mode: entry.metadata().mode(),
+            });
+            ops.push(Op::Chown {
+                path,
+                uid: entry.metadata().uid(),
+                gid: entry.metadata().gid(),
+            });
+        }
+        fs.apply(ops).expect("failed to apply");
+        fs.get_mut("testdata/lorem.txt")
+            .unwrap()
+            .set_xattr("user.demo", "lorem ipsum");
+        assert_eq!(fs, demo_fs());
+    }
+
+    #[test]
+    fn apply_errors() {
+        let mut fs = demo_fs();
+        for op in [
+            Op::Mkdir("testdata".into()),
+            Op::Mkdir("missing/dir".into()),
+            Op::CreateFile {
+                path: "testdata/lorem.txt/file".into(),
+                contents: Bytes::new(),
+            },
+            Op::Remove("testdata".into()),
+            Op::Remove("missing".into()),
+        ] {
+            assert!(fs.apply([op.clone()]).is_err(), "{op:?}");
+        }
+        assert_eq!(fs, demo_fs());
+
+        // ops before the failure are still applied
+        assert!(fs
+            .apply([
+                Op::Remove("testdata/lorem.txt".into()),
+                Op::Remove("testdata/lorem.txt".into()),
+            ])
+            .is_err());
+        assert!(!fs.exists("testdata/lorem.txt"));
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::path::Path;
+
+use nix::sys::stat::SFlag;
+
+use crate::entry::Entry;
+use crate::entry::Metadata;
+use crate::entry::Special;
+use crate::path::normalize;
+use crate::Filesystem;
+use crate::MergePolicy;
+
+/// xattr that marks a directory in an upper layer as opaque, hiding
+/// everything underneath it in the lower layers. Same as overlayfs.
+pub const OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+impl Special {
+    /// A whiteout hides the entry at the same path in lower layers of an
+    /// [OverlayFs]. Like overlayfs, this is a character device with a device
+    /// number of 0/0.
+    pub fn whiteout() -> Self {
+        Self::new(SFlag::S_IFCHR, 0, Metadata::default())
+    }
+
+    pub fn is_whiteout(&self) -> bool {
+        self.file_type() == SFlag::S_IFCHR && self.rdev().is_none()
+    }
+}
+
+impl Entry {
+    /// See [Special::whiteout]
+    pub fn whiteout() -> Self {
+        Self::Special(Special::whiteout())
+    }
+
+    pub fn is_whiteout(&self) -> bool {
+        matches!(self, Self::Special(s) if s.is_whiteout())
+    }
+}
+
+/// Whether `entry` is a directory marked with [OPAQUE_XATTR]
+pub(crate) fn is_opaque(entry: &Entry) -> bool {
+    entry.is_directory()
+        && entry
+            .metadata()
+            .xattrs()
+            .get(OPAQUE_XATTR.as_bytes())
+            .is_some_and(|v| v.as_ref() == b"y")
+}
+
+/// A read-only view of a stack of [Filesystem] layers, where entries in upper
+/// layers take precedence over the same paths in lower layers. An upper layer
+/// can delete a path with a [Special::whiteout], or hide all the contents of
+/// a directory in lower layers by marking it with [OPAQUE_XATTR].
+#[derive(Debug, Clone, Default)]
+pub struct OverlayFs {
+    /// Bottom layer first
+    layers: Vec<Filesystem>,
+}
+
+impl OverlayFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a new top-most layer
+    pub fn push(&mut self, layer: Filesystem) {
+        self.layers.push(layer);
+    }
+
+    /// All the layers, bottom layer first
+    pub fn layers(&self) -> &[Filesystem] {
+        &self.layers
+    }
+
+    /// Find the entry at `path` in the highest layer that has it, unless it
+    /// was deleted by a whiteout (of the path itself or any of its parents) or
+    /// hidden by an opaque directory in a higher layer.
+    pub fn get<P>(&self, path: P) -> Result<&Entry>
+    where
+        P: AsRef<Path>,
+    {
+        let path = &*normalize(path.as_ref());
+        let not_found = || {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("'{}' not found", path.display()),
+            )
+        };
+        for layer in self.layers.iter().rev() {
+            let mut opaque = false;
+            for ancestor in path.ancestors().skip(1) {
+                if let Ok(entry) = layer.get(ancestor) {
+                    if !entry.is_directory() {
+                        // whiteouts and anything else that is not a directory
+                        // hide everything underneath them
+                        return Err(not_found());
+                    }
+                    opaque |= is_opaque(entry);
+                }
+            }
+            match layer.get(path) {
+                Ok(entry) if entry.is_whiteout() => return Err(not_found()),
+                Ok(entry) => return Ok(entry),
+                Err(_) if opaque => return Err(not_found()),
+                Err(_) => {}
+            }
+        }
+        Err(not_found())
+    }
+
+    /// Collapse all the layers into a single [Filesystem], applying whiteouts
+    /// and opaque directories along the way. Neither end up in the result.
+    pub fn flatten(self) -> Filesystem {
+        let mut layers = self.layers.into_iter();
+        let mut fs = match layers.next() {
+            Some(bottom) => strip_markers(bottom),
+            None => return Filesystem::new(),
+        };
+        for mut layer in layers {
+            let markers: Vec<_> = layer
+                .iter()
+                .filter(|(_, entry)| entry.is_whiteout() || is_opaque(entry))
+                .map(|(path, entry)| (path.to_owned(), entry.is_whiteout()))
+                .collect();
+            for (path, whiteout) in markers {
+                match fs.get(&path).map(Entry::is_directory) {
+                    Ok(true) => fs.remove_dir_all(&path).expect("this is a directory"),
+                    Ok(false) => fs.unlink(&path).expect("this exists"),
+                    Err(_) => {}
+                }
+                if whiteout {
+                    layer.unlink(&path).expect("this exists");
+                }
+            }
+            fs.merge(strip_markers(layer), MergePolicy::Overwrite)
+                .expect("overwriting never fails");
+        }
+        fs
+    }
+}
+
+/// Remove whiteouts and opaque xattrs from the bottom layer, where they have
+/// nothing to apply to.
+fn strip_markers(mut fs: Filesystem) -> Filesystem {
+    let whiteouts: Vec<_> = fs
+        .iter()
+        .filter(|(_, entry)| entry.is_whiteout())
+        .map(|(path, _)| path.to_owned())
+        .collect();
+    for path in whiteouts {
+        fs.unlink(path).expect("this exists");
+    }
+    for entry in fs.inodes.values_mut() {
+        if is_opaque(entry) {
+            entry.remove_xattr(&OPAQUE_XATTR.into());
+        }
+    }
+    fs
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::entry::Directory;
+    use crate::tests::demo_fs;
+    use crate::File;
+
+    fn overlay() -> OverlayFs {
+        let mut upper = Filesystem::new();
+        upper.insert(
+            "testdata/lorem.txt",
+            File::builder().contents("consectetur\n").build(),
+        );
+        upper.insert("testdata/dir/symlink", Special::whiteout());
+        upper.insert(
+            "testdata/new.txt",
+            File::builder().contents("new\n").build(),
+        );
+        let mut overlay = OverlayFs::new();
+        overlay.push(demo_fs());
+        overlay.push(upper);
+        overlay
+    }
+
+    #[test]
+    fn upper_wins() {
+        let overlay = overlay();
+        assert_eq!(
+            overlay
+                .get("testdata/lorem.txt")
+                .unwrap()
+                .as_file()
+                .unwrap()
+                .to_bytes()
+                .as_ref(),
+            b"consectetur\n",
+        );
+        assert_eq!(
+            overlay.get("testdata/dir/lorem.txt").unwrap(),
+            demo_fs().get("testdata/dir/lorem.txt").unwrap(),
+        );
+        assert!(overlay.get("testdata/new.txt").is_ok());
+        assert_eq!(overlay.layers().len(), 2);
+    }
+
+    #[test]
+    fn whiteout() {
+        let mut overlay = overlay();
+        assert_eq!(
+            overlay.get("testdata/dir/symlink").unwrap_err().kind(),
+            ErrorKind::NotFound,
+        );
+        // whiting out a directory hides everything underneath it
+        let mut top = Filesystem::new();
+        top.insert("testdata/dir", Special::whiteout());
+        overlay.push(top);
+        assert!(overlay.get("testdata/dir").is_err());
+        assert!(overlay.get("testdata/dir/lorem.txt").is_err());
+        assert!(overlay.get("testdata/lorem.txt").is_ok());
+    }
+
+    #[test]
+    fn opaque() {
+        let mut overlay = overlay();
+        let mut top = Filesystem::new();
+        let mut dir = Entry::from(Directory::default());
+        dir.set_xattr(OPAQUE_XATTR, "y");
+        top.insert("testdata", dir);
+        top.insert("testdata/top.txt", File::new_empty());
+        overlay.push(top);
+        assert!(overlay.get("testdata").is_ok());
+        assert!(overlay.get("testdata/top.txt").is_ok());
+        assert!(overlay.get("testdata/lorem.txt").is_err());
+        assert!(overlay.get("testdata/dir/lorem.txt").is_err());
+        assert!(overlay.get("").is_ok());
+
+        let flat = overlay.flatten();
+        assert_eq!(
+            flat.iter().map(|(p, _)| p.to_owned()).collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new("testdata"),
+                Path::new("testdata/top.txt")
+            ],
+        );
+        assert!(flat.get("testdata").unwrap().metadata().xattrs().is_empty());
+    }
+
+    #[test]
+    fn flatten() {
+        let overlay = overlay();
+        let flat = overlay.clone().flatten();
+        let mut expected = demo_fs();
+        expected.insert(
+            "testdata/lorem.txt",
+            File::builder().contents("consectetur\n").build(),
+        );
+        expected.unlink("testdata/dir/symlink").unwrap();
+        expected.insert(
+            "testdata/new.txt",
+            File::builder().contents("new\n").build(),
+        );
+        assert_eq!(flat, expected);
+        for (path, entry) in &flat {
+            assert_eq!(overlay.get(path).unwrap(), entry, "{}", path.display());
+        }
+    }
+}
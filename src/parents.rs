@@ -0,0 +1,150 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+use nix::sys::stat::Mode;
+
+use crate::entry::Directory;
+use crate::entry::Entry;
+use crate::entry::Metadata;
+use crate::BytesPath;
+use crate::Filesystem;
+use crate::Gid;
+use crate::InodeKey;
+use crate::Uid;
+
+/// How [Filesystem::insert_with_parents] creates missing parent directories
+#[derive(Debug, Clone)]
+pub struct ParentOptions {
+    /// Mode of every created directory. Defaults to 0755.
+    pub mode: Mode,
+    /// Owner of every created directory, or `None` (the default) to use the
+    /// same owner as the entry being inserted.
+    pub owner: Option<(Uid, Gid)>,
+}
+
+impl Default for ParentOptions {
+    fn default() -> Self {
+        Self {
+            mode: Mode::from_bits_truncate(0o755),
+            owner: None,
+        }
+    }
+}
+
+impl Filesystem {
+    /// Like [Filesystem::insert], but first create any missing parent
+    /// directories of `path` (up to and including the root) as described by
+    /// `options`, like `mkdir -p`. Fails without changing anything if one of
+    /// the existing parents is not a directory.
+    pub fn insert_with_parents(
+        &mut self,
+        path: impl Into<BytesPath>,
+        entry: impl Into<Entry>,
+        options: &ParentOptions,
+    ) -> Result<InodeKey> {
+        let path = path.into().try_normalize()?;
+        let entry = entry.into();
+        let mut missing = Vec::new();
+        let mut parent = path.parent();
+        while let Some(dir) = parent {
+            match self.get(dir) {
+                Ok(e) if e.is_directory() => break,
+                Ok(_) => {
+                    return Err(Error::new(
+                        ErrorKind::NotADirectory,
+                        format!("'{}' is not a directory", dir.display()),
+                    ));
+                }
+                Err(_) => missing.push(dir.to_path_buf()),
+            }
+            parent = dir.parent();
+        }
+        let (uid, gid) = options
+            .owner
+            .unwrap_or((entry.metadata().uid(), entry.metadata().gid()));
+        for dir in missing.into_iter().rev() {
+            self.insert(
+                dir,
+                Directory::builder()
+                    .metadata(
+                        Metadata::builder()
+                            .mode(options.mode)
+                            .uid(uid)
+                            .gid(gid)
+                            .build(),
+                    )
+                    .build(),
+            );
+        }
+        Ok(self.insert(path, entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+    use crate::File;
+
+    #[test]
+    fn insert_with_parents() {
+        let mut fs = demo_fs();
+        let mut file = File::new_empty();
+        file.set_uid(Uid::from_raw(1000));
+        file.set_gid(Gid::from_raw(1001));
+        fs.insert_with_parents("testdata/a/b/c.txt", file, &ParentOptions::default())
+            .expect("failed to insert");
+        for dir in ["testdata/a", "testdata/a/b"] {
+            let metadata = fs.get(dir).expect("missing parent").metadata();
+            assert!(fs.is_dir(dir));
+            assert_eq!(metadata.mode(), Mode::from_bits_truncate(0o755));
+            assert_eq!(metadata.uid(), Uid::from_raw(1000));
+            assert_eq!(metadata.gid(), Gid::from_raw(1001));
+        }
+        // existing parents are left alone
+        assert_eq!(
+            fs.get("testdata").unwrap(),
+            demo_fs().get("testdata").unwrap()
+        );
+        assert!(fs.is_file("testdata/a/b/c.txt"));
+
+        let mut fs = Filesystem::new();
+        fs.insert_with_parents(
+            "/x/y",
+            Directory::default(),
+            &ParentOptions {
+                mode: Mode::from_bits_truncate(0o700),
+                owner: Some((Uid::from_raw(1), Gid::from_raw(2))),
+            },
+        )
+        .expect("failed to insert");
+        assert_eq!(
+            fs.iter().map(|(p, _)| p.to_owned()).collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new("x"), Path::new("x/y")],
+        );
+        let metadata = fs.get("").unwrap().metadata();
+        assert_eq!(metadata.mode(), Mode::from_bits_truncate(0o700));
+        assert_eq!(
+            (metadata.uid(), metadata.gid()),
+            (Uid::from_raw(1), Gid::from_raw(2))
+        );
+
+        let mut fs = demo_fs();
+        assert_eq!(
+            fs.insert_with_parents(
+                "testdata/lorem.txt/a/b",
+                File::new_empty(),
+                &ParentOptions::default()
+            )
+            .unwrap_err()
+            .kind(),
+            ErrorKind::NotADirectory,
+        );
+        assert_eq!(fs, demo_fs());
+    }
+}
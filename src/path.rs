@@ -1,8 +1,13 @@
 use std::borrow::Borrow;
+use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::fmt::Debug;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
 use std::ops::Deref;
 use std::os::unix::ffi::OsStrExt;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -27,6 +32,80 @@ impl BytesPath {
     pub fn as_path(&self) -> &Path {
         self
     }
+
+    /// Copy the path into its own allocation, see [crate::Filesystem::into_owned]
+    pub(crate) fn into_owned(self) -> Self {
+        Self(Bytes::copy_from_slice(&self.0))
+    }
+
+    /// Paths are always stored relative to the root of the filesystem, which
+    /// is itself keyed as the empty path "". Any leading '/' is dropped, as
+    /// are '.' components, repeated separators and trailing separators, so
+    /// "/", "." and "./" all refer to the root, and "/a//b/" is "a/b". '..'
+    /// is left alone, since resolving it would require following symlinks,
+    /// but a '..' that would climb above the root (like "../a" or
+    /// "a/../../b") is rejected with [ErrorKind::InvalidInput].
+    pub(crate) fn try_normalize(self) -> Result<Self> {
+        if is_normalized(&self.0) {
+            return Ok(self);
+        }
+        let leading = self.0.iter().take_while(|b| **b == b'/').count();
+        let stripped = self.0.slice(leading..);
+        if is_normalized(&stripped) {
+            return Ok(Self(stripped));
+        }
+        let path = Path::new(OsStr::from_bytes(&self.0));
+        match rebuild(path) {
+            Some(rebuilt) => Ok(Self::from(rebuilt)),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' escapes the root", path.display()),
+            )),
+        }
+    }
+
+    /// Infallible version of [BytesPath::try_normalize].
+    ///
+    /// # Panics
+    /// If the path escapes the root.
+    pub(crate) fn normalize(self) -> Self {
+        self.try_normalize().unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+/// Fast path for paths that are already normalized, which is almost all of
+/// them. Anything with a '..' is checked by [rebuild].
+fn is_normalized(bytes: &[u8]) -> bool {
+    bytes.is_empty()
+        || bytes
+            .split(|b| *b == b'/')
+            .all(|c| !c.is_empty() && c != b"." && c != b"..")
+}
+
+/// Normalize `path` component by component, or `None` if it escapes the root.
+fn rebuild(path: &Path) -> Option<PathBuf> {
+    let mut depth = 0usize;
+    path.components()
+        .filter(|c| !matches!(c, Component::RootDir | Component::CurDir))
+        .map(|c| {
+            depth = match c {
+                Component::ParentDir => depth.checked_sub(1)?,
+                _ => depth + 1,
+            };
+            Some(c)
+        })
+        .collect()
+}
+
+/// See [BytesPath::normalize]. This is for looking up paths, so a path that
+/// escapes the root is returned as-is: it can never be found.
+pub(crate) fn normalize(path: &Path) -> Cow<'_, Path> {
+    let bytes = path.as_os_str().as_bytes();
+    let leading = bytes.iter().take_while(|b| **b == b'/').count();
+    match is_normalized(&bytes[leading..]) {
+        true => Cow::Borrowed(Path::new(OsStr::from_bytes(&bytes[leading..]))),
+        false => rebuild(path).map_or(Cow::Borrowed(path), Cow::Owned),
+    }
 }
 
 impl Deref for BytesPath {
@@ -76,6 +155,12 @@ impl From<&'static [u8]> for BytesPath {
     }
 }
 
+impl From<String> for BytesPath {
+    fn from(value: String) -> Self {
+        Self(value.into())
+    }
+}
+
 impl From<&Path> for BytesPath {
     fn from(value: &Path) -> Self {
         Self(Bytes::copy_from_slice(value.as_os_str().as_bytes()))
@@ -0,0 +1,137 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::path::Path;
+
+use nix::sys::stat::Mode;
+
+use crate::entry::Directory;
+use crate::entry::Metadata;
+use crate::path::normalize;
+use crate::BytesPath;
+use crate::Filesystem;
+
+impl Filesystem {
+    /// Move every entry underneath `prefix`, so the root directory ends up at
+    /// `prefix` itself. Directories leading up to `prefix` are created as
+    /// root-owned 0755 directories. Hardlinks are preserved.
+    ///
+    /// # Panics
+    /// If `prefix` escapes the root (see [BytesPath]).
+    pub fn with_prefix(mut self, prefix: impl AsRef<Path>) -> Self {
+        let prefix = BytesPath::from(prefix.as_ref()).normalize();
+        if prefix.is_empty() {
+            return self;
+        }
+        self.paths = std::mem::take(&mut self.paths)
+            .into_iter()
+            .map(|(path, key)| {
+                let path = match path.is_empty() {
+                    true => prefix.clone(),
+                    false => prefix.join(path).into(),
+                };
+                (path, key)
+            })
+            .collect();
+        let mut parent = Some(prefix.as_path());
+        while let Some(dir) = parent {
+            if !self.paths.contains_key(dir) {
+                self.insert(
+                    dir,
+                    Directory::builder()
+                        .metadata(
+                            Metadata::builder()
+                                .mode(Mode::from_bits_truncate(0o755))
+                                .build(),
+                        )
+                        .build(),
+                );
+            }
+            parent = dir.parent();
+        }
+        self
+    }
+
+    /// The inverse of [Filesystem::with_prefix]: the directory at `prefix`
+    /// becomes the new root and everything outside of it is dropped.
+    pub fn strip_prefix(mut self, prefix: impl AsRef<Path>) -> Result<Self> {
+        let prefix = &*normalize(prefix.as_ref());
+        if !self.get(prefix)?.is_directory() {
+            return Err(Error::new(
+                ErrorKind::NotADirectory,
+                format!("'{}' is not a directory", prefix.display()),
+            ));
+        }
+        let mut released = Vec::new();
+        self.paths = std::mem::take(&mut self.paths)
+            .into_iter()
+            .filter_map(|(path, key)| match path.strip_prefix(prefix) {
+                Ok(rel) => Some((BytesPath::from(rel), key)),
+                Err(_) => {
+                    released.push(key);
+                    None
+                }
+            })
+            .collect();
+        for key in released {
+            self.release(key);
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn with_prefix() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        let prefixed = fs.clone().with_prefix("/opt/app");
+        for dir in ["", "opt"] {
+            let entry = prefixed.get(dir).expect("missing parent");
+            assert!(entry.is_directory());
+            assert_eq!(entry.metadata().mode(), Mode::from_bits_truncate(0o755));
+        }
+        assert_eq!(prefixed.get("opt/app").unwrap(), demo_fs().get("").unwrap());
+        assert_eq!(
+            prefixed.get("/opt/app/testdata/dir/lorem.txt").unwrap(),
+            demo_fs().get("testdata/dir/lorem.txt").unwrap(),
+        );
+        assert_eq!(prefixed.nlink("opt/app/testdata/hardlink.txt").unwrap(), 2);
+        assert_eq!(prefixed.iter().count(), fs.iter().count() + 2);
+
+        let stripped = prefixed.strip_prefix("opt/app").expect("failed to strip");
+        assert_eq!(stripped, fs);
+        assert_eq!(stripped.inodes.len(), fs.inodes.len());
+    }
+
+    #[test]
+    fn strip_prefix() {
+        let fs = demo_fs()
+            .strip_prefix("testdata/dir")
+            .expect("failed to strip");
+        assert_eq!(fs.get("").unwrap(), demo_fs().get("testdata/dir").unwrap());
+        assert!(fs.get("lorem.txt").is_ok());
+        assert!(fs.get("symlink").is_ok());
+        assert_eq!(fs.iter().count(), 3);
+        assert_eq!(fs.inodes.len(), 3);
+
+        assert_eq!(
+            demo_fs()
+                .strip_prefix("testdata/lorem.txt")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotADirectory,
+        );
+        assert_eq!(
+            demo_fs().strip_prefix("opt").unwrap_err().kind(),
+            ErrorKind::NotFound,
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! [serde] support. Most types just derive [Serialize] and [Deserialize], but
+//! some fields need a bit of help to get a stable representation.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use std::os::unix::ffi::OsStrExt;
+
+use bytes::Bytes;
+use serde::de::Error as _;
+use serde::de::SeqAccess;
+use serde::de::Visitor;
+use serde::ser::SerializeStruct;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+use crate::entry::Entry;
+use crate::file::extent::Extent;
+use crate::BytesPath;
+use crate::Filesystem;
+use crate::InodeKey;
+
+/// Raw bytes are serialized as a string when they are valid utf8 and the
+/// format is human readable (paths and xattrs almost always are), otherwise
+/// as plain bytes.
+pub(crate) mod byte_str {
+    use super::*;
+
+    pub(crate) fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match std::str::from_utf8(bytes) {
+            Ok(s) if serializer.is_human_readable() => serializer.serialize_str(s),
+            _ => serializer.serialize_bytes(bytes),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Bytes, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match deserializer.is_human_readable() {
+            true => deserializer.deserialize_any(BytesVisitor),
+            false => deserializer.deserialize_byte_buf(BytesVisitor),
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Bytes;
+
+        fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+            f.write_str("a string or bytes")
+        }
+
+        fn visit_str<E>(self, v: &str) -> Result<Bytes, E> {
+            Ok(Bytes::copy_from_slice(v.as_bytes()))
+        }
+
+        fn visit_string<E>(self, v: String) -> Result<Bytes, E> {
+            Ok(v.into())
+        }
+
+        fn visit_bytes<E>(self, v: &[u8]) -> Result<Bytes, E> {
+            Ok(Bytes::copy_from_slice(v))
+        }
+
+        fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Bytes, E> {
+            Ok(v.into())
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Bytes, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                v.push(b);
+            }
+            Ok(v.into())
+        }
+    }
+
+    /// Wrapper to use [self] for map keys and values
+    #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    pub(crate) struct Wrapper(pub(crate) Bytes);
+
+    impl Serialize for Wrapper {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serialize(&self.0, serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Wrapper {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserialize(deserializer).map(Self)
+        }
+    }
+}
+
+/// xattr names and values, see [byte_str]
+pub(crate) mod xattrs {
+    use super::byte_str::Wrapper;
+    use super::*;
+
+    pub(crate) fn serialize<S>(
+        xattrs: &BTreeMap<Bytes, Bytes>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(
+            xattrs
+                .iter()
+                .map(|(k, v)| (Wrapper(k.clone()), Wrapper(v.clone()))),
+        )
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<BTreeMap<Bytes, Bytes>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let map = BTreeMap::<Wrapper, Wrapper>::deserialize(deserializer)?;
+        Ok(map.into_iter().map(|(k, v)| (k.0, v.0)).collect())
+    }
+}
+
+/// [nix::sys::stat::Mode] as its raw integer value
+pub(crate) mod mode {
+    use nix::sys::stat::Mode;
+
+    use super::*;
+
+    pub(crate) fn serialize<S>(mode: &Mode, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        mode.bits().serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Mode, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Mode::from_bits_truncate(Deserialize::deserialize(
+            deserializer,
+        )?))
+    }
+}
+
+/// [nix::sys::stat::SFlag] as its raw integer value
+pub(crate) mod sflag {
+    use nix::sys::stat::SFlag;
+
+    use super::*;
+
+    pub(crate) fn serialize<S>(sflag: &SFlag, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        sflag.bits().serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<SFlag, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(SFlag::from_bits_truncate(Deserialize::deserialize(
+            deserializer,
+        )?))
+    }
+}
+
+impl Serialize for BytesPath {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        byte_str::serialize(self.as_os_str().as_bytes(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BytesPath {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        byte_str::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Extents are serialized as their materialized data, so a [Extent::Cloned]
+/// loses the reference to its source and comes back as [Extent::Owned].
+#[derive(Serialize, Deserialize)]
+enum ExtentRepr {
+    Data(#[serde(with = "byte_str")] Bytes),
+    Hole(u64),
+}
+
+impl Serialize for Extent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Hole(len) => ExtentRepr::Hole(*len),
+            _ => ExtentRepr::Data(self.bytes()),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Extent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match ExtentRepr::deserialize(deserializer)? {
+            ExtentRepr::Data(data) => Self::Owned(data),
+            ExtentRepr::Hole(len) => Self::Hole(len),
+        })
+    }
+}
+
+/// A [Filesystem] is serialized as a list of entries, and a map of paths to
+/// the index of the entry in that list, so that hardlinks are preserved.
+impl Serialize for Filesystem {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut indices: HashMap<InodeKey, usize> = HashMap::new();
+        let mut inodes: Vec<&Entry> = Vec::new();
+        let mut paths: BTreeMap<&BytesPath, usize> = BTreeMap::new();
+        for (path, key) in &self.paths {
+            let idx = *indices.entry(*key).or_insert_with(|| {
+                inodes.push(&self.inodes[*key]);
+                inodes.len() - 1
+            });
+            paths.insert(path, idx);
+        }
+        let mut s = serializer.serialize_struct("Filesystem", 2)?;
+        s.serialize_field("inodes", &inodes)?;
+        s.serialize_field("paths", &paths)?;
+        s.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Filesystem")]
+struct FilesystemRepr {
+    inodes: Vec<Entry>,
+    paths: BTreeMap<BytesPath, usize>,
+}
+
+impl<'de> Deserialize<'de> for Filesystem {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let FilesystemRepr { inodes, paths } = FilesystemRepr::deserialize(deserializer)?;
+        let mut fs = Self::new();
+        let mut first_paths: HashMap<usize, BytesPath> = HashMap::new();
+        for (path, idx) in paths {
+            match first_paths.get(&idx) {
+                Some(target) => fs.link(target, path).map_err(D::Error::custom)?,
+                None => {
+                    let entry = inodes.get(idx).ok_or_else(|| {
+                        D::Error::custom(format!(
+                            "'{}' refers to missing inode {idx}",
+                            path.display()
+                        ))
+                    })?;
+                    fs.insert(path.clone(), entry.clone());
+                    first_paths.insert(idx, path);
+                }
+            }
+        }
+        Ok(fs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+    use crate::File;
+
+    #[test]
+    fn json_round_trip() {
+        let fs = demo_fs();
+        let json = serde_json::to_string_pretty(&fs).expect("failed to serialize");
+        let deserialized: Filesystem = serde_json::from_str(&json).expect("failed to deserialize");
+        assert_eq!(fs, deserialized);
+    }
+
+    #[test]
+    fn hardlinks_and_holes() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        let mut f = File::builder().contents(&b"\xff\xfe"[..]).build();
+        f.truncate(10);
+        fs.insert("testdata/binary", f);
+        let json = serde_json::to_string(&fs).expect("failed to serialize");
+        let deserialized: Filesystem = serde_json::from_str(&json).expect("failed to deserialize");
+        assert_eq!(fs, deserialized);
+        assert_eq!(deserialized.nlink("testdata/hardlink.txt").unwrap(), 2);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use crate::entry::Entry;
+use crate::file::extent::Extent;
+use crate::File;
+use crate::Filesystem;
+
+/// Size that a directory is assumed to take up, since its real size depends
+/// on the filesystem that it is eventually written to.
+pub const DIRECTORY_SIZE: u64 = 4096;
+
+/// Summary of a [Filesystem], from [Filesystem::stats]. Hardlinked entries
+/// are only counted once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsStats {
+    pub files: usize,
+    pub directories: usize,
+    pub symlinks: usize,
+    /// Same as [Filesystem::apparent_size]
+    pub apparent_size: u64,
+    /// Path and length of the biggest regular file. Ties go to the first path
+    /// in sorted order.
+    pub largest_file: Option<(PathBuf, u64)>,
+}
+
+impl Filesystem {
+    /// Count up the entries and their sizes in a single pass
+    pub fn stats(&self) -> FsStats {
+        let mut stats = FsStats::default();
+        let mut seen = HashSet::new();
+        for (path, key) in &self.paths {
+            if !seen.insert(*key) {
+                continue;
+            }
+            let entry = &self.inodes[*key];
+            stats.apparent_size += apparent_size(entry);
+            match entry {
+                Entry::Directory(_) => stats.directories += 1,
+                Entry::File(f) => {
+                    stats.files += 1;
+                    if stats
+                        .largest_file
+                        .as_ref()
+                        .is_none_or(|(_, len)| f.len() > *len)
+                    {
+                        stats.largest_file = Some((path.to_path_buf(), f.len()));
+                    }
+                }
+                Entry::Special(_) => {}
+                Entry::Symlink(_) => stats.symlinks += 1,
+            }
+        }
+        stats
+    }
+
+    /// Sum of the sizes of every entry, as reported by `stat`: the length of
+    /// a file, the length of a symlink's target and [DIRECTORY_SIZE] for each
+    /// directory. Hardlinked entries are only counted once.
+    pub fn apparent_size(&self) -> u64 {
+        self.inodes.values().map(apparent_size).sum()
+    }
+
+    /// Like [Filesystem::apparent_size], but without holes in sparse files,
+    /// to approximate how much space the filesystem would actually use.
+    pub fn allocated_size(&self) -> u64 {
+        self.inodes
+            .values()
+            .map(|entry| match entry {
+                Entry::File(f) => f
+                    .extents
+                    .values()
+                    .filter(|e| !matches!(e, Extent::Hole(_)))
+                    .map(Extent::len)
+                    .sum(),
+                entry => apparent_size(entry),
+            })
+            .sum()
+    }
+
+    /// Total number of [Extent]s across all the files. Hardlinked files are
+    /// only counted once.
+    pub fn total_extents(&self) -> usize {
+        self.files().map(File::extent_count).sum()
+    }
+
+    /// Like [File::fragmentation_ratio], but for all the file data in the
+    /// filesystem.
+    pub fn fragmentation_ratio(&self) -> f64 {
+        match self.files().map(File::len).sum::<u64>() {
+            0 => 0.0,
+            len => self.total_extents() as f64 / len as f64,
+        }
+    }
+
+    /// Every unique file inode
+    fn files(&self) -> impl Iterator<Item = &File> {
+        self.inodes.values().filter_map(Entry::as_file)
+    }
+}
+
+/// See [Filesystem::apparent_size]
+pub(crate) fn apparent_size(entry: &Entry) -> u64 {
+    match entry {
+        Entry::Directory(_) => DIRECTORY_SIZE,
+        Entry::File(f) => f.len(),
+        Entry::Special(_) => 0,
+        Entry::Symlink(s) => s.target().as_os_str().len() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn sizes() {
+        let mut fs = demo_fs();
+        let dirs = 3 * DIRECTORY_SIZE;
+        let files = ("Lorem ipsum\n".len() + "Lorem ipsum dolor sit amet\n".len()) as u64;
+        let symlink = "../lorem.txt".len() as u64;
+        assert_eq!(fs.apparent_size(), dirs + files + symlink);
+        assert_eq!(fs.allocated_size(), fs.apparent_size());
+
+        let mut sparse = File::builder().contents("Lorem ipsum").build();
+        sparse.truncate(1 << 20);
+        fs.insert("testdata/sparse", sparse);
+        fs.link("testdata/sparse", "testdata/sparse_link")
+            .expect("failed to link");
+        assert_eq!(fs.apparent_size(), dirs + files + symlink + (1 << 20));
+        assert_eq!(
+            fs.allocated_size(),
+            dirs + files + symlink + "Lorem ipsum".len() as u64
+        );
+        assert!(fs.apparent_size() > fs.allocated_size());
+    }
+
+    #[test]
+    fn stats() {
+        let mut fs = demo_fs();
+        let expected = FsStats {
+            files: 2,
+            directories: 3,
+            symlinks: 1,
+            apparent_size: fs.apparent_size(),
+            largest_file: Some((
+                "testdata/dir/lorem.txt".into(),
+                "Lorem ipsum dolor sit amet\n".len() as u64,
+            )),
+        };
+        assert_eq!(fs.stats(), expected);
+        fs.link("testdata/lorem.txt", "testdata/lorem_link.txt")
+            .expect("failed to link");
+        assert_eq!(fs.stats(), expected);
+        assert_eq!(Filesystem::new().stats(), FsStats::default());
+    }
+
+    #[test]
+    fn extents() {
+        let mut fs = demo_fs();
+        assert_eq!(fs.total_extents(), 2);
+        fs.link("testdata/lorem.txt", "testdata/lorem_link.txt")
+            .expect("failed to link");
+        assert_eq!(fs.total_extents(), 2);
+        let mut f = File::builder().contents("Lorem ipsum").build();
+        f.append(" dolor sit amet").expect("failed to append");
+        f.truncate(100);
+        fs.insert("testdata/fragmented", f);
+        assert_eq!(fs.total_extents(), 5);
+        assert_eq!(
+            fs.fragmentation_ratio(),
+            5.0 / ("Lorem ipsum\n".len() + "Lorem ipsum dolor sit amet\n".len() + 100) as f64
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Simple self-describing flat archive format, so that an entire [Filesystem]
+//! can be streamed through anything that implements [Read].
+//!
+//! The stream starts with [MAGIC], followed by one record per path in sorted
+//! order. Every integer is little-endian and every byte string is prefixed by
+//! its length as a u32. A record is:
+//!  - path
+//!  - type tag (one of the `TAG_` constants)
+//!  - for hardlinks, the path of the first link to the same entry, and nothing
+//!    else
+//!  - metadata: mode, uid and gid as u32s, created, accessed and modified
+//!    times as (u64 seconds, u32 nanoseconds) since the epoch, and a u32
+//!    count of xattrs followed by each name and value
+//!  - contents: u64 length and the data for files, the target for symlinks,
+//!    and the u32 file type and u64 rdev for special files
+
+use std::collections::HashMap;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Result;
+use std::os::unix::ffi::OsStrExt;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use nix::sys::stat::Mode;
+use nix::sys::stat::SFlag;
+
+use crate::entry::Directory;
+use crate::entry::Entry;
+use crate::entry::Metadata;
+use crate::entry::Special;
+use crate::entry::Symlink;
+use crate::file::reader::Reader;
+use crate::BytesPath;
+use crate::File;
+use crate::Filesystem;
+use crate::Gid;
+use crate::InodeKey;
+use crate::Uid;
+
+pub(crate) const MAGIC: &[u8; 8] = b"FSIAF\0\0\x01";
+
+const TAG_DIRECTORY: u8 = b'd';
+const TAG_FILE: u8 = b'f';
+const TAG_HARDLINK: u8 = b'h';
+const TAG_SPECIAL: u8 = b's';
+const TAG_SYMLINK: u8 = b'l';
+
+/// [Read] implementation that serializes a [Filesystem] one entry at a time,
+/// see [Filesystem::reader]
+pub struct FilesystemReader<'f> {
+    fs: &'f Filesystem,
+    paths: std::collections::btree_map::Iter<'f, BytesPath, InodeKey>,
+    first_links: HashMap<InodeKey, &'f BytesPath>,
+    /// Encoded record header that has not been read yet
+    header: Vec<u8>,
+    header_pos: usize,
+    /// Contents of the current file, read after the header
+    contents: Option<Reader<'f>>,
+}
+
+impl Filesystem {
+    /// Stream the whole filesystem in a simple flat archive format that can
+    /// be loaded again with [Filesystem::from_reader]. File contents are read
+    /// directly from their extents and never buffered.
+    pub fn reader(&self) -> FilesystemReader<'_> {
+        FilesystemReader {
+            fs: self,
+            paths: self.paths.iter(),
+            first_links: HashMap::new(),
+            header: MAGIC.to_vec(),
+            header_pos: 0,
+            contents: None,
+        }
+    }
+
+    /// Load a filesystem from the format produced by [Filesystem::reader]
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "bad magic"));
+        }
+        let mut fs = Self::new();
+        while let Some(path) = read_path_or_eof(&mut reader)? {
+            let entry: Entry = match read_u8(&mut reader)? {
+                TAG_HARDLINK => {
+                    let target = BytesPath::from(read_bytes(&mut reader)?);
+                    fs.link(target, path)?;
+                    continue;
+                }
+                TAG_DIRECTORY => {
+                    let metadata = read_metadata(&mut reader)?;
+                    Directory::builder().metadata(metadata).build().into()
+                }
+                TAG_FILE => {
+                    let metadata = read_metadata(&mut reader)?;
+                    let len = read_u64(&mut reader)?;
+                    let contents = read_len(&mut reader, len)?;
+                    File::builder()
+                        .contents(contents)
+                        .metadata(metadata)
+                        .build()
+                        .into()
+                }
+                TAG_SPECIAL => {
+                    let metadata = read_metadata(&mut reader)?;
+                    let file_type = SFlag::from_bits_truncate(read_u32(&mut reader)?);
+                    let rdev = read_u64(&mut reader)?;
+                    Special::new(file_type, rdev, metadata).into()
+                }
+                TAG_SYMLINK => {
+                    let metadata = read_metadata(&mut reader)?;
+                    let target = BytesPath::from(read_bytes(&mut reader)?);
+                    Symlink::new(target, Some(metadata)).into()
+                }
+                tag => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("'{}' has unknown type {tag:#x}", path.display()),
+                    ))
+                }
+            };
+            fs.try_insert(path, entry)?;
+        }
+        Ok(fs)
+    }
+}
+
+impl<'f> FilesystemReader<'f> {
+    /// Encode the header of the next record, returning false if there are no
+    /// more entries
+    fn next_record(&mut self) -> bool {
+        let Some((path, key)) = self.paths.next() else {
+            return false;
+        };
+        let buf = &mut self.header;
+        buf.clear();
+        self.header_pos = 0;
+        write_bytes(buf, path.as_os_str().as_bytes());
+        if let Some(first) = self.first_links.get(key) {
+            buf.push(TAG_HARDLINK);
+            write_bytes(buf, first.as_os_str().as_bytes());
+            return true;
+        }
+        if self.fs.refcounts[*key] > 1 {
+            self.first_links.insert(*key, path);
+        }
+        let entry = &self.fs.inodes[*key];
+        buf.push(match entry {
+            Entry::Directory(_) => TAG_DIRECTORY,
+            Entry::File(_) => TAG_FILE,
+            Entry::Special(_) => TAG_SPECIAL,
+            Entry::Symlink(_) => TAG_SYMLINK,
+        });
+        write_metadata(buf, entry.metadata());
+        match entry {
+            Entry::Directory(_) => {}
+            Entry::File(f) => {
+                buf.extend_from_slice(&f.len().to_le_bytes());
+                self.contents = Some(f.reader());
+            }
+            Entry::Special(s) => {
+                buf.extend_from_slice(&s.file_type().bits().to_le_bytes());
+                buf.extend_from_slice(&s.rdev().unwrap_or(0).to_le_bytes());
+            }
+            Entry::Symlink(s) => write_bytes(buf, s.target().as_os_str().as_bytes()),
+        }
+        true
+    }
+}
+
+impl<'f> Read for FilesystemReader<'f> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.header_pos < self.header.len() {
+                let pending = &self.header[self.header_pos..];
+                let len = std::cmp::min(pending.len(), buf.len());
+                buf[..len].copy_from_slice(&pending[..len]);
+                self.header_pos += len;
+                return Ok(len);
+            }
+            if let Some(contents) = &mut self.contents {
+                match contents.read(buf)? {
+                    0 if !buf.is_empty() => self.contents = None,
+                    n => return Ok(n),
+                }
+            }
+            if !self.next_record() {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn write_time(buf: &mut Vec<u8>, time: SystemTime) {
+    // times before the epoch are not supported anywhere else either
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    buf.extend_from_slice(&since_epoch.as_secs().to_le_bytes());
+    buf.extend_from_slice(&since_epoch.subsec_nanos().to_le_bytes());
+}
+
+fn write_metadata(buf: &mut Vec<u8>, metadata: &Metadata) {
+    buf.extend_from_slice(&metadata.mode().bits().to_le_bytes());
+    buf.extend_from_slice(&metadata.uid().as_u32().to_le_bytes());
+    buf.extend_from_slice(&metadata.gid().as_u32().to_le_bytes());
+    write_time(buf, metadata.created());
+    write_time(buf, metadata.accessed());
+    write_time(buf, metadata.modified());
+    buf.extend_from_slice(&(metadata.xattrs().len() as u32).to_le_bytes());
+    for (name, value) in metadata.xattrs() {
+        write_bytes(buf, name);
+        write_bytes(buf, value);
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read exactly `len` bytes. The length comes from the stream, so the buffer
+/// only grows as data actually arrives instead of trusting it up front.
+fn read_len<R: Read>(r: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> Result<Bytes> {
+    let len = read_u32(r)?;
+    Ok(read_len(r, len.into())?.into())
+}
+
+/// Read the path that starts the next record, or None if the stream ended
+/// cleanly between records
+fn read_path_or_eof<R: Read>(r: &mut R) -> Result<Option<BytesPath>> {
+    let mut first = [0; 1];
+    loop {
+        match r.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    let mut rest = [0; 3];
+    r.read_exact(&mut rest)?;
+    let len = u32::from_le_bytes([first[0], rest[0], rest[1], rest[2]]);
+    Ok(Some(Bytes::from(read_len(r, len.into())?).into()))
+}
+
+fn read_time<R: Read>(r: &mut R) -> Result<SystemTime> {
+    let secs = read_u64(r)?;
+    let nanos = read_u32(r)?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+fn read_metadata<R: Read>(r: &mut R) -> Result<Metadata> {
+    let mode = Mode::from_bits_truncate(read_u32(r)?);
+    let uid = Uid::from_raw(read_u32(r)?);
+    let gid = Gid::from_raw(read_u32(r)?);
+    let created = read_time(r)?;
+    let accessed = read_time(r)?;
+    let modified = read_time(r)?;
+    let mut xattrs = std::collections::BTreeMap::new();
+    for _ in 0..read_u32(r)? {
+        let name = read_bytes(r)?;
+        xattrs.insert(name, read_bytes(r)?);
+    }
+    Ok(Metadata {
+        mode,
+        uid,
+        gid,
+        xattrs,
+        created,
+        accessed,
+        modified,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::cmp::assert_approx_eq;
+    use crate::cmp::Fields;
+    use crate::tests::demo_fs;
+
+    #[test]
+    fn round_trip() {
+        let mut fs = demo_fs();
+        fs.link("testdata/lorem.txt", "testdata/hardlink.txt")
+            .expect("failed to link");
+        fs.insert(
+            "testdata/fifo",
+            Special::new(SFlag::S_IFIFO, 0, Metadata::default()),
+        );
+        let mut sparse = File::builder().contents("Lorem ipsum").build();
+        sparse.truncate(10000);
+        fs.insert("testdata/sparse", sparse);
+        fs.get_mut("testdata/lorem.txt")
+            .unwrap()
+            .set_xattr("user.lorem", "ipsum");
+        fs.set_times(
+            "testdata",
+            SystemTime::UNIX_EPOCH + Duration::new(1, 2),
+            SystemTime::UNIX_EPOCH + Duration::new(3, 4),
+            SystemTime::UNIX_EPOCH + Duration::new(5, 6),
+        )
+        .unwrap();
+
+        // read in small chunks to exercise records split across reads
+        let reader = std::io::BufReader::with_capacity(7, fs.reader());
+        let loaded = Filesystem::from_reader(reader).expect("failed to load");
+        // holes are written out as zeroes
+        assert_approx_eq!(loaded, fs, Fields::all() - Fields::EXTENTS);
+        assert_eq!(loaded.nlink("testdata/hardlink.txt").unwrap(), 2);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut buf = Vec::new();
+        demo_fs().reader().read_to_end(&mut buf).unwrap();
+        assert_eq!(
+            Filesystem::from_reader(&buf[..buf.len() - 3])
+                .expect_err("truncated stream")
+                .kind(),
+            ErrorKind::UnexpectedEof,
+        );
+        // a corrupt length fails at the end of the data instead of
+        // allocating it all up front
+        let mut corrupt = MAGIC.to_vec();
+        corrupt.extend(u32::MAX.to_le_bytes());
+        corrupt.extend(b"lorem");
+        assert_eq!(
+            Filesystem::from_reader(corrupt.as_slice())
+                .expect_err("corrupt length")
+                .kind(),
+            ErrorKind::UnexpectedEof,
+        );
+        assert_eq!(
+            Filesystem::from_reader(&b"not an archive"[..])
+                .expect_err("bad magic")
+                .kind(),
+            ErrorKind::InvalidData,
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Display;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::BytesPath;
+use crate::Entry;
+use crate::Filesystem;
+
+/// A problem with the structure of a [Filesystem], see
+/// [Filesystem::validate].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The parent directory of this path does not exist
+    MissingParent(BytesPath),
+    /// The parent of this path exists, but is not a directory
+    ParentNotDirectory(BytesPath),
+    /// This symlink has an empty target or one containing a NUL byte
+    InvalidSymlinkTarget(BytesPath),
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingParent(p) => write!(f, "parent of '{}' does not exist", p.display()),
+            Self::ParentNotDirectory(p) => {
+                write!(f, "parent of '{}' is not a directory", p.display())
+            }
+            Self::InvalidSymlinkTarget(p) => {
+                write!(f, "symlink '{}' has an invalid target", p.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Filesystem {
+    /// Check that the filesystem is a well-formed tree: every entry's parent
+    /// must exist and be a directory, and symlink targets must be something
+    /// that could actually be written to disk. The root directory itself is
+    /// allowed to be missing, since many archive formats don't include it.
+    /// All problems are returned, in path order.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        for (path, key) in &self.paths {
+            if let Some(parent) = path.parent() {
+                match self.get(parent) {
+                    Ok(Entry::Directory(_)) => {}
+                    Ok(_) => errors.push(ValidationError::ParentNotDirectory(path.clone())),
+                    Err(_) if parent.as_os_str().is_empty() => {}
+                    Err(_) => errors.push(ValidationError::MissingParent(path.clone())),
+                }
+            }
+            if let Entry::Symlink(s) = &self.inodes[*key] {
+                let target = s.target().as_os_str().as_bytes();
+                if target.is_empty() || target.contains(&0) {
+                    errors.push(ValidationError::InvalidSymlinkTarget(path.clone()));
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::entry::Symlink;
+    use crate::tests::demo_fs;
+    use crate::File;
+
+    #[test]
+    fn valid() {
+        assert_eq!(demo_fs().validate(), Ok(()));
+        // no root is fine
+        let mut fs = demo_fs();
+        fs.unlink("").expect("failed to unlink root");
+        assert_eq!(fs.validate(), Ok(()));
+    }
+
+    #[test]
+    fn invalid() {
+        let mut fs = demo_fs();
+        fs.insert("missing/lorem.txt", File::new_empty());
+        fs.insert("testdata/lorem.txt/ipsum.txt", File::new_empty());
+        fs.insert("testdata/empty", Symlink::new("", None));
+        fs.insert("testdata/nul", Symlink::new(&b"lorem\0ipsum"[..], None));
+        assert_eq!(
+            fs.validate(),
+            Err(vec![
+                ValidationError::MissingParent("missing/lorem.txt".into()),
+                ValidationError::InvalidSymlinkTarget("testdata/empty".into()),
+                ValidationError::ParentNotDirectory("testdata/lorem.txt/ipsum.txt".into()),
+                ValidationError::InvalidSymlinkTarget("testdata/nul".into()),
+            ]),
+        );
+    }
+}
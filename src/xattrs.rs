@@ -0,0 +1,167 @@
+use std::path::PathBuf;
+
+use bytes::Bytes;
+
+/// The namespace of an xattr, which is the part of its name before the first
+/// '.' (like `user` in `user.foo`). Linux rejects names outside of these.
+#[remain::sorted]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Namespace {
+    Security,
+    System,
+    Trusted,
+    User,
+}
+
+impl Namespace {
+    /// Split a full xattr name into its namespace and the rest of the name,
+    /// or `None` if the prefix is not a known namespace.
+    pub fn parse(name: &[u8]) -> Option<(Self, &[u8])> {
+        let dot = name.iter().position(|b| *b == b'.')?;
+        let namespace = match &name[..dot] {
+            b"security" => Self::Security,
+            b"system" => Self::System,
+            b"trusted" => Self::Trusted,
+            b"user" => Self::User,
+            _ => return None,
+        };
+        Some((namespace, &name[dot + 1..]))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Security => "security",
+            Self::System => "system",
+            Self::Trusted => "trusted",
+            Self::User => "user",
+        }
+    }
+}
+
+impl std::fmt::Display for Namespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<Namespace> for Bytes {
+    fn from(namespace: Namespace) -> Self {
+        Bytes::from_static(namespace.as_str().as_bytes())
+    }
+}
+
+/// Decides which xattrs are kept when exporting a [crate::Filesystem] with
+/// [crate::Filesystem::write_to_dir_with] or [crate::Filesystem::to_tar_with].
+/// Some namespaces (like `security.*` or `trusted.*`) need privileges to be
+/// set, so it is often useful to drop them. The default keeps everything.
+pub struct XattrFilter(Box<FilterFn>);
+
+type FilterFn = dyn Fn(&[u8], &Bytes) -> Option<Bytes> + Send + Sync;
+
+impl XattrFilter {
+    /// Filter with an arbitrary function of the xattr name and value, which
+    /// returns the value to export, or `None` to drop the xattr entirely.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&[u8], &Bytes) -> Option<Bytes> + Send + Sync + 'static,
+    {
+        Self(Box::new(f))
+    }
+
+    /// Only keep xattrs in these namespaces (the part of the name before the
+    /// first '.', like `user`).
+    pub fn namespaces<I, N>(allowed: I) -> Self
+    where
+        I: IntoIterator<Item = N>,
+        N: Into<Bytes>,
+    {
+        let allowed: Vec<Bytes> = allowed.into_iter().map(Into::into).collect();
+        Self::new(move |name, value| {
+            let namespace = name.split(|b| *b == b'.').next().unwrap_or_default();
+            allowed
+                .iter()
+                .any(|a| a == namespace)
+                .then(|| value.clone())
+        })
+    }
+
+    pub(crate) fn apply(&self, name: &[u8], value: &Bytes) -> Option<Bytes> {
+        (self.0)(name, value)
+    }
+}
+
+impl Default for XattrFilter {
+    fn default() -> Self {
+        Self::new(|_, value| Some(value.clone()))
+    }
+}
+
+impl std::fmt::Debug for XattrFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("XattrFilter").finish_non_exhaustive()
+    }
+}
+
+/// An xattr that could not be set by [crate::Filesystem::write_to_dir]. These
+/// do not stop the rest of the export.
+#[derive(Debug)]
+pub struct XattrError {
+    pub path: PathBuf,
+    pub name: Bytes,
+    pub error: std::io::Error,
+}
+
+impl std::fmt::Display for XattrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to set xattr '{}' on '{}': {}",
+            String::from_utf8_lossy(&self.name),
+            self.path.display(),
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for XattrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_namespace() {
+        assert_eq!(
+            Namespace::parse(b"user.foo"),
+            Some((Namespace::User, &b"foo"[..]))
+        );
+        assert_eq!(
+            Namespace::parse(b"security.selinux"),
+            Some((Namespace::Security, &b"selinux"[..]))
+        );
+        assert_eq!(
+            Namespace::parse(b"trusted.overlay.opaque"),
+            Some((Namespace::Trusted, &b"overlay.opaque"[..]))
+        );
+        assert_eq!(Namespace::parse(b"username"), None);
+        assert_eq!(Namespace::parse(b"lorem.ipsum"), None);
+    }
+
+    #[test]
+    fn namespaces() {
+        let filter = XattrFilter::namespaces([Namespace::User, Namespace::Trusted]);
+        let value = Bytes::from("lorem");
+        assert_eq!(filter.apply(b"user.demo", &value), Some(value.clone()));
+        assert_eq!(filter.apply(b"trusted.demo", &value), Some(value.clone()));
+        assert_eq!(filter.apply(b"security.selinux", &value), None);
+        assert_eq!(filter.apply(b"username", &value), None);
+        assert_eq!(
+            XattrFilter::default().apply(b"security.selinux", &value),
+            Some(value)
+        );
+    }
+}